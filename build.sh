@@ -5,9 +5,18 @@ set -eu
 TARGET=wasm32-unknown-unknown
 BINARY=target/$TARGET/release/invade_rs.wasm
 
+# every current browser has simd128, blit.rs falls back to scalar code without it
+export RUSTFLAGS="${RUSTFLAGS:-} -C target-feature=+simd128"
+
 cd ./invade-rs
 cargo build --target $TARGET --release
-wasm-strip $BINARY
-wasm-opt -o ../docs/invade_rs.wasm -Oz $BINARY
+# wabt and binaryen only make the binary smaller
+if command -v wasm-strip >/dev/null && command -v wasm-opt >/dev/null; then
+    wasm-strip $BINARY
+    wasm-opt --enable-simd -o ../docs/invade_rs.wasm -Oz $BINARY
+else
+    echo "wasm-strip or wasm-opt not found, docs/invade_rs.wasm is left unoptimised" >&2
+    cp $BINARY ../docs/invade_rs.wasm
+fi
 cd ..
 ls -lh docs/invade_rs.wasm
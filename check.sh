@@ -0,0 +1,22 @@
+#!/bin/sh
+
+set -eu
+
+TARGET=wasm32-unknown-unknown
+
+cd ./invade-rs
+cargo test
+# the simd128 lanes in blit.rs only compile for wasm, so build with and without
+# them, and fail if either build warns about blit.rs
+for FLAGS in "-C target-feature=+simd128" ""; do
+    echo "building $TARGET with RUSTFLAGS=\"$FLAGS\""
+    OUTPUT=$(RUSTFLAGS="${RUSTFLAGS:-} $FLAGS" cargo build --target $TARGET --release --color never 2>&1) || {
+        echo "$OUTPUT" >&2
+        exit 1
+    }
+    echo "$OUTPUT" >&2
+    if echo "$OUTPUT" | grep -q "src/blit.rs"; then
+        echo "blit.rs has warnings with RUSTFLAGS=\"$FLAGS\"" >&2
+        exit 1
+    fi
+done
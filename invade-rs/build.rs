@@ -0,0 +1,183 @@
+// Turns the sprites in assets/ into Rust constants in $OUT_DIR/assets.rs.
+//
+// Text art (*.txt) holds any number of sprites:
+//
+//     # comment
+//     sprite NAME
+//     ..OO..
+//     .O11O.
+//
+// Lines starting with '#' are comments, also inside a sprite. '.' and ' ' are
+// transparent, 'O' is palette index 1 and '1'-'9', 'A'-'F' are palette indices
+// 1-15. A sprite ends at a line that is empty or only spaces, or the next
+// `sprite`, so fully transparent rows are written with '.'. Sprites only using
+// index 1 that fit in a u16 row become `Bitmap2D`s. Anything else becomes an
+// `IndexedBitmap` with 2 bits per pixel, or 4 if it uses indices above 3.
+//
+// PBM images (*.pbm, plain P1 or raw P4) become `Bitmap2D`s named after the
+// file, e.g. infinity.pbm is INFINITY_BITMAP.
+//
+// Songs (*.trk) aren't turned into anything, the game parses them itself.
+// They're parsed here too so a broken song fails the build, not the game.
+
+use std::env;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+#[path = "build/text_art.rs"]
+mod text_art;
+// the game's own song parser, the sequencer and the synth go unused here
+#[allow(dead_code)]
+#[path = "src/synth.rs"]
+mod synth;
+#[allow(dead_code)]
+#[path = "src/music.rs"]
+mod music;
+
+use text_art::Sprite;
+
+const ASSETS_DIR: &str = "assets";
+const MAX_MONO_WIDTH: usize = 16;
+const MAX_2_BIT_INDEX: u8 = 3;
+const MAX_INDEX: u8 = 15;
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", ASSETS_DIR);
+    let mut paths: Vec<PathBuf> = fs::read_dir(ASSETS_DIR)
+        .unwrap_or_else(|err| panic!("can't read {}: {}", ASSETS_DIR, err))
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .collect();
+    // generated code shouldn't depend on the directory order
+    paths.sort();
+
+    let mut sprites = Vec::new();
+    for path in paths.iter() {
+        println!("cargo:rerun-if-changed={}", path.display());
+        let result = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("txt") => fs::read_to_string(path)
+                .map_err(|err| err.to_string())
+                .and_then(|text| text_art::parse_text_art(&text)),
+            Some("pbm") => parse_pbm(path),
+            Some("trk") => {
+                if let Err(err) = check_song(path) {
+                    panic!("{}: {}", path.display(), err);
+                }
+                continue;
+            },
+            _ => continue,
+        };
+        match result {
+            Ok(mut parsed) => sprites.append(&mut parsed),
+            Err(err) => panic!("{}: {}", path.display(), err),
+        }
+    }
+
+    let mut out = String::from("// generated by build.rs from assets/, don't edit\n");
+    for sprite in sprites.iter() {
+        if sprites.iter().filter(|other| other.name == sprite.name).count() > 1 {
+            panic!("sprite {} is defined more than once", sprite.name);
+        }
+        if let Err(err) = write_sprite(&mut out, sprite) {
+            panic!("sprite {}: {}", sprite.name, err);
+        }
+    }
+    let out_path = Path::new(&env::var("OUT_DIR").expect("OUT_DIR is set by cargo")).join("assets.rs");
+    fs::write(&out_path, out).unwrap_or_else(|err| panic!("can't write {}: {}", out_path.display(), err));
+}
+
+fn check_song(path: &Path) -> Result<(), String> {
+    let text = fs::read_to_string(path).map_err(|err| err.to_string())?;
+    match music::Songbook::parse(&text) {
+        Ok(_) => Ok(()),
+        Err(err) => Err(format!("line {}: {}", err.line, err.reason)),
+    }
+}
+
+fn parse_pbm(path: &Path) -> Result<Vec<Sprite>, String> {
+    let data = fs::read(path).map_err(|err| err.to_string())?;
+    let stem = path.file_stem().and_then(|stem| stem.to_str()).ok_or("file name isn't utf-8")?;
+    let name = format!("{}_BITMAP", stem.to_ascii_uppercase().replace(['-', ' '], "_"));
+
+    // the header is whitespace separated, comments run to the end of the line
+    let mut pos = 0;
+    let mut next_token = |data: &[u8]| -> Result<(String, usize), String> {
+        loop {
+            while data.get(pos).is_some_and(|c| c.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            if data.get(pos) == Some(&b'#') {
+                while data.get(pos).is_some_and(|c| *c != b'\n') {
+                    pos += 1;
+                }
+                continue;
+            }
+            let start = pos;
+            while data.get(pos).is_some_and(|c| !c.is_ascii_whitespace()) {
+                pos += 1;
+            }
+            if start == pos {
+                return Err("unexpected end of file".to_string());
+            }
+            // a single whitespace byte separates the header from raw pixels
+            return Ok((String::from_utf8_lossy(&data[start..pos]).into_owned(), pos + 1));
+        }
+    };
+    let (magic, _) = next_token(&data)?;
+    let (width, _) = next_token(&data)?;
+    let (height, header_end) = next_token(&data)?;
+    let width: usize = width.parse().map_err(|_| format!("bad width {:?}", width))?;
+    let height: usize = height.parse().map_err(|_| format!("bad height {:?}", height))?;
+
+    let pixels = match magic.as_str() {
+        "P1" => {
+            let pixels: Vec<u8> = data.get(header_end..).unwrap_or(&[]).iter()
+                .filter(|c| **c == b'0' || **c == b'1')
+                .map(|c| c - b'0')
+                .collect();
+            if pixels.len() != width * height {
+                return Err(format!("expected {} pixels, got {}", width * height, pixels.len()));
+            }
+            pixels
+        },
+        "P4" => {
+            let row_bytes = width.div_ceil(8);
+            let raw = data.get(header_end..header_end + row_bytes * height).ok_or("truncated pixel data")?;
+            raw.chunks(row_bytes)
+                .flat_map(|row| (0..width).map(move |x| (row[x / 8] >> (7 - x % 8)) & 1))
+                .collect()
+        },
+        _ => return Err(format!("only P1 and P4 PBM images are supported, got {:?}", magic)),
+    };
+    Ok(vec![Sprite { name, width, height, pixels }])
+}
+
+fn write_sprite(out: &mut String, sprite: &Sprite) -> Result<(), String> {
+    let max_index = sprite.pixels.iter().copied().max().unwrap_or(0);
+    if max_index > MAX_INDEX {
+        return Err(format!("palette index {} is over {}", max_index, MAX_INDEX));
+    }
+    if max_index <= 1 && sprite.width <= MAX_MONO_WIDTH {
+        let _ = writeln!(out, "pub const {}: crate::sprite::Bitmap2D = crate::sprite::Bitmap2D {{ width: {}, height: {}, bitmap: &[",
+                         sprite.name, sprite.width, sprite.height);
+        for row in sprite.pixels.chunks(sprite.width) {
+            let bits = row.iter().enumerate().fold(0u16, |bits, (x, idx)| bits | ((*idx as u16) << (15 - x)));
+            let _ = writeln!(out, "    {:#018b},", bits);
+        }
+    } else {
+        let bits_per_pixel = if max_index <= MAX_2_BIT_INDEX { 2 } else { 4 };
+        let pixels_per_byte = 8 / bits_per_pixel;
+        let _ = writeln!(out, "pub const {}: crate::sprite::IndexedBitmap = crate::sprite::IndexedBitmap {{ width: {}, height: {}, bits_per_pixel: {}, pixels: &[",
+                         sprite.name, sprite.width, sprite.height, bits_per_pixel);
+        for row in sprite.pixels.chunks(sprite.width) {
+            // every row starts on a new byte
+            let bytes: Vec<String> = row.chunks(pixels_per_byte)
+                .map(|pixels| pixels.iter().enumerate().fold(0u8, |byte, (x, idx)| byte | idx << (8 - bits_per_pixel * (x + 1))))
+                .map(|byte| format!("{:#04x}", byte))
+                .collect();
+            let _ = writeln!(out, "    {},", bytes.join(", "));
+        }
+    }
+    let _ = writeln!(out, "] }};");
+    Ok(())
+}
@@ -0,0 +1,98 @@
+// Parser for the text art sprite sheets, see build.rs for the format. Shared
+// with the library's tests, which can't reach into the build script.
+
+pub struct Sprite {
+    pub name: String,
+    pub width: usize,
+    pub height: usize,
+    // palette index per pixel, row major
+    pub pixels: Vec<u8>,
+}
+
+pub fn parse_text_art(text: &str) -> Result<Vec<Sprite>, String> {
+    let mut sprites: Vec<Sprite> = Vec::new();
+    let mut in_sprite = false;
+    for (line_idx, line) in text.lines().enumerate() {
+        let line_no = line_idx + 1;
+        if line.starts_with('#') {
+            continue;
+        }
+        if line.trim().is_empty() {
+            in_sprite = false;
+            continue;
+        }
+        if let Some(name) = line.strip_prefix("sprite ") {
+            let name = name.trim();
+            if name.is_empty() || !name.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '_') {
+                return Err(format!("line {}: sprite names are UPPER_SNAKE_CASE, got {:?}", line_no, name));
+            }
+            sprites.push(Sprite { name: name.to_string(), width: 0, height: 0, pixels: Vec::new() });
+            in_sprite = true;
+            continue;
+        }
+        let sprite = match sprites.last_mut() {
+            Some(sprite) if in_sprite => sprite,
+            _ => return Err(format!("line {}: pixels outside of a sprite", line_no)),
+        };
+        // not trimmed, trailing spaces are transparent pixels
+        let row = line.chars()
+            .map(|c| pixel_index(c).ok_or_else(|| format!("line {}: unknown pixel {:?}", line_no, c)))
+            .collect::<Result<Vec<u8>, String>>()?;
+        if sprite.height == 0 {
+            sprite.width = row.len();
+        } else if row.len() != sprite.width {
+            return Err(format!("line {}: row is {} pixels wide, the sprite's rows are {}", line_no, row.len(), sprite.width));
+        }
+        sprite.pixels.extend(row);
+        sprite.height += 1;
+    }
+    match sprites.iter().find(|sprite| sprite.height == 0) {
+        Some(sprite) => Err(format!("sprite {} has no pixels", sprite.name)),
+        None => Ok(sprites),
+    }
+}
+
+fn pixel_index(c: char) -> Option<u8> {
+    match c {
+        '.' | ' ' => Some(0),
+        'O' => Some(1),
+        _ => c.to_digit(16).filter(|idx| *idx != 0).map(|idx| idx as u8),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_comment_rows() {
+        let sprites = parse_text_art("# a comment\nsprite X\n# no pixels in here\nO..O\n.OO.\nO..O\n").unwrap();
+        assert_eq!(sprites.len(), 1);
+        assert_eq!((sprites[0].name.as_str(), sprites[0].width, sprites[0].height), ("X", 4, 3));
+        assert_eq!(sprites[0].pixels, [1, 0, 0, 1, 0, 1, 1, 0, 1, 0, 0, 1]);
+        // '#' is no pixel, rows starting with one are comments even inside a sprite
+        assert!(parse_text_art("sprite X\n.#.\n").is_err());
+    }
+
+    #[test]
+    fn test_trailing_transparency() {
+        let sprites = parse_text_art("sprite A\nO  \n O \n  O\r\n\nsprite B\nOO\n").unwrap();
+        assert_eq!((sprites[0].width, sprites[0].height), (3, 3));
+        assert_eq!(sprites[0].pixels, [1, 0, 0, 0, 1, 0, 0, 0, 1]);
+        // a row of only spaces ends the sprite like an empty line
+        assert_eq!((sprites[1].name.as_str(), sprites[1].height), ("B", 1));
+        assert_eq!(parse_text_art("sprite A\nO.\n  \n").unwrap()[0].height, 1);
+    }
+
+    #[test]
+    fn test_errors() {
+        let err = |text: &str| parse_text_art(text).err().unwrap_or_default();
+        assert_eq!(err("sprite X\nOO.\nO.\n"), "line 3: row is 2 pixels wide, the sprite's rows are 3");
+        assert_eq!(err("sprite X\nO.\nO. \n"), "line 3: row is 3 pixels wide, the sprite's rows are 2");
+        assert_eq!(err("sprite X\nOx\n"), "line 2: unknown pixel 'x'");
+        assert_eq!(err("OO\n"), "line 1: pixels outside of a sprite");
+        assert_eq!(err("sprite X\n\nOO\n"), "line 3: pixels outside of a sprite");
+        assert_eq!(err("sprite x\nOO\n"), "line 1: sprite names are UPPER_SNAKE_CASE, got \"x\"");
+        assert_eq!(err("sprite X\n"), "sprite X has no pixels");
+    }
+}
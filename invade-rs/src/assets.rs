@@ -0,0 +1,3 @@
+// Sprites generated by build.rs from the files in assets/.
+
+include!(concat!(env!("OUT_DIR"), "/assets.rs"));
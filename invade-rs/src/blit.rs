@@ -0,0 +1,357 @@
+// Pixel loops of the renderer. Solid fills, row copies and expanding 1-bit rows
+// have a wasm simd128 path that works on four pixels at a time, other builds
+// use the plain scalar versions. The lane code is written against a tiny
+// 4 x u32 type, which is emulated with an array in native tests so both paths
+// can be checked against each other.
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+use lanes as active;
+#[cfg(not(all(target_arch = "wasm32", target_feature = "simd128")))]
+use scalar as active;
+
+pub fn fill(dst: &mut [u32], color: u32) {
+    active::fill(dst, color)
+}
+
+// Pixel `k` of `dst` gets `color` if bit `n_bits - 1 - k / scale` of `bits` is
+// set, i.e. the bitmap is read MSB first and every bit covers `scale` pixels.
+// Pixels of unset bits are left as they are.
+pub fn expand_bits(dst: &mut [u32], bits: u32, n_bits: usize, scale: usize, color: u32) {
+    active::expand_bits(dst, bits, n_bits, scale, color)
+}
+
+// copies `len` pixels at `start` into the `count` rows below it
+pub fn replicate_row(buf: &mut [u32], start: usize, len: usize, stride: usize, count: usize) {
+    active::replicate_row(buf, start, len, stride, count)
+}
+
+fn bit_is_set(bits: u32, n_bits: usize, scale: usize, pixel: usize) -> bool {
+    match n_bits.checked_sub(1 + pixel / scale) {
+        Some(bit_idx) if bit_idx < 32 => bits & (1 << bit_idx) != 0,
+        _ => false,
+    }
+}
+
+#[cfg(any(test, not(all(target_arch = "wasm32", target_feature = "simd128"))))]
+mod scalar {
+    pub fn fill(dst: &mut [u32], color: u32) {
+        dst.fill(color)
+    }
+
+    pub fn replicate_row(buf: &mut [u32], start: usize, len: usize, stride: usize, count: usize) {
+        for row in 1..=count {
+            let dst_start = start + row * stride;
+            if dst_start + len <= buf.len() && start + len <= buf.len() {
+                buf.copy_within(start..start + len, dst_start);
+            }
+        }
+    }
+
+    pub fn expand_bits(dst: &mut [u32], bits: u32, n_bits: usize, scale: usize, color: u32) {
+        if scale == 0 {
+            return;
+        }
+        for (pixel, x) in dst.iter_mut().enumerate().take(n_bits * scale) {
+            if super::bit_is_set(bits, n_bits, scale, pixel) {
+                *x = color;
+            }
+        }
+    }
+}
+
+#[cfg(all(target_arch = "wasm32", target_feature = "simd128"))]
+mod x4 {
+    use core::arch::wasm32::*;
+
+    #[derive(Clone, Copy)]
+    pub struct X4(v128);
+
+    impl X4 {
+        pub fn splat(val: u32) -> X4 {
+            X4(u32x4_splat(val))
+        }
+
+        pub fn from_array(vals: [u32; 4]) -> X4 {
+            X4(u32x4(vals[0], vals[1], vals[2], vals[3]))
+        }
+
+        pub fn load(src: &[u32; 4]) -> X4 {
+            // wasm loads don't need any alignment
+            X4(unsafe { v128_load(src.as_ptr() as *const v128) })
+        }
+
+        pub fn store(self, dst: &mut [u32; 4]) {
+            unsafe { v128_store(dst.as_mut_ptr() as *mut v128, self.0) }
+        }
+
+        pub fn and(self, other: X4) -> X4 {
+            X4(v128_and(self.0, other.0))
+        }
+
+        pub fn add(self, other: X4) -> X4 {
+            X4(u32x4_add(self.0, other.0))
+        }
+
+        pub fn sub(self, other: X4) -> X4 {
+            X4(u32x4_sub(self.0, other.0))
+        }
+
+        // every lane shifted by the same amount
+        pub fn shr(self, amount: u32) -> X4 {
+            X4(u32x4_shr(self.0, amount))
+        }
+
+        // all bits set in lanes that are at least `other`, unsigned
+        pub fn ge(self, other: X4) -> X4 {
+            X4(u32x4_ge(self.0, other.0))
+        }
+
+        pub fn non_zero(self) -> X4 {
+            X4(u32x4_ne(self.0, u32x4_splat(0)))
+        }
+
+        pub fn any(self) -> bool {
+            v128_any_true(self.0)
+        }
+
+        // bits of `a` where `mask` is set, bits of `b` elsewhere
+        pub fn select(mask: X4, a: X4, b: X4) -> X4 {
+            X4(v128_bitselect(a.0, b.0, mask.0))
+        }
+    }
+}
+
+#[cfg(all(test, not(all(target_arch = "wasm32", target_feature = "simd128"))))]
+mod x4 {
+    #[derive(Clone, Copy)]
+    pub struct X4([u32; 4]);
+
+    impl X4 {
+        pub fn splat(val: u32) -> X4 {
+            X4([val; 4])
+        }
+
+        pub fn from_array(vals: [u32; 4]) -> X4 {
+            X4(vals)
+        }
+
+        pub fn load(src: &[u32; 4]) -> X4 {
+            X4(*src)
+        }
+
+        pub fn store(self, dst: &mut [u32; 4]) {
+            *dst = self.0;
+        }
+
+        fn map(self, other: X4, op: impl Fn(u32, u32) -> u32) -> X4 {
+            X4(std::array::from_fn(|idx| op(self.0[idx], other.0[idx])))
+        }
+
+        pub fn and(self, other: X4) -> X4 {
+            self.map(other, |a, b| a & b)
+        }
+
+        pub fn add(self, other: X4) -> X4 {
+            self.map(other, u32::wrapping_add)
+        }
+
+        pub fn sub(self, other: X4) -> X4 {
+            self.map(other, u32::wrapping_sub)
+        }
+
+        pub fn shr(self, amount: u32) -> X4 {
+            X4(self.0.map(|lane| lane.checked_shr(amount).unwrap_or(0)))
+        }
+
+        pub fn ge(self, other: X4) -> X4 {
+            self.map(other, |a, b| if a >= b { u32::MAX } else { 0 })
+        }
+
+        pub fn non_zero(self) -> X4 {
+            X4(self.0.map(|lane| if lane != 0 { u32::MAX } else { 0 }))
+        }
+
+        pub fn any(self) -> bool {
+            self.0 != [0; 4]
+        }
+
+        pub fn select(mask: X4, a: X4, b: X4) -> X4 {
+            X4(std::array::from_fn(|idx| (a.0[idx] & mask.0[idx]) | (b.0[idx] & !mask.0[idx])))
+        }
+    }
+}
+
+#[cfg(any(test, all(target_arch = "wasm32", target_feature = "simd128")))]
+mod lanes {
+    use super::x4::X4;
+
+    pub fn fill(dst: &mut [u32], color: u32) {
+        let color_x4 = X4::splat(color);
+        let mut chunks = dst.chunks_exact_mut(4);
+        for chunk in chunks.by_ref() {
+            if let Ok(chunk) = <&mut [u32; 4]>::try_from(chunk) {
+                color_x4.store(chunk);
+            }
+        }
+        chunks.into_remainder().fill(color);
+    }
+
+    pub fn replicate_row(buf: &mut [u32], start: usize, len: usize, stride: usize, count: usize) {
+        for row in 1..=count {
+            let dst_start = start + row * stride;
+            if dst_start + len > buf.len() || start + len > buf.len() {
+                continue;
+            }
+            if row * stride < len {
+                // rows overlap, only copy_within gets that right
+                buf.copy_within(start..start + len, dst_start);
+                continue;
+            }
+            let (head, tail) = buf.split_at_mut(dst_start);
+            copy(&head[start..start + len], &mut tail[..len]);
+        }
+    }
+
+    fn copy(src: &[u32], dst: &mut [u32]) {
+        let mut src_chunks = src.chunks_exact(4);
+        let mut dst_chunks = dst.chunks_exact_mut(4);
+        for (src_chunk, dst_chunk) in src_chunks.by_ref().zip(dst_chunks.by_ref()) {
+            if let (Ok(src_chunk), Ok(dst_chunk)) = (<&[u32; 4]>::try_from(src_chunk), <&mut [u32; 4]>::try_from(dst_chunk)) {
+                X4::load(src_chunk).store(dst_chunk);
+            }
+        }
+        dst_chunks.into_remainder().copy_from_slice(src_chunks.remainder());
+    }
+
+    // Every lane keeps the bit it reads as a one-bit mask, and how far it is
+    // into that bit's `scale` pixels. Moving on by four pixels shifts the mask
+    // of a lane right once for every `scale` pixels it passed.
+    pub fn expand_bits(dst: &mut [u32], bits: u32, n_bits: usize, scale: usize, color: u32) {
+        if scale == 0 {
+            return;
+        }
+        // bits above the 32nd are never set
+        let skip = n_bits.saturating_sub(32) * scale;
+        let n_bits = n_bits.min(32);
+        let len = dst.len().min(skip + n_bits * scale);
+        let dst = match dst.get_mut(skip.min(len)..len) {
+            Some(dst) => dst,
+            None => return,
+        };
+
+        let lane_bit = |lane: usize| match n_bits.checked_sub(1 + lane / scale) {
+            Some(bit_idx) => 1u32 << bit_idx,
+            None => 0,
+        };
+        let mut bit_x4 = X4::from_array([lane_bit(0), lane_bit(1), lane_bit(2), lane_bit(3)]);
+        let mut offset_x4 = X4::from_array([0, 1, 2, 3].map(|lane| (lane % scale) as u32));
+        let (bits_x4, color_x4) = (X4::splat(bits), X4::splat(color));
+        let (scale_x4, four_x4) = (X4::splat(scale as u32), X4::splat(4));
+        // the same bit is four pixels further on for every lane
+        let uniform_shift = if 4 % scale == 0 { Some((4 / scale) as u32) } else { None };
+
+        let mut chunks = dst.chunks_exact_mut(4);
+        for chunk in chunks.by_ref() {
+            if let Ok(chunk) = <&mut [u32; 4]>::try_from(chunk) {
+                let mask = bits_x4.and(bit_x4).non_zero();
+                if mask.any() {
+                    X4::select(mask, color_x4, X4::load(chunk)).store(chunk);
+                }
+            }
+            match uniform_shift {
+                Some(shift) => bit_x4 = bit_x4.shr(shift),
+                None => {
+                    // scale isn't a divisor of 4, so scale > 2 and no lane passes more than two bits
+                    offset_x4 = offset_x4.add(four_x4);
+                    for _ in 0..2 {
+                        let passed = offset_x4.ge(scale_x4);
+                        offset_x4 = X4::select(passed, offset_x4.sub(scale_x4), offset_x4);
+                        bit_x4 = X4::select(passed, bit_x4.shr(1), bit_x4);
+                    }
+                },
+            }
+        }
+        let rest = chunks.into_remainder();
+        let rest_start = len - skip - rest.len();
+        for (lane, x) in rest.iter_mut().enumerate() {
+            if super::bit_is_set(bits, n_bits, scale, rest_start + lane) {
+                *x = color;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(len: usize) -> Vec<u32> {
+        (0..len as u32).map(|x| x.wrapping_mul(2654435761)).collect()
+    }
+
+    #[test]
+    fn test_expand_bits_matches_scalar() {
+        for bits in [0, 1, 0b1011_0000_0000_0001, 0x8000, 0xFFFF, 0b0110_0011_0000_0000] {
+            for scale in 0..9 {
+                for len in [0, 3, 16 * scale, 16 * scale + 5] {
+                    let mut expected = pattern(len);
+                    let mut actual = expected.clone();
+                    scalar::expand_bits(&mut expected, bits, 16, scale, 7);
+                    lanes::expand_bits(&mut actual, bits, 16, scale, 7);
+                    assert_eq!(expected, actual, "bits {bits:#b} scale {scale} len {len}");
+                }
+            }
+        }
+        // every bit of a u32, and bits past it which are never set
+        for (bits, n_bits) in [(0x8000_0001, 32), (0xA5A5_A5A5, 33), (0xFFFF_FFFF, 35), (0b101, 3)] {
+            for scale in 1..12 {
+                let mut expected = pattern(n_bits * scale + 7);
+                let mut actual = expected.clone();
+                scalar::expand_bits(&mut expected, bits, n_bits, scale, 7);
+                lanes::expand_bits(&mut actual, bits, n_bits, scale, 7);
+                assert_eq!(expected, actual, "bits {bits:#b} n_bits {n_bits} scale {scale}");
+            }
+        }
+
+        let mut row = [0; 8];
+        expand_bits(&mut row, 0b101, 3, 2, 1);
+        assert_eq!(row, [1, 1, 0, 0, 1, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_fill_matches_scalar() {
+        for len in 0..11 {
+            let mut expected = pattern(len);
+            let mut actual = expected.clone();
+            scalar::fill(&mut expected, 7);
+            lanes::fill(&mut actual, 7);
+            assert_eq!(expected, actual, "len {len}");
+        }
+    }
+
+    #[test]
+    fn test_replicate_row_matches_scalar() {
+        // strides shorter than the row make the copies overlap
+        for (start, len, stride, count) in [(0, 8, 8, 3), (3, 6, 10, 4), (1, 13, 16, 2), (2, 7, 3, 5), (5, 10, 10, 9)] {
+            let mut expected = pattern(96);
+            let mut actual = expected.clone();
+            scalar::replicate_row(&mut expected, start, len, stride, count);
+            lanes::replicate_row(&mut actual, start, len, stride, count);
+            assert_eq!(expected, actual, "start {start} len {len} stride {stride} count {count}");
+        }
+    }
+
+    #[test]
+    fn test_replicate_row() {
+        let mut buf = pattern(64);
+        let row: Vec<u32> = buf[3..9].to_vec();
+        replicate_row(&mut buf, 3, 6, 10, 3);
+        for idx in 1..=3 {
+            assert_eq!(buf[3 + idx * 10..9 + idx * 10], row[..]);
+        }
+        // rows that don't fit are left out
+        let before = buf.clone();
+        replicate_row(&mut buf, 50, 10, 10, 2);
+        assert!(buf == before);
+    }
+}
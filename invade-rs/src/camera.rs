@@ -0,0 +1,123 @@
+// Screen shake and hit-stop. Neither touches the simulation: the shake only
+// moves the playfield when frames are composed, and hit-stop holds the local
+// game loop for a few frames. Both can be turned off for players who'd rather
+// not have the screen jump around.
+
+use crate::rng::Rng;
+use crate::SUBPIXELS;
+
+// amplitudes are in subpixels
+const MAX_SHAKE: i32 = 4 * SUBPIXELS;
+// what's left of the amplitude after a frame, in 1/256
+const SHAKE_DECAY: i32 = 200;
+const MAX_HIT_STOP_FRAMES: u32 = 10;
+
+pub struct Camera {
+    amplitude: i32,
+    hit_stop_frames: u32,
+    rng: Rng,
+    pub shake_enabled: bool,
+    pub hit_stop_enabled: bool,
+}
+
+impl Camera {
+    pub fn new() -> Camera {
+        Camera { amplitude: 0, hit_stop_frames: 0, rng: Rng::new(0), shake_enabled: true, hit_stop_enabled: true }
+    }
+
+    pub fn reset(&mut self) {
+        self.amplitude = 0;
+        self.hit_stop_frames = 0;
+    }
+
+    // in playfield pixels, a weaker shake doesn't cut a stronger one short
+    pub fn shake(&mut self, amplitude: i32) {
+        if self.shake_enabled {
+            self.amplitude = self.amplitude.max((amplitude * SUBPIXELS).min(MAX_SHAKE));
+        }
+    }
+
+    pub fn hit_stop(&mut self, frames: u32) {
+        if self.hit_stop_enabled {
+            self.hit_stop_frames = self.hit_stop_frames.max(frames.min(MAX_HIT_STOP_FRAMES));
+        }
+    }
+
+    // true while the game should stay at the same moment, counts the frame off
+    pub fn hold_frame(&mut self) -> bool {
+        if self.hit_stop_frames == 0 || !self.hit_stop_enabled {
+            self.hit_stop_frames = 0;
+            return false;
+        }
+        self.hit_stop_frames -= 1;
+        true
+    }
+
+    #[cfg(test)]
+    pub fn shaking(&self) -> bool {
+        self.amplitude > 0
+    }
+
+    // offset of the playfield in the next frame, `unit` pixels per playfield pixel
+    pub fn next_offset(&mut self, unit: usize) -> (i32, i32) {
+        if !self.shake_enabled {
+            self.amplitude = 0;
+        }
+        if self.amplitude == 0 {
+            return (0, 0);
+        }
+        let dx = self.rng.below(2 * self.amplitude + 1) - self.amplitude;
+        let dy = self.rng.below(2 * self.amplitude + 1) - self.amplitude;
+        self.amplitude = self.amplitude * SHAKE_DECAY / 256;
+        (dx * unit as i32 / SUBPIXELS, dy * unit as i32 / SUBPIXELS)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_shake_decays() {
+        let mut camera = Camera::new();
+        camera.shake(2);
+        camera.shake(1);
+        let mut frames = 0;
+        loop {
+            let (dx, dy) = camera.next_offset(6);
+            assert!(dx.abs() <= 2 * 6 && dy.abs() <= 2 * 6);
+            if camera.amplitude == 0 {
+                break;
+            }
+            frames += 1;
+        }
+        assert!((5..20).contains(&frames));
+        assert_eq!(camera.next_offset(6), (0, 0));
+
+        camera.shake(100);
+        assert_eq!(camera.amplitude, MAX_SHAKE);
+        // turning it off stops a running shake
+        camera.shake_enabled = false;
+        assert_eq!(camera.next_offset(6), (0, 0));
+        camera.shake(2);
+        assert_eq!(camera.next_offset(6), (0, 0));
+    }
+
+    #[test]
+    fn test_hit_stop() {
+        let mut camera = Camera::new();
+        camera.hit_stop(2);
+        camera.hit_stop(1);
+        assert!(camera.hold_frame());
+        assert!(camera.hold_frame());
+        assert!(!camera.hold_frame());
+
+        camera.hit_stop(100);
+        let held = std::iter::from_fn(|| Some(camera.hold_frame())).take_while(|held| *held).count();
+        assert_eq!(held, MAX_HIT_STOP_FRAMES as usize);
+
+        camera.hit_stop_enabled = false;
+        camera.hit_stop(3);
+        assert!(!camera.hold_frame());
+    }
+}
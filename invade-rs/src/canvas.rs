@@ -0,0 +1,270 @@
+// Drawing on the framebuffer in playfield coordinates. Every playfield pixel
+// covers `unit` x `unit` framebuffer pixels. Everything is clipped to the
+// canvas and the clip rectangle, so shapes can be partly or fully off screen.
+
+use crate::blit;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Rect {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, width: i32, height: i32) -> Rect {
+        Rect { x, y, width, height }
+    }
+
+    fn right(&self) -> i32 {
+        self.x + self.width
+    }
+
+    fn bottom(&self) -> i32 {
+        self.y + self.height
+    }
+
+    fn is_empty(&self) -> bool {
+        self.width <= 0 || self.height <= 0
+    }
+
+    fn intersect(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let width = (self.right().min(other.right()) - x).max(0);
+        let height = (self.bottom().min(other.bottom()) - y).max(0);
+        Rect { x, y, width, height }
+    }
+}
+
+pub struct Canvas<'a> {
+    pixels: &'a mut [u32],
+    width: usize,
+    height: usize,
+    unit: usize,
+    clip: Rect,
+}
+
+impl<'a> Canvas<'a> {
+    // `width` and `height` in playfield pixels, `pixels` holds them `unit` times larger
+    pub fn new(pixels: &'a mut [u32], width: usize, height: usize, unit: usize) -> Canvas<'a> {
+        let clip = Rect::new(0, 0, width as i32, height as i32);
+        Canvas { pixels, width, height, unit: unit.max(1), clip }
+    }
+
+    fn bounds(&self) -> Rect {
+        Rect::new(0, 0, self.width as i32, self.height as i32)
+    }
+
+    pub fn set_clip(&mut self, clip: Rect) {
+        self.clip = clip.intersect(&self.bounds());
+    }
+
+    pub fn reset_clip(&mut self) {
+        self.clip = self.bounds();
+    }
+
+    // fills the clip rectangle
+    pub fn clear(&mut self, color: u32) {
+        if self.clip == self.bounds() {
+            blit::fill(self.pixels, color);
+        } else {
+            self.fill_rect(self.clip, color);
+        }
+    }
+
+    pub fn pixel(&mut self, x: i32, y: i32, color: u32) {
+        self.fill_rect(Rect::new(x, y, 1, 1), color);
+    }
+
+    pub fn fill_rect(&mut self, rect: Rect, color: u32) {
+        let rect = rect.intersect(&self.clip);
+        if rect.is_empty() {
+            return;
+        }
+        let stride = self.width * self.unit;
+        let start = rect.y as usize * self.unit * stride + rect.x as usize * self.unit;
+        let len = rect.width as usize * self.unit;
+        if let Some(row) = self.pixels.get_mut(start..start + len) {
+            blit::fill(row, color);
+        }
+        blit::replicate_row(self.pixels, start, len, stride, rect.height as usize * self.unit - 1);
+    }
+
+    // one pixel wide outline, inside of `rect`
+    pub fn rect(&mut self, rect: Rect, color: u32) {
+        if rect.is_empty() {
+            return;
+        }
+        self.fill_rect(Rect::new(rect.x, rect.y, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.bottom() - 1, rect.width, 1), color);
+        self.fill_rect(Rect::new(rect.x, rect.y + 1, 1, rect.height - 2), color);
+        self.fill_rect(Rect::new(rect.right() - 1, rect.y + 1, 1, rect.height - 2), color);
+    }
+
+    // Bresenham, both ends included
+    pub fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u32) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let (mut x, mut y) = (x0, y0);
+        let mut err = dx + dy;
+        loop {
+            self.pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+            let err2 = 2 * err;
+            if err2 >= dy {
+                err += dy;
+                x += step_x;
+            }
+            if err2 <= dx {
+                err += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    // midpoint circle outline, nothing on screen is round
+    #[cfg(test)]
+    pub fn circle(&mut self, center_x: i32, center_y: i32, radius: i32, color: u32) {
+        if radius < 0 {
+            return;
+        }
+        let (mut x, mut y) = (radius, 0);
+        let mut err = 1 - radius;
+        while x >= y {
+            for (dx, dy) in [(x, y), (y, x), (-y, x), (-x, y), (-x, -y), (-y, -x), (y, -x), (x, -y)] {
+                self.pixel(center_x + dx, center_y + dy, color);
+            }
+            y += 1;
+            if err < 0 {
+                err += 2 * y + 1;
+            } else {
+                x -= 1;
+                err += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    // Draws the first `n_bits` bits of every row, the top bit is the leftmost
+    // pixel. Every bit covers `scale` x `scale` pixels, unset bits are left as they are.
+    pub fn bitmap(&mut self, x: i32, y: i32, rows: &[u16], n_bits: usize, scale: usize, color: u32) {
+        let n_bits = n_bits.min(16);
+        if scale == 0 || n_bits == 0 {
+            return;
+        }
+        let scale_i = scale as i32;
+        let right = (x + n_bits as i32 * scale_i).min(self.clip.right());
+        let stride = self.width * self.unit;
+        for (row_idx, &row) in rows.iter().enumerate() {
+            let row_y = y + row_idx as i32 * scale_i;
+            if x < self.clip.x {
+                // partly left of the clip rectangle, not worth a fast path
+                for bit in 0..n_bits {
+                    if row & (0x8000 >> bit) != 0 {
+                        self.fill_rect(Rect::new(x + bit as i32 * scale_i, row_y, scale_i, scale_i), color);
+                    }
+                }
+                continue;
+            }
+            if x >= right {
+                continue;
+            }
+            let bits = (row >> (16 - n_bits)) as u32;
+            let len = (right - x) as usize * self.unit;
+            for py in row_y.max(self.clip.y)..(row_y + scale_i).min(self.clip.bottom()) {
+                for sub_row in 0..self.unit {
+                    let start = (py as usize * self.unit + sub_row) * stride + x as usize * self.unit;
+                    if let Some(dst) = self.pixels.get_mut(start..start + len) {
+                        blit::expand_bits(dst, bits, n_bits, scale * self.unit, color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // one char per playfield pixel, '#' for `color` and '.' for anything else
+    fn render(canvas: &Canvas, color: u32) -> Vec<String> {
+        let stride = canvas.width * canvas.unit;
+        (0..canvas.height).map(|y| {
+            (0..canvas.width).map(|x| {
+                let pixel = canvas.pixels[y * canvas.unit * stride + x * canvas.unit];
+                if pixel == color { '#' } else { '.' }
+            }).collect()
+        }).collect()
+    }
+
+    #[test]
+    fn test_fill_rect_clips_and_scales() {
+        let mut pixels = vec![0; 6 * 4 * 3 * 3];
+        let mut canvas = Canvas::new(&mut pixels, 6, 4, 3);
+        canvas.fill_rect(Rect::new(-2, 2, 4, 9), 1);
+        canvas.set_clip(Rect::new(3, 0, 10, 2));
+        canvas.rect(Rect::new(2, 0, 4, 3), 1);
+        canvas.reset_clip();
+        canvas.pixel(6, 0, 1);
+        canvas.pixel(5, 3, 1);
+        assert_eq!(render(&canvas, 1), ["...###", ".....#", "##....", "##...#"]);
+        // whole blocks are filled, not only the pixel the test looks at
+        assert_eq!(pixels.iter().filter(|pixel| **pixel == 1).count(), 9 * 9);
+    }
+
+    #[test]
+    fn test_line_and_circle() {
+        let mut pixels = vec![0; 7 * 7];
+        let mut canvas = Canvas::new(&mut pixels, 7, 7, 1);
+        canvas.line(0, 0, 6, 3, 1);
+        assert_eq!(render(&canvas, 1), [
+            "#......",
+            ".##....",
+            "...##..",
+            ".....##",
+            ".......",
+            ".......",
+            ".......",
+        ]);
+
+        canvas.clear(0);
+        canvas.circle(3, 3, 3, 1);
+        assert_eq!(render(&canvas, 1), [
+            "..###..",
+            ".#...#.",
+            "#.....#",
+            "#.....#",
+            "#.....#",
+            ".#...#.",
+            "..###..",
+        ]);
+    }
+
+    #[test]
+    fn test_bitmap_clipping() {
+        let rows = [0b1010_0000_0000_0000, 0b0110_0000_0000_0000];
+        let mut pixels = vec![0; 6 * 4 * 2 * 2];
+        let mut canvas = Canvas::new(&mut pixels, 6, 4, 2);
+        canvas.bitmap(1, 0, &rows, 4, 1, 1);
+        canvas.bitmap(-1, 2, &rows, 4, 1, 1);
+        canvas.bitmap(4, 2, &rows, 4, 2, 1);
+        assert_eq!(render(&canvas, 1), [".#.#..", "..##..", ".#..##", "##..##"]);
+    }
+
+    #[test]
+    fn test_bitmap_full_width_rows() {
+        let rows = [0x8001, 0xFFFF];
+        let mut pixels = vec![0; 17 * 2];
+        let mut canvas = Canvas::new(&mut pixels, 17, 2, 1);
+        canvas.bitmap(0, 0, &rows, 17, 1, 1);
+        canvas.bitmap(0, 0, &rows, 0, 1, 2);
+        assert_eq!(render(&canvas, 1), ["#..............#.", "################."]);
+        assert!(!pixels.contains(&2));
+    }
+}
@@ -0,0 +1,227 @@
+// Frames are drawn on separate layers and blended bottom to top with straight
+// alpha into the framebuffer. Layers are at playfield resolution, everything on
+// them is drawn in whole playfield pixels, and are scaled up while they are
+// blended. Only the changed parts of the frame are blended, and layers nobody
+// drew on since they were cleared are skipped. The layers under the HUD can be
+// composed shifted, for the screen shake.
+
+use crate::canvas::Canvas;
+
+pub const TRANSPARENT: u32 = 0;
+// shows where no layer is opaque
+const BACKDROP: u32 = 0xFF_00_00_00;
+const N_LAYERS: usize = 5;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Layer {
+    Background,
+    Playfield,
+    Effects,
+    Hud,
+    Overlay,
+}
+
+impl Layer {
+    // bottom to top
+    pub const ALL: [Layer; N_LAYERS] = [Layer::Background, Layer::Playfield, Layer::Effects, Layer::Hud, Layer::Overlay];
+}
+
+#[derive(Default)]
+pub struct Compositor {
+    // playfield pixels, every one is `unit` x `unit` pixels in the frame
+    width: usize,
+    height: usize,
+    unit: usize,
+    // indexed by `Layer`, buffers are allocated when first drawn on
+    layers: [Vec<u32>; N_LAYERS],
+    in_use: [bool; N_LAYERS],
+    // in frame pixels, applied to the top `camera_rows` rows of the frame
+    camera: (i32, i32),
+    camera_rows: usize,
+}
+
+impl Compositor {
+    pub fn new(width: usize, height: usize, unit: usize) -> Compositor {
+        Compositor { width, height, unit: unit.max(1), ..Default::default() }
+    }
+
+    pub fn canvas(&mut self, layer: Layer) -> Canvas<'_> {
+        let size = self.width * self.height;
+        let idx = layer as usize;
+        if let Some(in_use) = self.in_use.get_mut(idx) {
+            *in_use = true;
+        }
+        let pixels = &mut self.layers[idx];
+        if pixels.len() != size {
+            *pixels = vec![TRANSPARENT; size];
+        }
+        Canvas::new(pixels, self.width, self.height, 1)
+    }
+
+    pub fn clear(&mut self, layer: Layer) {
+        let idx = layer as usize;
+        if let (Some(pixels), Some(in_use)) = (self.layers.get_mut(idx), self.in_use.get_mut(idx)) {
+            if *in_use {
+                pixels.fill(TRANSPARENT);
+                *in_use = false;
+            }
+        }
+    }
+
+    // Shifts the layers under the HUD by `offset` frame pixels in the top
+    // `rows` rows, the edges are stretched into the gap. Returns whether the
+    // offset changed, those rows then have to be composed again.
+    pub fn set_camera(&mut self, offset: (i32, i32), rows: usize) -> bool {
+        let changed = offset != self.camera;
+        self.camera = offset;
+        self.camera_rows = rows.min(self.height * self.unit);
+        changed
+    }
+
+    // blends all layers inside `rects` (x, y, width, height in frame pixels) into `dst`
+    pub fn compose(&self, dst: &mut [u32], rects: &[[u32; 4]]) {
+        let (unit, stride) = (self.unit, self.width * self.unit);
+        let mut layers: [(&[u32], bool); N_LAYERS] = [(&[], false); N_LAYERS];
+        let mut n_layers = 0;
+        for layer in Layer::ALL {
+            if self.in_use[layer as usize] {
+                let shaken = (layer as usize) < Layer::Hud as usize && self.camera != (0, 0);
+                layers[n_layers] = (&self.layers[layer as usize], shaken);
+                n_layers += 1;
+            }
+        }
+        let layers = &layers[..n_layers];
+        let (dx, dy) = self.camera;
+        for &[x, y, width, height] in rects {
+            let (x, width) = (x as usize, width as usize);
+            // layer rows, still and shaken, the frame row above was blended from
+            let mut above = None;
+            for row in y as usize..(y + height) as usize {
+                let start = row * stride + x;
+                let shaken_row = if row < self.camera_rows {
+                    (row as i32 - dy).clamp(0, self.camera_rows as i32 - 1) as usize
+                } else {
+                    row
+                };
+                let src_rows = (row / unit, shaken_row / unit);
+                if above == Some(src_rows) && start + width <= dst.len() {
+                    dst.copy_within(start - stride..start - stride + width, start);
+                    continue;
+                }
+                let dst_row = match dst.get_mut(start..start + width) {
+                    Some(dst_row) => dst_row,
+                    None => {
+                        above = None;
+                        continue;
+                    },
+                };
+                above = Some(src_rows);
+                dst_row.fill(BACKDROP);
+                for (layer, shaken) in layers.iter() {
+                    let (src_row, dx) = if *shaken && row < self.camera_rows { (src_rows.1, dx) } else { (src_rows.0, 0) };
+                    let src_row = match layer.get(src_row * self.width..(src_row + 1) * self.width) {
+                        Some(src_row) => src_row,
+                        None => continue,
+                    };
+                    for (col, dst) in (x as i32..).zip(dst_row.iter_mut()) {
+                        let src_col = (col - dx).clamp(0, stride as i32 - 1) as usize / unit;
+                        if let Some(src) = src_row.get(src_col) {
+                            *dst = blend(*dst, *src);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+// `src` over the opaque `dst`
+pub fn blend(dst: u32, src: u32) -> u32 {
+    let alpha = src >> 24;
+    match alpha {
+        0 => dst,
+        0xFF => src,
+        _ => {
+            let mix = |shift: u32| {
+                let (src, dst) = ((src >> shift) & 0xFF, (dst >> shift) & 0xFF);
+                ((src * alpha + dst * (0xFF - alpha) + 0x7F) / 0xFF) << shift
+            };
+            0xFF_00_00_00 | mix(16) | mix(8) | mix(0)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::canvas::Rect;
+
+    #[test]
+    fn test_blend() {
+        assert_eq!(blend(0xFF_10_20_30, 0x00_FF_FF_FF), 0xFF_10_20_30);
+        assert_eq!(blend(0xFF_10_20_30, 0xFF_40_50_60), 0xFF_40_50_60);
+        assert_eq!(blend(0xFF_00_00_00, 0x80_FF_FF_FF), 0xFF_80_80_80);
+        assert_eq!(blend(0xFF_FF_00_FF, 0x40_00_FF_00), 0xFF_BF_40_BF);
+    }
+
+    #[test]
+    fn test_compose_layers_inside_rects() {
+        let mut compositor = Compositor::new(4, 2, 2);
+        compositor.canvas(Layer::Background).clear(0xFF_00_00_FF);
+        compositor.canvas(Layer::Playfield).pixel(1, 0, 0xFF_00_FF_00);
+        compositor.canvas(Layer::Overlay).fill_rect(Rect::new(0, 0, 2, 1), 0x80_FF_00_00);
+        compositor.canvas(Layer::Hud).pixel(3, 1, 0x80_FF_00_00);
+        compositor.clear(Layer::Hud);
+
+        // layers stay at playfield resolution
+        assert!(compositor.layers.iter().all(|pixels| pixels.is_empty() || pixels.len() == 4 * 2));
+
+        let mut frame = vec![7; 8 * 4];
+        compositor.compose(&mut frame, &[[0, 0, 4, 2], [6, 3, 2, 1]]);
+        assert_eq!(frame[..4], [0xFF_80_00_7F, 0xFF_80_00_7F, 0xFF_80_7F_00, 0xFF_80_7F_00]);
+        assert_eq!(frame[8..12], frame[..4]);
+        assert_eq!(frame[4..8], [7; 4]);
+        // cleared layers don't show up
+        assert_eq!(frame[30..], [0xFF_00_00_FF; 2]);
+        assert_eq!(frame[28..30], [7; 2]);
+
+        compositor.clear(Layer::Background);
+        compositor.clear(Layer::Overlay);
+        compositor.compose(&mut frame, &[[0, 0, 4, 1]]);
+        assert_eq!(frame[..4], [BACKDROP, BACKDROP, 0xFF_00_FF_00, 0xFF_00_FF_00]);
+    }
+
+    #[test]
+    fn test_camera_offset() {
+        let mut compositor = Compositor::new(4, 3, 1);
+        let mut playfield = compositor.canvas(Layer::Playfield);
+        playfield.pixel(0, 0, 0xFF_00_00_01);
+        playfield.pixel(1, 0, 0xFF_00_00_02);
+        playfield.pixel(2, 1, 0xFF_00_00_05);
+        playfield.pixel(1, 2, 0xFF_00_00_03);
+        compositor.canvas(Layer::Hud).pixel(3, 0, 0xFF_00_00_04);
+        let mut frame = vec![0; 4 * 3];
+        assert!(!compositor.set_camera((0, 0), 2));
+        assert!(compositor.set_camera((1, 1), 2));
+        compositor.compose(&mut frame, &[[0, 0, 4, 3]]);
+        // the top row is stretched into the gap, the HUD and the rows under the camera stay put
+        let shown: Vec<u32> = frame.iter().map(|pixel| pixel & 0xFF).collect();
+        assert_eq!(shown, [1, 1, 2, 4, 1, 1, 2, 0, 0, 3, 0, 0]);
+        assert!(!compositor.set_camera((1, 1), 2));
+    }
+
+    #[test]
+    fn test_camera_offset_inside_a_unit() {
+        let mut compositor = Compositor::new(2, 2, 2);
+        let mut playfield = compositor.canvas(Layer::Playfield);
+        playfield.pixel(0, 0, 0xFF_00_00_01);
+        playfield.pixel(1, 0, 0xFF_00_00_02);
+        playfield.pixel(0, 1, 0xFF_00_00_03);
+        playfield.pixel(1, 1, 0xFF_00_00_04);
+        let mut frame = vec![0; 4 * 4];
+        compositor.set_camera((1, 1), 4);
+        compositor.compose(&mut frame, &[[0, 0, 4, 4]]);
+        let shown: Vec<u32> = frame.iter().map(|pixel| pixel & 0xFF).collect();
+        assert_eq!(shown, [1, 1, 1, 2, 1, 1, 1, 2, 1, 1, 1, 2, 3, 3, 3, 4]);
+    }
+}
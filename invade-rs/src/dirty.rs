@@ -0,0 +1,134 @@
+// Tracks which parts of the frame changed since the host last uploaded it, and
+// since the layers were last composed into the framebuffer. Changes are
+// collected on a coarse grid of cells, so a bunch of bullets don't turn into
+// hundreds of tiny rectangles.
+
+const CELL_SIZE: usize = 8;
+
+pub struct DirtyRegion {
+    width: usize,
+    height: usize,
+    cols: usize,
+    cells: Vec<bool>,
+    // x, y, width, height in framebuffer pixels
+    rects: Vec<[u32; 4]>,
+    frame_cells: Vec<bool>,
+    frame_rects: Vec<[u32; 4]>,
+}
+
+impl DirtyRegion {
+    // size in playfield pixels
+    pub fn new(width: usize, height: usize) -> DirtyRegion {
+        let cols = width.div_ceil(CELL_SIZE);
+        let rows = height.div_ceil(CELL_SIZE);
+        DirtyRegion {
+            width,
+            height,
+            cols,
+            cells: vec![false; cols * rows],
+            rects: Vec::new(),
+            frame_cells: vec![false; cols * rows],
+            frame_rects: Vec::new(),
+        }
+    }
+
+    pub fn mark(&mut self, x: usize, y: usize) {
+        let idx = (y / CELL_SIZE) * self.cols + x / CELL_SIZE;
+        for cells in [&mut self.cells, &mut self.frame_cells] {
+            if let Some(cell) = cells.get_mut(idx) {
+                *cell = true;
+            }
+        }
+    }
+
+    pub fn mark_rows(&mut self, y: usize, height: usize) {
+        let rows = y / CELL_SIZE..(y + height).div_ceil(CELL_SIZE);
+        for cells in [&mut self.cells, &mut self.frame_cells] {
+            if let Some(cells) = cells.get_mut(rows.start * self.cols..rows.end * self.cols) {
+                cells.fill(true);
+            }
+        }
+    }
+
+    pub fn mark_all(&mut self) {
+        self.cells.fill(true);
+        self.frame_cells.fill(true);
+    }
+
+    pub fn clear(&mut self) {
+        self.cells.fill(false);
+        self.rects.clear();
+    }
+
+    #[cfg(test)]
+    pub fn is_empty(&self) -> bool {
+        !self.cells.iter().any(|cell| *cell)
+    }
+
+    // merges runs of dirty cells on each row, `scale` is framebuffer pixels per playfield pixel
+    pub fn rects(&mut self, scale: usize) -> &[[u32; 4]] {
+        merge_cells(&self.cells, self.cols, self.width, self.height, scale, &mut self.rects);
+        &self.rects
+    }
+
+    // like `rects`, but only what changed since the last call
+    pub fn take_frame_rects(&mut self, scale: usize) -> &[[u32; 4]] {
+        merge_cells(&self.frame_cells, self.cols, self.width, self.height, scale, &mut self.frame_rects);
+        self.frame_cells.fill(false);
+        &self.frame_rects
+    }
+}
+
+fn merge_cells(cells: &[bool], cols: usize, width: usize, height: usize, scale: usize, rects: &mut Vec<[u32; 4]>) {
+    rects.clear();
+    for (row, cells) in cells.chunks(cols).enumerate() {
+        let mut col = 0;
+        while col < cells.len() {
+            if !cells.get(col).copied().unwrap_or(false) {
+                col += 1;
+                continue;
+            }
+            let start = col;
+            while cells.get(col).copied().unwrap_or(false) {
+                col += 1;
+            }
+            let x = start * CELL_SIZE;
+            let y = row * CELL_SIZE;
+            let rect_width = (col * CELL_SIZE).min(width) - x;
+            let rect_height = ((row + 1) * CELL_SIZE).min(height) - y;
+            rects.push([(x * scale) as u32, (y * scale) as u32, (rect_width * scale) as u32, (rect_height * scale) as u32]);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rects_merge_neighbouring_cells() {
+        let mut dirty = DirtyRegion::new(20, 12);
+        assert!(dirty.is_empty());
+        assert!(dirty.rects(1).is_empty());
+
+        dirty.mark(1, 1);
+        dirty.mark(9, 2);
+        dirty.mark(19, 11);
+        assert_eq!(dirty.rects(1), [[0, 0, 16, 8], [16, 8, 4, 4]]);
+        assert_eq!(dirty.rects(3), [[0, 0, 48, 24], [48, 24, 12, 12]]);
+
+        dirty.clear();
+        dirty.mark_rows(10, 2);
+        assert_eq!(dirty.rects(1), [[0, 8, 20, 4]]);
+
+        dirty.mark_all();
+        assert_eq!(dirty.rects(1), [[0, 0, 20, 8], [0, 8, 20, 4]]);
+
+        // frame rects reset on every call, the host's ones only on clear
+        assert_eq!(dirty.take_frame_rects(1).len(), 2);
+        dirty.mark(1, 1);
+        assert_eq!(dirty.take_frame_rects(1), [[0, 0, 8, 8]]);
+        assert!(dirty.take_frame_rects(1).is_empty());
+        assert_eq!(dirty.rects(1).len(), 2);
+    }
+}
@@ -0,0 +1,146 @@
+// Gameplay events for the host, which otherwise only sees pixels, to drive
+// haptics, analytics or sound of its own. Records pile up in a byte queue in
+// linear memory until the host drains it, records that don't fit are dropped.
+//
+// Every record is RECORD_SIZE bytes, little endian:
+//   [kind: u8][player: u8][x: u8][y: u8][tick: u32][value: u32]
+// `tick` counts ticks since the level started, `player` is NO_PLAYER when no
+// player is involved and `x`, `y` are in playfield pixels or 0.
+//
+//   kind               player       x, y             value
+//   1 enemy killed     shooter      enemy position   score for the kill
+//   2 player hit       hit player   bullet position  health left
+//   3 weapon changed   player       0, 0             new weapon, 0 pistol, 1 rifle, 2 shotgun
+//   4 ammo depleted    player       0, 0             weapon that ran out, same numbers
+//   5 wave cleared     NO_PLAYER    0, 0             0
+//   6 game over        NO_PLAYER    0, 0             1 if the players won, 0 if they lost
+
+pub const RECORD_SIZE: usize = 12;
+pub const NO_PLAYER: u8 = 0xFF;
+// about 85 records, plenty for a few ticks
+const QUEUE_SIZE: usize = 1020;
+
+const KIND_ENEMY_KILLED: u8 = 1;
+const KIND_PLAYER_HIT: u8 = 2;
+const KIND_WEAPON_CHANGED: u8 = 3;
+const KIND_AMMO_DEPLETED: u8 = 4;
+const KIND_WAVE_CLEARED: u8 = 5;
+const KIND_GAME_OVER: u8 = 6;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Event {
+    EnemyKilled { player: u8, x: u8, y: u8, score: u32 },
+    PlayerHit { player: u8, x: u8, y: u8, health: u32 },
+    WeaponChanged { player: u8, weapon: u32 },
+    AmmoDepleted { player: u8, weapon: u32 },
+    WaveCleared,
+    GameOver { won: bool },
+}
+
+impl Event {
+    pub fn encode(&self, tick: u32) -> [u8; RECORD_SIZE] {
+        let (kind, player, x, y, value) = match *self {
+            Event::EnemyKilled { player, x, y, score } => (KIND_ENEMY_KILLED, player, x, y, score),
+            Event::PlayerHit { player, x, y, health } => (KIND_PLAYER_HIT, player, x, y, health),
+            Event::WeaponChanged { player, weapon } => (KIND_WEAPON_CHANGED, player, 0, 0, weapon),
+            Event::AmmoDepleted { player, weapon } => (KIND_AMMO_DEPLETED, player, 0, 0, weapon),
+            Event::WaveCleared => (KIND_WAVE_CLEARED, NO_PLAYER, 0, 0, 0),
+            Event::GameOver { won } => (KIND_GAME_OVER, NO_PLAYER, 0, 0, won as u32),
+        };
+        let mut record = [0; RECORD_SIZE];
+        record[0..4].copy_from_slice(&[kind, player, x, y]);
+        record[4..8].copy_from_slice(&tick.to_le_bytes());
+        record[8..12].copy_from_slice(&value.to_le_bytes());
+        record
+    }
+
+    // the tick and the event, what a host would read
+    #[cfg(test)]
+    pub fn decode(record: &[u8]) -> Option<(u32, Event)> {
+        let record: &[u8; RECORD_SIZE] = record.try_into().ok()?;
+        let (kind, player, x, y) = (record[0], record[1], record[2], record[3]);
+        let tick = u32::from_le_bytes([record[4], record[5], record[6], record[7]]);
+        let value = u32::from_le_bytes([record[8], record[9], record[10], record[11]]);
+        let event = match kind {
+            KIND_ENEMY_KILLED => Event::EnemyKilled { player, x, y, score: value },
+            KIND_PLAYER_HIT => Event::PlayerHit { player, x, y, health: value },
+            KIND_WEAPON_CHANGED => Event::WeaponChanged { player, weapon: value },
+            KIND_AMMO_DEPLETED => Event::AmmoDepleted { player, weapon: value },
+            KIND_WAVE_CLEARED => Event::WaveCleared,
+            KIND_GAME_OVER => Event::GameOver { won: value != 0 },
+            _ => return None,
+        };
+        Some((tick, event))
+    }
+}
+
+pub struct EventQueue {
+    buffer: [u8; QUEUE_SIZE],
+    len: usize,
+}
+
+impl EventQueue {
+    pub fn new() -> EventQueue {
+        EventQueue { buffer: [0; QUEUE_SIZE], len: 0 }
+    }
+
+    pub fn push(&mut self, tick: u32, event: &Event) {
+        if let Some(x) = self.buffer.get_mut(self.len..self.len + RECORD_SIZE) {
+            x.copy_from_slice(&event.encode(tick));
+            self.len += RECORD_SIZE;
+        }
+    }
+
+    pub fn bytes(&self) -> &[u8] {
+        self.buffer.get(..self.len).unwrap_or(&[])
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    // drops records pushed since the queue was `len` bytes long
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_layout() {
+        let event = Event::EnemyKilled { player: 1, x: 120, y: 40, score: 100 };
+        let record = event.encode(0x0102_0304);
+        assert_eq!(record, [1, 1, 120, 40, 4, 3, 2, 1, 100, 0, 0, 0]);
+        assert_eq!(Event::decode(&record), Some((0x0102_0304, event)));
+        for event in [
+            Event::PlayerHit { player: 0, x: 3, y: 4, health: 2 },
+            Event::WeaponChanged { player: 1, weapon: 2 },
+            Event::AmmoDepleted { player: 0, weapon: 1 },
+            Event::WaveCleared,
+            Event::GameOver { won: true },
+        ] {
+            assert_eq!(Event::decode(&event.encode(7)), Some((7, event)));
+        }
+        assert_eq!(Event::GameOver { won: false }.encode(0)[1], NO_PLAYER);
+    }
+
+    #[test]
+    fn test_queue_drops_what_does_not_fit() {
+        let mut queue = EventQueue::new();
+        for tick in 0..100 {
+            queue.push(tick, &Event::WaveCleared);
+        }
+        assert_eq!(queue.bytes().len(), QUEUE_SIZE / RECORD_SIZE * RECORD_SIZE);
+        // the oldest records are kept
+        let ticks: Vec<u32> = queue.bytes().chunks_exact(RECORD_SIZE).filter_map(Event::decode).map(|(tick, _)| tick).collect();
+        assert_eq!(ticks, (0..(QUEUE_SIZE / RECORD_SIZE) as u32).collect::<Vec<u32>>());
+
+        queue.truncate(RECORD_SIZE);
+        assert_eq!(queue.bytes().len(), RECORD_SIZE);
+        queue.clear();
+        assert!(queue.bytes().is_empty());
+    }
+}
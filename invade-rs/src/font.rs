@@ -0,0 +1,337 @@
+// Bitmap fonts: the built-in 5 pixel font covering printable ASCII and a
+// loader for fonts packed by the host.
+//
+// Glyph rows are u16 with the most significant bit as the leftmost column, a
+// glyph is at most 15 columns wide and is followed by one column of spacing.
+
+pub const MAX_GLYPH_WIDTH: u8 = 15;
+pub const MAX_HEIGHT: u8 = 16;
+
+// "IVFN", version, height, first char, glyph count, kerning pair count,
+// then per glyph its width and `height` rows as u16 little endian,
+// then per kerning pair the left char, the right char and an i8 adjustment
+const PACKED_MAGIC: &[u8; 4] = b"IVFN";
+const PACKED_VERSION: u8 = 1;
+const PACKED_HEADER_SIZE: usize = 9;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum FontError {
+    BadMagic,
+    UnsupportedVersion(u8),
+    BadHeight(u8),
+    BadGlyphWidth(u8),
+    Truncated,
+}
+
+pub struct Glyph<'a> {
+    pub width: u8,
+    pub rows: &'a [u16],
+}
+
+pub struct Font {
+    height: u8,
+    first_char: u8,
+    widths: Vec<u8>,
+    // `height` rows for every glyph
+    rows: Vec<u16>,
+    kerning: Vec<(u8, u8, i8)>,
+}
+
+// built-in glyphs are 5 rows of at most 8 columns
+struct Glyph5 {
+    width: u8,
+    rows: [u8; 5],
+}
+
+const BUILTIN_FIRST_CHAR: u8 = b' ';
+const BUILTIN_GLYPHS: [Glyph5; 95] = [
+    Glyph5 { width: 2, rows: [0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b00000000] }, // ' '
+    Glyph5 { width: 1, rows: [0b10000000, 0b10000000, 0b10000000, 0b00000000, 0b10000000] }, // '!'
+    Glyph5 { width: 3, rows: [0b10100000, 0b10100000, 0b00000000, 0b00000000, 0b00000000] }, // '"'
+    Glyph5 { width: 5, rows: [0b01010000, 0b11111000, 0b01010000, 0b11111000, 0b01010000] }, // '#'
+    Glyph5 { width: 4, rows: [0b01110000, 0b10100000, 0b01100000, 0b00110000, 0b11100000] }, // '$'
+    Glyph5 { width: 4, rows: [0b10010000, 0b00100000, 0b01000000, 0b10000000, 0b10010000] }, // '%'
+    Glyph5 { width: 4, rows: [0b01000000, 0b10100000, 0b01000000, 0b10100000, 0b01010000] }, // '&'
+    Glyph5 { width: 1, rows: [0b10000000, 0b10000000, 0b00000000, 0b00000000, 0b00000000] }, // '\''
+    Glyph5 { width: 2, rows: [0b01000000, 0b10000000, 0b10000000, 0b10000000, 0b01000000] }, // '('
+    Glyph5 { width: 2, rows: [0b10000000, 0b01000000, 0b01000000, 0b01000000, 0b10000000] }, // ')'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b01000000, 0b10100000, 0b00000000] }, // '*'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01000000, 0b11100000, 0b01000000, 0b00000000] }, // '+'
+    Glyph5 { width: 2, rows: [0b00000000, 0b00000000, 0b00000000, 0b01000000, 0b10000000] }, // ','
+    Glyph5 { width: 3, rows: [0b00000000, 0b00000000, 0b11100000, 0b00000000, 0b00000000] }, // '-'
+    Glyph5 { width: 1, rows: [0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b10000000] }, // '.'
+    Glyph5 { width: 5, rows: [0b00001000, 0b00010000, 0b00100000, 0b01000000, 0b10000000] }, // '/'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b10010000, 0b10010000, 0b01100000] }, // '0'
+    Glyph5 { width: 3, rows: [0b01000000, 0b11000000, 0b01000000, 0b01000000, 0b11100000] }, // '1'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b00100000, 0b01000000, 0b11110000] }, // '2'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b00100000, 0b10010000, 0b01100000] }, // '3'
+    Glyph5 { width: 4, rows: [0b01010000, 0b10010000, 0b11110000, 0b00010000, 0b00010000] }, // '4'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b11100000, 0b00010000, 0b11100000] }, // '5'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b11110000, 0b10010000, 0b11110000] }, // '6'
+    Glyph5 { width: 4, rows: [0b11110000, 0b00010000, 0b00100000, 0b01000000, 0b01000000] }, // '7'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b01100000, 0b10010000, 0b01100000] }, // '8'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b01110000, 0b00100000, 0b01000000] }, // '9'
+    Glyph5 { width: 1, rows: [0b00000000, 0b00000000, 0b10000000, 0b00000000, 0b10000000] }, // ':'
+    Glyph5 { width: 2, rows: [0b00000000, 0b00000000, 0b01000000, 0b00000000, 0b01000000] }, // ';'
+    Glyph5 { width: 3, rows: [0b00100000, 0b01000000, 0b10000000, 0b01000000, 0b00100000] }, // '<'
+    Glyph5 { width: 3, rows: [0b00000000, 0b11100000, 0b00000000, 0b11100000, 0b00000000] }, // '='
+    Glyph5 { width: 3, rows: [0b10000000, 0b01000000, 0b00100000, 0b01000000, 0b10000000] }, // '>'
+    Glyph5 { width: 3, rows: [0b11000000, 0b00100000, 0b01000000, 0b00000000, 0b01000000] }, // '?'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b10110000, 0b10000000, 0b01110000] }, // '@'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10010000, 0b11110000, 0b10010000, 0b10010000] }, // 'A'
+    Glyph5 { width: 4, rows: [0b11100000, 0b10010000, 0b11100000, 0b10010000, 0b11100000] }, // 'B'
+    Glyph5 { width: 3, rows: [0b11100000, 0b10000000, 0b10000000, 0b10000000, 0b11100000] }, // 'C'
+    Glyph5 { width: 4, rows: [0b11100000, 0b10010000, 0b10010000, 0b10010000, 0b11100000] }, // 'D'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b11100000, 0b10000000, 0b11110000] }, // 'E'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b11100000, 0b10000000, 0b10000000] }, // 'F'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b10110000, 0b10010000, 0b11110000] }, // 'G'
+    Glyph5 { width: 4, rows: [0b10010000, 0b10010000, 0b11110000, 0b10010000, 0b10010000] }, // 'H'
+    Glyph5 { width: 1, rows: [0b10000000, 0b10000000, 0b10000000, 0b10000000, 0b10000000] }, // 'I'
+    Glyph5 { width: 4, rows: [0b00010000, 0b00010000, 0b00010000, 0b10010000, 0b01100000] }, // 'J'
+    Glyph5 { width: 4, rows: [0b10010000, 0b10100000, 0b11000000, 0b10100000, 0b10010000] }, // 'K'
+    Glyph5 { width: 4, rows: [0b10000000, 0b10000000, 0b10000000, 0b10000000, 0b11110000] }, // 'L'
+    Glyph5 { width: 5, rows: [0b10001000, 0b11011000, 0b10101000, 0b10001000, 0b10001000] }, // 'M'
+    Glyph5 { width: 5, rows: [0b10001000, 0b11001000, 0b10101000, 0b10011000, 0b10001000] }, // 'N'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10010000, 0b10010000, 0b10010000, 0b11110000] }, // 'O'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10010000, 0b11110000, 0b10000000, 0b10000000] }, // 'P'
+    Glyph5 { width: 4, rows: [0b01100000, 0b10010000, 0b10010000, 0b10100000, 0b01010000] }, // 'Q'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10010000, 0b11110000, 0b10100000, 0b10010000] }, // 'R'
+    Glyph5 { width: 4, rows: [0b11110000, 0b10000000, 0b11110000, 0b00010000, 0b11110000] }, // 'S'
+    Glyph5 { width: 5, rows: [0b11111000, 0b00100000, 0b00100000, 0b00100000, 0b00100000] }, // 'T'
+    Glyph5 { width: 4, rows: [0b10010000, 0b10010000, 0b10010000, 0b10010000, 0b11110000] }, // 'U'
+    Glyph5 { width: 5, rows: [0b10001000, 0b10001000, 0b10001000, 0b01010000, 0b00100000] }, // 'V'
+    Glyph5 { width: 5, rows: [0b10001000, 0b10001000, 0b10101000, 0b11011000, 0b10001000] }, // 'W'
+    Glyph5 { width: 5, rows: [0b10001000, 0b01010000, 0b00100000, 0b01010000, 0b10001000] }, // 'X'
+    Glyph5 { width: 5, rows: [0b10001000, 0b01010000, 0b00100000, 0b00100000, 0b00100000] }, // 'Y'
+    Glyph5 { width: 4, rows: [0b11110000, 0b00010000, 0b01100000, 0b10000000, 0b11110000] }, // 'Z'
+    Glyph5 { width: 2, rows: [0b11000000, 0b10000000, 0b10000000, 0b10000000, 0b11000000] }, // '['
+    Glyph5 { width: 5, rows: [0b10000000, 0b01000000, 0b00100000, 0b00010000, 0b00001000] }, // '\\'
+    Glyph5 { width: 2, rows: [0b11000000, 0b01000000, 0b01000000, 0b01000000, 0b11000000] }, // ']'
+    Glyph5 { width: 3, rows: [0b01000000, 0b10100000, 0b00000000, 0b00000000, 0b00000000] }, // '^'
+    Glyph5 { width: 4, rows: [0b00000000, 0b00000000, 0b00000000, 0b00000000, 0b11110000] }, // '_'
+    Glyph5 { width: 2, rows: [0b10000000, 0b01000000, 0b00000000, 0b00000000, 0b00000000] }, // '`'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01100000, 0b10100000, 0b10100000, 0b01100000] }, // 'a'
+    Glyph5 { width: 3, rows: [0b10000000, 0b11000000, 0b10100000, 0b10100000, 0b11000000] }, // 'b'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01100000, 0b10000000, 0b10000000, 0b01100000] }, // 'c'
+    Glyph5 { width: 3, rows: [0b00100000, 0b01100000, 0b10100000, 0b10100000, 0b01100000] }, // 'd'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01000000, 0b10100000, 0b11000000, 0b01100000] }, // 'e'
+    Glyph5 { width: 3, rows: [0b01100000, 0b10000000, 0b11100000, 0b10000000, 0b10000000] }, // 'f'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01100000, 0b10100000, 0b01100000, 0b11000000] }, // 'g'
+    Glyph5 { width: 3, rows: [0b10000000, 0b11000000, 0b10100000, 0b10100000, 0b10100000] }, // 'h'
+    Glyph5 { width: 1, rows: [0b10000000, 0b00000000, 0b10000000, 0b10000000, 0b10000000] }, // 'i'
+    Glyph5 { width: 2, rows: [0b01000000, 0b00000000, 0b01000000, 0b01000000, 0b10000000] }, // 'j'
+    Glyph5 { width: 3, rows: [0b10000000, 0b10100000, 0b11000000, 0b10100000, 0b10100000] }, // 'k'
+    Glyph5 { width: 2, rows: [0b10000000, 0b10000000, 0b10000000, 0b10000000, 0b01000000] }, // 'l'
+    Glyph5 { width: 5, rows: [0b00000000, 0b11010000, 0b10101000, 0b10101000, 0b10101000] }, // 'm'
+    Glyph5 { width: 3, rows: [0b00000000, 0b11000000, 0b10100000, 0b10100000, 0b10100000] }, // 'n'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01000000, 0b10100000, 0b10100000, 0b01000000] }, // 'o'
+    Glyph5 { width: 3, rows: [0b00000000, 0b11000000, 0b10100000, 0b11000000, 0b10000000] }, // 'p'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01100000, 0b10100000, 0b01100000, 0b00100000] }, // 'q'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b11000000, 0b10000000, 0b10000000] }, // 'r'
+    Glyph5 { width: 3, rows: [0b00000000, 0b01100000, 0b10000000, 0b00100000, 0b11000000] }, // 's'
+    Glyph5 { width: 3, rows: [0b01000000, 0b11100000, 0b01000000, 0b01000000, 0b00100000] }, // 't'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b10100000, 0b10100000, 0b01100000] }, // 'u'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b10100000, 0b10100000, 0b01000000] }, // 'v'
+    Glyph5 { width: 5, rows: [0b00000000, 0b10001000, 0b10101000, 0b10101000, 0b01010000] }, // 'w'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b01000000, 0b01000000, 0b10100000] }, // 'x'
+    Glyph5 { width: 3, rows: [0b00000000, 0b10100000, 0b10100000, 0b01100000, 0b11000000] }, // 'y'
+    Glyph5 { width: 3, rows: [0b00000000, 0b11100000, 0b01000000, 0b10000000, 0b11100000] }, // 'z'
+    Glyph5 { width: 3, rows: [0b01100000, 0b01000000, 0b11000000, 0b01000000, 0b01100000] }, // '{'
+    Glyph5 { width: 1, rows: [0b10000000, 0b10000000, 0b10000000, 0b10000000, 0b10000000] }, // '|'
+    Glyph5 { width: 3, rows: [0b11000000, 0b01000000, 0b01100000, 0b01000000, 0b11000000] }, // '}'
+    Glyph5 { width: 4, rows: [0b00000000, 0b01010000, 0b10100000, 0b00000000, 0b00000000] }, // '~'
+];
+
+// only pairs where the shapes leave a gap to close
+const BUILTIN_KERNING: [(u8, u8, i8); 9] = [
+    (b'L', b'T', -1),
+    (b'L', b'V', -1),
+    (b'L', b'Y', -1),
+    (b'T', b'.', -1),
+    (b'T', b',', -1),
+    (b'P', b'.', -1),
+    (b'F', b'.', -1),
+    (b'Y', b'.', -1),
+    (b'V', b'.', -1),
+];
+
+impl Font {
+    pub fn builtin() -> Font {
+        let mut rows = Vec::with_capacity(BUILTIN_GLYPHS.len() * 5);
+        for glyph in BUILTIN_GLYPHS.iter() {
+            rows.extend(glyph.rows.iter().map(|row| (*row as u16) << 8));
+        }
+        Font {
+            height: 5,
+            first_char: BUILTIN_FIRST_CHAR,
+            widths: BUILTIN_GLYPHS.iter().map(|glyph| glyph.width).collect(),
+            rows,
+            kerning: BUILTIN_KERNING.to_vec(),
+        }
+    }
+
+    pub fn height(&self) -> usize {
+        self.height as usize
+    }
+
+    fn glyph_idx(&self, c: char) -> Option<usize> {
+        let idx = (c as u32).checked_sub(self.first_char as u32)? as usize;
+        if idx < self.widths.len() { Some(idx) } else { None }
+    }
+
+    // characters the font doesn't have are drawn as '?', or as a box if that's missing too
+    pub fn glyph(&self, c: char) -> Glyph<'_> {
+        const NO_ROWS: [u16; MAX_HEIGHT as usize] = [0; MAX_HEIGHT as usize];
+        let height = self.height as usize;
+        match self.glyph_idx(c).or_else(|| self.glyph_idx('?')) {
+            Some(idx) => Glyph {
+                width: self.widths.get(idx).copied().unwrap_or(0),
+                rows: self.rows.get(idx * height..(idx + 1) * height).unwrap_or(&NO_ROWS[..height]),
+            },
+            None => Glyph { width: 3, rows: &BOX_ROWS[..height.min(BOX_ROWS.len())] },
+        }
+    }
+
+    pub fn kerning(&self, left: char, right: char) -> i32 {
+        match self.kerning.iter().find(|(l, r, _)| *l as char == left && *r as char == right) {
+            Some((_, _, adjust)) => *adjust as i32,
+            None => 0,
+        }
+    }
+
+    // distance to the start of the next character, in font pixels
+    pub fn advance(&self, c: char, next: Option<char>) -> i32 {
+        let kerning = match next {
+            Some(next) => self.kerning(c, next),
+            None => 0,
+        };
+        self.glyph(c).width as i32 + 1 + kerning
+    }
+
+    pub fn measure<I: Iterator<Item = char>>(&self, text: I) -> usize {
+        let mut text = text.peekable();
+        let mut width = 0;
+        while let Some(c) = text.next() {
+            width += self.advance(c, text.peek().copied());
+        }
+        width.max(0) as usize
+    }
+
+    pub fn load_packed(data: &[u8]) -> Result<Font, FontError> {
+        let header = data.get(..PACKED_HEADER_SIZE).ok_or(FontError::Truncated)?;
+        if &header[0..4] != PACKED_MAGIC {
+            return Err(FontError::BadMagic);
+        }
+        if header[4] != PACKED_VERSION {
+            return Err(FontError::UnsupportedVersion(header[4]));
+        }
+        let (height, first_char, n_glyphs, n_kerning) = (header[5], header[6], header[7] as usize, header[8] as usize);
+        if height == 0 || height > MAX_HEIGHT {
+            return Err(FontError::BadHeight(height));
+        }
+
+        let glyph_size = 1 + 2 * height as usize;
+        let glyph_data = data.get(PACKED_HEADER_SIZE..PACKED_HEADER_SIZE + n_glyphs * glyph_size).ok_or(FontError::Truncated)?;
+        let mut widths = Vec::with_capacity(n_glyphs);
+        let mut rows = Vec::with_capacity(n_glyphs * height as usize);
+        for glyph in glyph_data.chunks_exact(glyph_size) {
+            let width = glyph[0];
+            if width > MAX_GLYPH_WIDTH {
+                return Err(FontError::BadGlyphWidth(width));
+            }
+            widths.push(width);
+            rows.extend(glyph[1..].chunks_exact(2).map(|row| u16::from_le_bytes([row[0], row[1]])));
+        }
+
+        let kerning_start = PACKED_HEADER_SIZE + n_glyphs * glyph_size;
+        let kerning_data = data.get(kerning_start..kerning_start + 3 * n_kerning).ok_or(FontError::Truncated)?;
+        let kerning = kerning_data.chunks_exact(3).map(|pair| (pair[0], pair[1], pair[2] as i8)).collect();
+
+        Ok(Font { height, first_char, widths, rows, kerning })
+    }
+
+    #[cfg(test)]
+    pub fn to_packed(&self) -> Vec<u8> {
+        let mut data = Vec::new();
+        data.extend_from_slice(PACKED_MAGIC);
+        data.extend_from_slice(&[PACKED_VERSION, self.height, self.first_char, self.widths.len() as u8, self.kerning.len() as u8]);
+        for (width, rows) in self.widths.iter().zip(self.rows.chunks_exact(self.height as usize)) {
+            data.push(*width);
+            for row in rows {
+                data.extend_from_slice(&row.to_le_bytes());
+            }
+        }
+        for (left, right, adjust) in self.kerning.iter() {
+            data.extend_from_slice(&[*left, *right, *adjust as u8]);
+        }
+        data
+    }
+}
+
+const BOX_ROWS: [u16; MAX_HEIGHT as usize] = [0b1110_0000_0000_0000; MAX_HEIGHT as usize];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_builtin_covers_printable_ascii() {
+        let font = Font::builtin();
+        let question_mark = font.glyph('?').rows.to_vec();
+        for c in ' '..='~' {
+            let glyph = font.glyph(c);
+            assert_eq!(glyph.rows.len(), 5);
+            assert!(glyph.width >= 1 && glyph.width <= 5, "{c}");
+            // blank space only for ' ', glyphs never use the last column
+            assert_eq!(glyph.rows.iter().all(|row| *row == 0), c == ' ', "{c}");
+            assert!(glyph.rows.iter().all(|row| row & !(u16::MAX << (16 - glyph.width)) == 0), "{c}");
+            if c != '?' {
+                assert_ne!(glyph.rows, question_mark, "{c}");
+            }
+        }
+        assert_eq!(font.glyph('\u{e9}').rows, question_mark);
+    }
+
+    #[test]
+    fn test_measure_and_kerning() {
+        let font = Font::builtin();
+        assert_eq!(font.measure("".chars()), 0);
+        assert_eq!(font.measure("HI".chars()), 5 + 2);
+        assert_eq!(font.measure("LT".chars()), 5 + 6 - 1);
+        assert_eq!(font.advance('L', Some('A')), 5);
+    }
+
+    #[test]
+    fn test_packed_roundtrip() {
+        let font = Font::builtin();
+        let packed = font.to_packed();
+        let loaded = Font::load_packed(&packed).unwrap();
+        for c in ' '..='~' {
+            assert_eq!(loaded.glyph(c).rows, font.glyph(c).rows);
+            assert_eq!(loaded.glyph(c).width, font.glyph(c).width);
+        }
+        assert_eq!(loaded.kerning('L', 'T'), -1);
+
+        assert_eq!(Font::load_packed(&packed[..packed.len() - 1]).err(), Some(FontError::Truncated));
+        assert_eq!(Font::load_packed(b"IVFX").err(), Some(FontError::Truncated));
+        let mut bad = packed.clone();
+        bad[0] = b'X';
+        assert_eq!(Font::load_packed(&bad).err(), Some(FontError::BadMagic));
+        let mut bad = packed.clone();
+        bad[5] = 17;
+        assert_eq!(Font::load_packed(&bad).err(), Some(FontError::BadHeight(17)));
+    }
+
+    #[test]
+    fn test_missing_glyphs() {
+        // two glyph font starting at 'A', without '?'
+        let mut packed = b"IVFN\x01\x02A\x02\x00".to_vec();
+        packed.extend_from_slice(&[3, 0x00, 0xE0, 0x00, 0xA0, 2, 0x00, 0xC0, 0x00, 0xC0]);
+        let font = Font::load_packed(&packed).unwrap();
+        assert_eq!(font.height(), 2);
+        assert_eq!(font.glyph('B').width, 2);
+        assert_eq!(font.glyph('B').rows, [0xC000, 0xC000]);
+        assert_eq!(font.glyph('Z').width, 3);
+        assert_eq!(font.glyph('Z').rows, [0xE000, 0xE000]);
+    }
+}
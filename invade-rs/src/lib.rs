@@ -1,80 +1,139 @@
+// the js_* exports share the safety contract documented on GAMES
+#![allow(clippy::missing_safety_doc)]
+
+mod assets;
+mod blit;
+mod camera;
+mod canvas;
+mod compositor;
+mod dirty;
+mod events;
+mod font;
+mod music;
+mod netplay;
+mod palette;
+mod particles;
+mod rewind;
+mod rng;
+mod sprite;
+mod starfield;
 mod static_allocator;
+mod synth;
+mod text;
+mod upscale;
+// the build script's sprite parser, only here so its tests run
+#[cfg(test)]
+#[path = "../build/text_art.rs"]
+mod text_art;
 
-use std::cell::OnceCell;
-
-const STATUS_BAR_HEIGHT: usize = 10;
+const MAX_PLAYERS: usize = 2;
+const STATUS_BAR_HEIGHT: usize = 1 + MAX_PLAYERS * (FONT_SIZE as usize + 2);
 const WIDTH: usize = 200;
 const HEIGHT: usize = 150;
 const MULT: usize = 6;
 const BUFFER_SIZE: usize = WIDTH * MULT * (HEIGHT + STATUS_BAR_HEIGHT) * MULT;
 const MAX_BULLETS: usize = 64;
 const MAX_ENEMIES: usize = 16;
+const MAX_PARTICLES: usize = 48;
 const MAX_PLAYER_HEALTH: i32 = 3;
 const MAX_RIFLE_AMMO: i32 = 10;
 const MAX_SHOTGUN_AMMO: i32 = 10;
 const FONT_SIZE: u32 = 5;
-
-#[no_mangle]
-static mut BUFFER: [u32; BUFFER_SIZE] = [0; BUFFER_SIZE];
-
-struct Bitmap2D {
-    width: u32,
-    height: u32,
-    bitmap: &'static [u16]
-}
-
-/*
- *       OO     
- *      OOOO    
- *      OOOO    
- *  OOOOOOOOOOOO 
- * OOOOOOOOOOOOOO
- * OOOOOOOOOOOOOO
- * OOOOOOOOOOOOOO
- * */
-const PLAYER_BITMAP: Bitmap2D = Bitmap2D { width: 14, height: 7,
-bitmap: &[
-    0b0000001100000000,
-    0b0000011110000000,
-    0b0000011110000000,
-    0b0111111111111000,
-    0b1111111111111100,
-    0b1111111111111100,
-    0b1111111111111100,
-] };
-
-/*
- *   O      O  
- *    O    O   
- *   OOOOOOOO  
- *  OO OOOO OO 
- * OOOOOOOOOOOO
- * O OOOOOOOO O
- * O O      O O
- *    OO  OO   
- * */
-const ENEMY_BITMAP: Bitmap2D = Bitmap2D { width: 12, height: 8,
-bitmap: &[
-    0b0010000001000000,
-    0b0001000010000000,
-    0b0011111111000000,
-    0b0110111101100000,
-    0b1111111111110000,
-    0b1011111111010000,
-    0b1010000001010000,
-    0b0001100110000000,
-] };
-
-#[derive(Eq, PartialEq)]
+const MOVE_SIZE: i32 = MULT as i32;
+const AXIS_RESOLUTION: i32 = 1000;
+const DEFAULT_DEAD_ZONE: i32 = 150;
+const MAX_POINTER_SPEED: i32 = MOVE_SIZE;
+// playfield pixels are split this finely for shake, particles and stars
+const SUBPIXELS: i32 = 16;
+const ENEMY_HIT_SCORE: u32 = 10;
+const ENEMY_KILL_SCORE: u32 = 100;
+const COMMANDER_SHOT_COOLDOWN_IN_TICKS: u32 = 20;
+const COMMANDER_SPECIAL_COOLDOWN_IN_TICKS: u32 = 300;
+const MAX_NET_CATCHUP_TICKS: usize = 4;
+const MAX_GAMES: usize = 8;
+// how much of the frozen game the help screen covers
+const PAUSE_SCREEN_ALPHA: u32 = 0xC0;
+// palette indices of the sprites in assets/sprites.txt
+const SPRITE_BODY: u8 = 1;
+const SPRITE_COCKPIT: u8 = 2;
+const SPRITE_ENGINE: u8 = 3;
+const SPRITE_OUTLINE: u8 = 4;
+// colour ramps of particles, see Game::particle_ramps
+const PARTICLE_FLASH: u8 = 0;
+const PARTICLE_EXPLOSION: u8 = 1;
+const PARTICLE_RUBBLE: u8 = 2;
+// followed by one for every other player
+const PARTICLE_DEBRIS: u8 = 3;
+const MUZZLE_FLASH: particles::Burst = particles::Burst {
+    count: 3, velocity: (0, -SUBPIXELS), spread: SUBPIXELS / 2, gravity: 0, lifetime: 4, color: PARTICLE_FLASH,
+};
+const EXPLOSION: particles::Burst = particles::Burst {
+    count: 12, velocity: (0, 0), spread: SUBPIXELS, gravity: 2, lifetime: 20, color: PARTICLE_EXPLOSION,
+};
+const RUBBLE: particles::Burst = particles::Burst {
+    count: 4, velocity: (0, 0), spread: SUBPIXELS / 2, gravity: 3, lifetime: 12, color: PARTICLE_RUBBLE,
+};
+// brightness of the star depths from far to near, between the background and the star colour
+const STAR_LEVELS: [u8; starfield::N_DEPTHS] = [96, 160, 255];
+const PLAYER_HIT_SHAKE: i32 = 2;
+// player deaths and clearing the wave
+const BIG_EVENT_SHAKE: i32 = 4;
+const BIG_EVENT_HIT_STOP_FRAMES: u32 = 6;
+// space, ctrl, escape and special only count on the frame they're pressed
+const LATCHED_KEYS: u32 = 4 | 8 | 16 | 32;
+const DEBRIS: particles::Burst = particles::Burst {
+    count: 8, velocity: (0, -SUBPIXELS), spread: SUBPIXELS, gravity: 3, lifetime: 16, color: PARTICLE_DEBRIS,
+};
+// the host runs a tick every 30 ms, that much sound is rendered per tick
+const TICK_IN_MS: u32 = 30;
+const SAMPLES_PER_TICK: usize = (synth::SAMPLE_RATE * TICK_IN_MS / 1000) as usize;
+const SHOT_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Square, freq: (1200, 300), attack: 2, hold: 20, release: 60, volume: 50,
+};
+const ENEMY_SHOT_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Triangle, freq: (600, 150), attack: 2, hold: 10, release: 60, volume: 60,
+};
+const ENEMY_HIT_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Triangle, freq: (330, 660), attack: 2, hold: 30, release: 40, volume: 90,
+};
+const EXPLOSION_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Noise, freq: (4000, 300), attack: 2, hold: 60, release: 300, volume: 100,
+};
+const PLAYER_HIT_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Square, freq: (220, 55), attack: 2, hold: 60, release: 120, volume: 80,
+};
+const PICKUP_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Square, freq: (660, 1320), attack: 2, hold: 50, release: 50, volume: 60,
+};
+// frequency is set per note
+const MARCH_SOUND: synth::Effect = synth::Effect {
+    wave: synth::Wave::Square, freq: (0, 0), attack: 2, hold: 40, release: 60, volume: 90,
+};
+// the four falling notes of the march, in Hz
+const MARCH_NOTES: [u32; 4] = [98, 87, 78, 73];
+// formation steps between notes, plus two for every enemy left so the beat picks up as the wave thins out
+const MARCH_MIN_STEPS: u32 = 8;
+// songs in assets/music.trk for every screen
+const START_TRACK: &str = "start";
+const GAME_TRACK: &str = "game";
+// the game has no boss enemy, so the last invader of a wave is the boss, and
+// in versus it's player two commanding the invaders
+const BOSS_TRACK: &str = "boss";
+const GAME_OVER_TRACK: &str = "game_over";
+// in percent at the last enemy, in-game music gets faster as the enemies go
+const MUSIC_MAX_SPEEDUP: u32 = 100;
+
+#[derive(Clone, Copy, Eq, PartialEq)]
 enum Weapon {
     Pistol,
     Rifle,
     Shotgun,
 }
 
+#[derive(Clone, Copy)]
 struct Player {
+    id: u8,
     pos: i32,
-    color: u32,
     health: i32,
     last_shot_in_ticks: u32,
     opacity: u32,
@@ -82,19 +141,23 @@ struct Player {
     rifle_ammo: i32,
     shotgun_ammo: i32,
     reset_status_bar: bool,
+    score: u32,
 }
 
 #[derive(Clone, Copy, PartialEq)]
 enum Tile {
     Background,
-    Player,
+    // player index and the sprite's palette index
+    Player(u8, u8),
     Bullet,
     Obstacle,
-    Enemy(u8),
+    // colour level and the sprite's palette index
+    Enemy(u8, u8),
+    Marker,
 }
 const DEFAULT_TILE: Tile = Tile::Background;
-const ENEMY_SPEED_FREQ_IN_TICKS: u64 = 1;
 
+#[derive(Clone, Copy)]
 struct Enemy {
     x: u8,
     y: u8,
@@ -102,28 +165,85 @@ struct Enemy {
     max_health: u8,
 }
 
+#[derive(Clone, Copy)]
 enum BulletStatus {
     Alive,
-    HitPlayer,
+    HitPlayer(u8),
     HitEnemy,
-    HitObstacle
+    HitObstacle,
+    // knocked a tile out of a bunker
+    HitBunker,
 }
 
+#[derive(Clone, Copy, PartialEq)]
+enum BulletOwner {
+    Enemy,
+    Player(u8),
+}
+
+#[derive(Clone, Copy)]
 struct Bullet {
     x: u8,
     y: u8,
     speed: i8,
     damage: u8,
     status: BulletStatus,
+    owner: BulletOwner,
 }
 
-#[derive(Eq, PartialEq)]
+#[derive(Clone, Copy, Eq, PartialEq)]
 enum GameState {
     StartScreen,
     Playing,
     EndScreen(bool)
 }
 
+#[derive(Clone, Copy, Eq, PartialEq)]
+enum GameMode {
+    Single,
+    Coop,
+    Versus,
+}
+
+impl GameMode {
+    // number of ships, in versus mode player 2 commands the invaders
+    fn n_players(&self) -> usize {
+        match self {
+            GameMode::Single => 1,
+            GameMode::Coop => 2,
+            GameMode::Versus => 1,
+        }
+    }
+
+    fn next(&self) -> GameMode {
+        match self {
+            GameMode::Single => GameMode::Coop,
+            GameMode::Coop => GameMode::Versus,
+            GameMode::Versus => GameMode::Single,
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Commander {
+    selected_enemy: usize,
+    last_shot_in_ticks: u32,
+    special_cooldown: u32,
+    formation_blocked: bool,
+    reset_status_bar: bool,
+}
+
+impl Commander {
+    fn new() -> Commander {
+        Commander {
+            selected_enemy: 0, last_shot_in_ticks: 0,
+            special_cooldown: COMMANDER_SPECIAL_COOLDOWN_IN_TICKS,
+            formation_blocked: false, reset_status_bar: false
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
 struct KeyEvent (u32);
 
 impl KeyEvent {
@@ -142,84 +262,633 @@ impl KeyEvent {
     fn pressed_escape(&self) -> bool {
         (self.0 & 16) != 0
     }
+    fn pressed_special(&self) -> bool {
+        (self.0 & 32) != 0
+    }
+    fn move_diff(&self) -> i32 {
+        if self.pressed_left() { -MOVE_SIZE }
+        else if self.pressed_right() { MOVE_SIZE }
+        else { 0 }
+    }
 }
 
-struct Game<'a> {
-    default_color: u32,
+#[derive(Clone, Copy)]
+struct PlayerInput {
+    key_event: KeyEvent,
+    move_diff: i32,
+}
+
+impl PlayerInput {
+    fn from_keys(key_event_flags: u32) -> PlayerInput {
+        let key_event = get_key_event(key_event_flags);
+        let move_diff = key_event.move_diff();
+        PlayerInput { key_event, move_diff }
+    }
+}
+
+const OBSTACLE_MAP_SIZE: usize = (WIDTH * HEIGHT).div_ceil(64);
+const NO_ENEMY: Enemy = Enemy { x: 0, y: 0, health: 0, max_health: 0 };
+const NO_BULLET: Bullet = Bullet {
+    x: 0, y: 0, speed: 0, damage: 0, status: BulletStatus::Alive, owner: BulletOwner::Enemy
+};
+
+// Copy of everything the simulation depends on, the tile map is reduced to a
+// bitmap of obstacles as the rest of it is redrawn every tick.
+#[derive(Clone)]
+struct GameSnapshot {
+    random_seed: u32,
+    game_state: GameState,
+    mode: GameMode,
+    players: [Player; MAX_PLAYERS],
+    commander: Commander,
+    enemies: [Enemy; MAX_ENEMIES],
+    n_enemies: usize,
+    bullets: [Bullet; MAX_BULLETS],
+    n_bullets: usize,
+    obstacles: [u64; OBSTACLE_MAP_SIZE],
+    tick_counter: u64,
+    moving_right: bool,
+    paused: bool,
+    friendly_fire: bool,
+}
+
+// axis_x is in -AXIS_RESOLUTION..=AXIS_RESOLUTION, deflection past the dead zone
+// is rescaled so that full tilt moves as fast as the digital keys
+fn analog_move_diff(axis_x: i32, dead_zone: i32) -> i32 {
+    let dead_zone = dead_zone.clamp(0, AXIS_RESOLUTION - 1);
+    let axis_x = axis_x.clamp(-AXIS_RESOLUTION, AXIS_RESOLUTION);
+    let deflection = axis_x.abs() - dead_zone;
+    if deflection <= 0 {
+        return 0;
+    }
+    let range = AXIS_RESOLUTION - dead_zone;
+    let speed = (deflection * MOVE_SIZE + range - 1) / range;
+    speed * axis_x.signum()
+}
+
+// host reports the pointer on a MULT pixels per playfield pixel canvas, whatever the render scale
+fn canvas_to_playfield_x(canvas_x: i32) -> i32 {
+    (canvas_x / MULT as i32).clamp(0, WIDTH as i32 - 1)
+}
+
+// box from `y` down to the bottom of the screen
+fn screen_box(y: usize) -> text::TextBox {
+    text::TextBox { x: 0, y: y as i32, width: WIDTH, height: HEIGHT + STATUS_BAR_HEIGHT - y }
+}
+
+fn pointer_move_diff(player_pos: i32, target_x: i32) -> i32 {
+    let half_width = assets::PLAYER_BITMAP.width as i32 / 2;
+    let target_x = target_x.clamp(half_width, WIDTH as i32 - half_width);
+    (target_x - player_pos).clamp(-MAX_POINTER_SPEED, MAX_POINTER_SPEED)
+}
+
+struct Game {
+    palette: palette::Palette,
+    palette_staging: [u32; palette::N_WORDS],
+    font: font::Font,
+    font_upload: Vec<u8>,
     random_seed: u32,
     game_state: GameState,
-    player: Player,
+    mode: GameMode,
+    players: [Player; MAX_PLAYERS],
+    commander: Commander,
     enemies: static_allocator::SVector<Enemy>,
     bullets: static_allocator::SVector<Bullet>,
-    buffer: &'a mut [Tile; WIDTH * HEIGHT],
+    particles: particles::Particles,
+    camera: camera::Camera,
+    // shake and hit-stop the simulated ticks asked for since they were last shown
+    pending_shake: i32,
+    pending_hit_stop: u32,
+    // presses that came in during hit-stop, they go in with the first frame after it
+    held_keys: [u32; MAX_PLAYERS],
+    // off unless the host turns it on
+    starfield: Option<starfield::Starfield>,
+    synth: synth::Synth,
+    music: music::Sequencer,
+    music_enabled: bool,
+    // formation steps since the last note of the march and the next note
+    march_steps: u32,
+    march_note: usize,
+    buffer: Box<[Tile; WIDTH * HEIGHT]>,
+    // tiles the last update_buffer painted over, the rest is background or obstacles
+    painted: Vec<canvas::Rect>,
+    framebuffer: Vec<u32>,
+    layers: compositor::Compositor,
+    // framebuffer pixels per playfield pixel, 1 renders at native resolution
+    mult: usize,
+    upscaler: Option<upscale::Upscaler>,
+    // colour of every tile currently on the playfield layer, transparent after a clear
+    drawn_tiles: Vec<u32>,
+    drawn_status_bar: Option<u32>,
+    // playfield pixels particles were drawn on in the last frame
+    drawn_particles: Vec<usize>,
+    // same for the stars on the background layer
+    drawn_stars: Vec<usize>,
+    dirty: dirty::DirtyRegion,
+    events: events::EventQueue,
+    net: Option<Box<netplay::Lockstep<netplay::HostTransport>>>,
     tick_counter: u64,
     moving_right: bool,
     paused: bool,
+    dead_zone: i32,
+    pointer_down: bool,
+    friendly_fire: bool,
+    full_redraw: bool,
+    history: rewind::History<GameSnapshot, [PlayerInput; MAX_PLAYERS]>,
+    // where enemies, bullets and particles live, last so it's dropped after them
+    _memory: Box<static_allocator::StaticAllocator>,
 }
 
-static mut GAMECELL: OnceCell<&mut Game> = OnceCell::new();
-static mut GAMEBUFFER: [Tile; WIDTH * HEIGHT] = [DEFAULT_TILE; WIDTH * HEIGHT];
+// only the handle table is global, every Game owns all of its state
+//
+// Safety contract of every js_* export: they hand out `&'static mut` borrows of
+// a slot here, so they are only sound when called one at a time from the host
+// thread, never from within one another. A stale or unknown `game_id` is
+// ignored. Pointers they return point into the game's own memory and dangle
+// once the game is destroyed. Exports with more to it say so in their own
+// `# Safety` section, the others are only documented here.
+static mut GAMES: [Option<Box<Game>>; MAX_GAMES] = [const { None }; MAX_GAMES];
 
 fn get_key_event(key_event_flags: u32) -> KeyEvent {
     KeyEvent(key_event_flags)
 }
 
+unsafe fn get_game(game_id: u32) -> Option<&'static mut Game> {
+    match (*std::ptr::addr_of_mut!(GAMES)).get_mut(game_id as usize) {
+        Some(Some(game)) => Some(game),
+        _ => None,
+    }
+}
+
+/// Returns the id of the new game, -1 if all slots are taken.
+///
+/// # Safety
+/// The id stays valid until js_game_destroy, after which the slot and its id
+/// are handed out again.
 #[no_mangle]
-pub unsafe extern fn js_game_init() {
-    let game = static_allocator::static_alloc::<Game>();
-    game.default_color = 0xFF_FF_FF_FF;
-    game.game_state = GameState::StartScreen;
-    game.player = Player {
-        pos: (WIDTH as i32)/2, color: 0xFF_00_00_FF, health: MAX_PLAYER_HEALTH,
-        last_shot_in_ticks: 0, opacity: 100, weapon: Weapon::Pistol,
-        rifle_ammo: MAX_RIFLE_AMMO, shotgun_ammo: MAX_SHOTGUN_AMMO,
-        reset_status_bar: false
-    };
-    game.enemies = static_allocator::SVector::new(MAX_ENEMIES);
-    game.bullets = static_allocator::SVector::new(MAX_BULLETS);
-    game.tick_counter = 0;
-    game.moving_right = true;
-    game.paused = false;
-    game.buffer = &mut GAMEBUFFER;
-
-    let _ = GAMECELL.set(game);
+pub unsafe extern "C" fn js_game_create() -> i32 {
+    let games = &mut *std::ptr::addr_of_mut!(GAMES);
+    match games.iter_mut().position(|slot| slot.is_none()) {
+        Some(game_id) => {
+            games[game_id] = Some(Box::new(Game::new()));
+            game_id as i32
+        },
+        None => -1,
+    }
 }
 
+/// # Safety
+/// Every pointer returned for `game_id` dangles afterwards.
 #[no_mangle]
-pub unsafe extern fn js_game_tick(key_event_flags: u32) {
-    if let Some(game) = GAMECELL.get_mut() {
-        let key_event = get_key_event(key_event_flags);
+pub unsafe extern "C" fn js_game_destroy(game_id: u32) {
+    if let Some(slot) = (*std::ptr::addr_of_mut!(GAMES)).get_mut(game_id as usize) {
+        *slot = None;
+    }
+}
 
-        if key_event.pressed_escape() {
-            game.paused = !game.paused;
+/// # Safety
+/// Points at width * height pixels. Valid until the game is destroyed or the
+/// render scale or upscaler changes, both of which reallocate the output.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_framebuffer_ptr(game_id: u32) -> *const u32 {
+    match get_game(game_id) {
+        Some(game) => game.output().0.as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_framebuffer_width(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.output().1 as u32,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_framebuffer_height(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.output().2 as u32,
+        None => 0,
+    }
+}
+
+/// Dirty rectangles are x, y, width, height quadruples in framebuffer pixels,
+/// they pile up over ticks until the host clears them after an upload.
+/// With an upscaler set the host should upload the whole output instead.
+///
+/// # Safety
+/// Points at js_game_dirty_rects_len quadruples, valid until the next call
+/// into the same game.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_dirty_rects_ptr(game_id: u32) -> *const u32 {
+    match get_game(game_id) {
+        Some(game) => game.dirty.rects(game.mult).as_ptr() as *const u32,
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_dirty_rects_len(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.dirty.rects(game.mult).len() as u32,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_dirty_rects_clear(game_id: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.dirty.clear();
+    }
+}
+
+/// Gameplay events since the host last cleared the queue, see events.rs for the
+/// record layout. The host should drain them after every batch of ticks.
+///
+/// # Safety
+/// Points at js_game_events_len bytes of a buffer that lives as long as the
+/// game, only the records below the length are initialised.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_events_ptr(game_id: u32) -> *const u8 {
+    match get_game(game_id) {
+        Some(game) => game.events.bytes().as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_events_len(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.events.bytes().len() as u32,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_events_clear(game_id: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.events.clear();
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn js_palette_theme_count() -> u32 {
+    palette::THEMES.len() as u32
+}
+
+/// Returns 0 if there's no such built-in theme.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_theme(game_id: u32, theme: u32) -> u32 {
+    match (get_game(game_id), palette::THEMES.get(theme as usize)) {
+        (Some(game), Some(palette)) => {
+            game.set_palette(*palette);
+            game.draw();
+            1
+        },
+        _ => 0,
+    }
+}
+
+/// Custom palettes are written by the host as palette::N_WORDS colours in the
+/// order of palette::Palette fields, then applied with js_game_palette_commit.
+/// The words initially hold the active palette. Alpha bytes are ignored, the
+/// colours are always opaque.
+///
+/// # Safety
+/// The host may write palette::N_WORDS words through the pointer until the
+/// game is destroyed, but not while another export runs.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_palette_ptr(game_id: u32) -> *mut u32 {
+    match get_game(game_id) {
+        Some(game) => game.palette_staging.as_mut_ptr(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn js_palette_len() -> u32 {
+    palette::N_WORDS as u32
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_palette_commit(game_id: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.set_palette(palette::Palette::from_words(&game.palette_staging));
+        game.draw();
+    }
+}
+
+/// The host copies a packed font (see font.rs) into the buffer returned here
+/// and then loads it with js_game_font_commit.
+///
+/// # Safety
+/// The host may write exactly `len` bytes through the pointer, it dangles
+/// after js_game_font_commit or the next upload.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_font_upload_ptr(game_id: u32, len: u32) -> *mut u8 {
+    match get_game(game_id) {
+        Some(game) => {
+            game.font_upload = vec![0; len as usize];
+            game.font_upload.as_mut_ptr()
+        },
+        None => std::ptr::null_mut(),
+    }
+}
 
-            if game.paused {
-                game.draw_help_screen(&mut BUFFER);
+/// Returns 0 and keeps the current font if the data isn't a valid font.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_font_commit(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => {
+            let upload = std::mem::take(&mut game.font_upload);
+            match font::Font::load_packed(&upload) {
+                Ok(font) => {
+                    game.set_font(font);
+                    1
+                },
+                Err(_) => 0,
             }
+        },
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_font_reset(game_id: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.set_font(font::Font::builtin());
+    }
+}
+
+/// 1 renders the playfield at native resolution, MULT is the classic look.
+///
+/// # Safety
+/// Reallocates the framebuffer, pointers from js_game_framebuffer_ptr dangle.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_render_scale(game_id: u32, mult: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.set_render_scale(mult as usize);
+    }
+}
+
+/// Mode 0 leaves scaling to the host, see upscale::Mode for the others.
+///
+/// # Safety
+/// Reallocates the output, pointers from js_game_framebuffer_ptr dangle.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_upscale(game_id: u32, mode: u32, width: u32, height: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.upscaler = upscale::Mode::from_u32(mode)
+            .map(|mode| upscale::Upscaler::new(mode, width as usize, height as usize));
+        game.draw();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_tick(game_id: u32, key_event_flags: u32, key_event_flags_p2: u32) {
+    if let Some(game) = get_game(game_id) {
+        let inputs = [PlayerInput::from_keys(key_event_flags), PlayerInput::from_keys(key_event_flags_p2)];
+        game.step(inputs);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_tick_analog(game_id: u32, key_event_flags: u32, axis_x: f32, key_event_flags_p2: u32) {
+    if let Some(game) = get_game(game_id) {
+        let mut input = PlayerInput::from_keys(key_event_flags);
+        let axis_x = (axis_x * AXIS_RESOLUTION as f32) as i32;
+        let analog_diff = analog_move_diff(axis_x, game.dead_zone);
+        // d-pad is reported through the key flags
+        if analog_diff != 0 {
+            input.move_diff = analog_diff;
         }
-        if game.paused { return };
+        let inputs = [input, PlayerInput::from_keys(key_event_flags_p2)];
+        game.step(inputs);
+    }
+}
 
-        match game.game_state {
-            GameState::StartScreen => {
-                game.draw_start_screen(&mut BUFFER);
-                if key_event.pressed_space() {
-                    game.reset_level();
-                    BUFFER.fill(0xFF_FF_FF_FF);
-                    game.game_state = GameState::Playing;
-                }
-            },
-            GameState::Playing => {
-                game.tick(key_event);
-                game.update_buffer();
-                game.render(&mut BUFFER);
-            },
-            GameState::EndScreen(has_won) => {
-                game.draw_end_screen(has_won, &mut BUFFER);
-                if key_event.pressed_space() {
-                    game.game_state = GameState::StartScreen;
-                }
-            },
-            
+#[no_mangle]
+pub unsafe extern "C" fn js_game_tick_pointer(game_id: u32, key_event_flags: u32, canvas_x: i32, touching: u32, key_event_flags_p2: u32) {
+    if let Some(game) = get_game(game_id) {
+        let touching = touching != 0;
+        let mut key_event_flags = key_event_flags;
+        // keep firing while touching, but menus only react to a new touch
+        if touching && (game.game_state == GameState::Playing || !game.pointer_down) {
+            key_event_flags |= 4;
+        }
+        game.pointer_down = touching;
+
+        let mut input = PlayerInput::from_keys(key_event_flags);
+        if touching {
+            input.move_diff = pointer_move_diff(game.players[0].pos, canvas_to_playfield_x(canvas_x));
+        }
+        let inputs = [input, PlayerInput::from_keys(key_event_flags_p2)];
+        game.step(inputs);
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_friendly_fire(game_id: u32, enabled: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.friendly_fire = enabled != 0;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_screen_shake(game_id: u32, enabled: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.camera.shake_enabled = enabled != 0;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_hit_stop(game_id: u32, enabled: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.camera.hit_stop_enabled = enabled != 0;
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_starfield(game_id: u32, enabled: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.set_starfield(enabled != 0);
+        game.draw();
+    }
+}
+
+/// Sound is 16 bit mono PCM at js_audio_sample_rate in a ring of js_audio_ring_len
+/// samples. The host keeps its own read position and after every tick copies
+/// what's new up to js_game_audio_written, a count that wraps around.
+#[no_mangle]
+pub extern "C" fn js_audio_sample_rate() -> u32 {
+    synth::SAMPLE_RATE
+}
+
+#[no_mangle]
+pub extern "C" fn js_audio_ring_len() -> u32 {
+    synth::RING_LEN as u32
+}
+
+/// # Safety
+/// Points at js_audio_ring_len samples that live as long as the game.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_audio_ptr(game_id: u32) -> *const i16 {
+    match get_game(game_id) {
+        Some(game) => game.synth.ring().as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_audio_written(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.synth.written(),
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_music(game_id: u32, enabled: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.music_enabled = enabled != 0;
+        if !game.music_enabled {
+            game.music.stop(&mut game.synth);
+        }
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_set_dead_zone(game_id: u32, dead_zone: f32) {
+    if let Some(game) = get_game(game_id) {
+        game.dead_zone = ((dead_zone * AXIS_RESOLUTION as f32) as i32).clamp(0, AXIS_RESOLUTION - 1);
+    }
+}
+
+/// Returns how many frames were rewound, always 0 during a net session.
+#[no_mangle]
+pub unsafe extern "C" fn js_game_rewind(game_id: u32, frames: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => {
+            let rewound = game.rewind(frames);
+            game.draw();
+            rewound
+        },
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_game_checksum(game_id: u32) -> u32 {
+    match get_game(game_id) {
+        Some(game) => game.checksum(),
+        None => 0,
+    }
+}
+
+/// # Safety
+/// Replaces the transport, pointers from the js_net_* buffer exports dangle.
+#[no_mangle]
+pub unsafe extern "C" fn js_net_start(game_id: u32, local_player: u32, input_delay: u32) {
+    if let Some(game) = get_game(game_id) {
+        let transport = netplay::HostTransport::new();
+        game.start_net(Box::new(netplay::Lockstep::new(transport, local_player as usize, input_delay)));
+        game.draw();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_net_tick(game_id: u32, key_event_flags: u32) {
+    if let Some(game) = get_game(game_id) {
+        game.net_tick(key_event_flags);
+    }
+}
+
+unsafe fn get_net(game_id: u32) -> Option<&'static mut netplay::Lockstep<netplay::HostTransport>> {
+    get_game(game_id).and_then(|game| game.net.as_deref_mut())
+}
+
+/// # Safety
+/// The host may write up to js_net_inbox_free_len bytes through the pointer
+/// before js_net_inbox_commit, any other js_net_* call can move the free space.
+#[no_mangle]
+pub unsafe extern "C" fn js_net_inbox_ptr(game_id: u32) -> *mut u8 {
+    match get_net(game_id) {
+        Some(net) => net.transport_mut().inbox_free_ptr(),
+        None => std::ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_net_inbox_free_len(game_id: u32) -> u32 {
+    match get_net(game_id) {
+        Some(net) => net.transport_mut().inbox_free_len() as u32,
+        None => 0,
+    }
+}
+
+/// # Safety
+/// `len` must not count more bytes than the host wrote through js_net_inbox_ptr.
+#[no_mangle]
+pub unsafe extern "C" fn js_net_inbox_commit(game_id: u32, len: u32) {
+    if let Some(net) = get_net(game_id) {
+        net.transport_mut().commit_inbox(len as usize);
+    }
+}
+
+/// # Safety
+/// Points at js_net_outbox_len bytes, valid until the next js_net_* call.
+#[no_mangle]
+pub unsafe extern "C" fn js_net_outbox_ptr(game_id: u32) -> *const u8 {
+    match get_net(game_id) {
+        Some(net) => net.transport_mut().outbox().as_ptr(),
+        None => std::ptr::null(),
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_net_outbox_len(game_id: u32) -> u32 {
+    match get_net(game_id) {
+        Some(net) => net.transport_mut().outbox().len() as u32,
+        None => 0,
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_net_outbox_clear(game_id: u32) {
+    if let Some(net) = get_net(game_id) {
+        net.transport_mut().clear_outbox();
+    }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn js_net_current_tick(game_id: u32) -> u32 {
+    match get_net(game_id) {
+        Some(net) => net.current_tick(),
+        None => 0,
+    }
+}
+
+/// Tick of the first mismatching checksum, -1 while both peers agree.
+#[no_mangle]
+pub unsafe extern "C" fn js_net_desync_tick(game_id: u32) -> i32 {
+    match get_net(game_id).and_then(|net| net.desync_tick()) {
+        Some(tick) => tick as i32,
+        None => -1,
+    }
+}
+
+struct Fnv1a(u32);
+
+impl Fnv1a {
+    fn new() -> Fnv1a {
+        Fnv1a(0x811C9DC5)
+    }
+
+    fn write_u32(&mut self, val: u32) {
+        for byte in val.to_le_bytes() {
+            self.0 ^= byte as u32;
+            self.0 = self.0.wrapping_mul(0x01000193);
         }
     }
 }
@@ -252,12 +921,511 @@ fn stringify_u32(val: u32, s: &mut [char]) -> usize {
     return str_len;
 }
 
-impl Game<'_> {
+impl Game {
+    fn new() -> Game {
+        let mut memory = Box::new(static_allocator::StaticAllocator::new());
+        // SAFETY: the vectors point into the heap block behind `memory`, which
+        // stays put when the box moves into the game, and `_memory` is the
+        // last field of Game so it is dropped after enemies, bullets and particles
+        let (enemies, bullets, particles) = unsafe {
+            (
+                static_allocator::SVector::new(&mut memory, MAX_ENEMIES),
+                static_allocator::SVector::new(&mut memory, MAX_BULLETS),
+                particles::Particles::new(&mut memory, MAX_PARTICLES),
+            )
+        };
+        Game {
+            palette: palette::CLASSIC,
+            palette_staging: palette::CLASSIC.to_words(),
+            font: font::Font::builtin(),
+            font_upload: Vec::new(),
+            random_seed: 0,
+            game_state: GameState::StartScreen,
+            mode: GameMode::Single,
+            players: [Player::new(0), Player::new(1)],
+            commander: Commander::new(),
+            enemies,
+            bullets,
+            particles,
+            camera: camera::Camera::new(),
+            pending_shake: 0,
+            pending_hit_stop: 0,
+            held_keys: [0; MAX_PLAYERS],
+            starfield: None,
+            synth: synth::Synth::new(),
+            // build.rs refuses to build with a broken music.trk
+            music: music::Sequencer::new(music::Songbook::parse(include_str!("../assets/music.trk")).expect("assets/music.trk is valid")),
+            music_enabled: true,
+            march_steps: 0,
+            march_note: 0,
+            buffer: Box::new([DEFAULT_TILE; WIDTH * HEIGHT]),
+            painted: Vec::new(),
+            framebuffer: vec![0; BUFFER_SIZE],
+            layers: compositor::Compositor::new(WIDTH, HEIGHT + STATUS_BAR_HEIGHT, MULT),
+            mult: MULT,
+            upscaler: None,
+            drawn_tiles: vec![0; WIDTH * HEIGHT],
+            drawn_status_bar: None,
+            drawn_particles: Vec::new(),
+            drawn_stars: Vec::new(),
+            dirty: dirty::DirtyRegion::new(WIDTH, HEIGHT + STATUS_BAR_HEIGHT),
+            events: events::EventQueue::new(),
+            net: None,
+            tick_counter: 0,
+            moving_right: true,
+            paused: false,
+            dead_zone: DEFAULT_DEAD_ZONE,
+            pointer_down: false,
+            friendly_fire: false,
+            full_redraw: true,
+            history: rewind::History::new(),
+            _memory: memory,
+        }
+    }
+}
+
+impl Game {
+    fn step(&mut self, mut inputs: [PlayerInput; MAX_PLAYERS]) {
+        // hit-stop holds local play on the same moment, only presses are kept meanwhile
+        if self.camera.hold_frame() {
+            for (held, input) in self.held_keys.iter_mut().zip(inputs.iter()) {
+                *held |= input.key_event.0 & LATCHED_KEYS;
+            }
+        } else {
+            for (held, input) in self.held_keys.iter_mut().zip(inputs.iter_mut()) {
+                input.key_event.0 |= std::mem::take(held);
+            }
+            self.advance(&inputs);
+            self.camera.hit_stop(std::mem::take(&mut self.pending_hit_stop));
+        }
+        self.render_audio();
+        self.draw();
+    }
+
+    // Both peers begin the session on the start screen of a new game, whatever
+    // they played before. Friendly fire stays as the host set it, the peers have
+    // to agree on it like on the input delay.
+    fn start_net(&mut self, net: Box<netplay::Lockstep<netplay::HostTransport>>) {
+        let new_game = GameSnapshot {
+            random_seed: 0,
+            game_state: GameState::StartScreen,
+            mode: GameMode::Single,
+            players: [Player::new(0), Player::new(1)],
+            commander: Commander::new(),
+            enemies: [NO_ENEMY; MAX_ENEMIES],
+            n_enemies: 0,
+            bullets: [NO_BULLET; MAX_BULLETS],
+            n_bullets: 0,
+            obstacles: [0; OBSTACLE_MAP_SIZE],
+            tick_counter: 0,
+            moving_right: true,
+            paused: false,
+            friendly_fire: self.friendly_fire,
+        };
+        self.restore(&new_game);
+        self.history = rewind::History::new();
+        self.events.clear();
+        self.particles.clear();
+        self.camera.reset();
+        self.pending_shake = 0;
+        self.pending_hit_stop = 0;
+        self.held_keys = [0; MAX_PLAYERS];
+        self.full_redraw = true;
+        self.net = Some(net);
+    }
+
+    fn net_tick(&mut self, key_event_flags: u32) {
+        // session is moved out while it drives the simulation
+        let mut net = match self.net.take() {
+            Some(net) => net,
+            None => return,
+        };
+        net.add_local_input(key_event_flags);
+        let mut n_ticks = 0;
+        while n_ticks < MAX_NET_CATCHUP_TICKS {
+            if let Some((tick, flags)) = net.next_inputs() {
+                let inputs = [PlayerInput::from_keys(flags[0]), PlayerInput::from_keys(flags[1])];
+                self.simulate(&inputs);
+                net.report_checksum(tick, self.checksum());
+                n_ticks += 1;
+            } else {
+                break;
+            }
+        }
+        self.net = Some(net);
+        // holding the frame would put the local game behind the session
+        self.pending_hit_stop = 0;
+        self.render_audio();
+        if n_ticks > 0 {
+            self.draw();
+        }
+    }
+
+    // simulates a frame and keeps it in the rewind history
+    fn advance(&mut self, inputs: &[PlayerInput; MAX_PLAYERS]) {
+        if self.history.needs_snapshot() {
+            let snapshot = self.snapshot();
+            self.history.save_snapshot(snapshot);
+        }
+        self.history.record(*inputs);
+        self.simulate(inputs);
+    }
+
+    // goes back by up to `frames`, returns how many frames were actually rewound
+    fn rewind(&mut self, frames: u32) -> u32 {
+        // the peer would play on from where we left, and net_tick keeps no history anyway
+        if self.net.is_some() {
+            return 0;
+        }
+        let current_frame = self.history.frame();
+        let frames = frames.min(current_frame).min(rewind::MAX_REWIND_FRAMES);
+        let target = current_frame - frames;
+        let (snapshot_frame, snapshot) = match self.history.snapshot_before(target) {
+            Some(x) => x,
+            None => return 0,
+        };
+        self.restore(&snapshot);
+        // the host has seen the replayed frames' events already
+        let n_event_bytes = self.events.bytes().len();
+        for frame in snapshot_frame..target {
+            if let Some(inputs) = self.history.input(frame) {
+                self.simulate(&inputs);
+            }
+        }
+        self.events.truncate(n_event_bytes);
+        self.history.set_frame(target);
+        // the replayed frames' particles, shakes and sounds would be out of place
+        self.particles.clear();
+        self.camera.reset();
+        self.pending_shake = 0;
+        self.pending_hit_stop = 0;
+        self.held_keys = [0; MAX_PLAYERS];
+        self.synth.stop_all();
+        self.full_redraw = true;
+        frames
+    }
+
+    fn snapshot(&self) -> GameSnapshot {
+        let mut enemies = [NO_ENEMY; MAX_ENEMIES];
+        for (dst, src) in enemies.iter_mut().zip(self.enemies.iter()) {
+            *dst = *src;
+        }
+        let mut bullets = [NO_BULLET; MAX_BULLETS];
+        for (dst, src) in bullets.iter_mut().zip(self.bullets.iter()) {
+            *dst = *src;
+        }
+        let mut obstacles = [0; OBSTACLE_MAP_SIZE];
+        for (idx, tile) in self.buffer.iter().enumerate() {
+            if *tile == Tile::Obstacle {
+                if let Some(x) = obstacles.get_mut(idx / 64) {
+                    *x |= 1 << (idx % 64);
+                }
+            }
+        }
+        GameSnapshot {
+            random_seed: self.random_seed,
+            game_state: self.game_state,
+            mode: self.mode,
+            players: self.players,
+            commander: self.commander,
+            enemies,
+            n_enemies: self.enemies.size(),
+            bullets,
+            n_bullets: self.bullets.size(),
+            obstacles,
+            tick_counter: self.tick_counter,
+            moving_right: self.moving_right,
+            paused: self.paused,
+            friendly_fire: self.friendly_fire,
+        }
+    }
+
+    fn restore(&mut self, snapshot: &GameSnapshot) {
+        self.random_seed = snapshot.random_seed;
+        self.game_state = snapshot.game_state;
+        self.mode = snapshot.mode;
+        self.players = snapshot.players;
+        self.commander = snapshot.commander;
+        self.enemies.reset();
+        for enemy in snapshot.enemies.iter().take(snapshot.n_enemies) {
+            self.enemies.push_back(*enemy);
+        }
+        self.bullets.reset();
+        for bullet in snapshot.bullets.iter().take(snapshot.n_bullets) {
+            self.bullets.push_back(*bullet);
+        }
+        self.tick_counter = snapshot.tick_counter;
+        self.moving_right = snapshot.moving_right;
+        self.paused = snapshot.paused;
+        self.friendly_fire = snapshot.friendly_fire;
+
+        for (idx, tile) in self.buffer.iter_mut().enumerate() {
+            let is_obstacle = match snapshot.obstacles.get(idx / 64) {
+                Some(x) => x & (1 << (idx % 64)) != 0,
+                None => false,
+            };
+            *tile = if is_obstacle { Tile::Obstacle } else { Tile::Background };
+        }
+        // only for display until the next tick rebuilds the map
+        self.painted = vec![canvas::Rect::new(0, 0, WIDTH as i32, HEIGHT as i32)];
+        for player in self.players.iter().take(self.mode.n_players()) {
+            if player.is_alive() {
+                player.update(&mut self.buffer);
+            }
+        }
+        for enemy in self.enemies.iter() {
+            enemy.update(&mut self.buffer);
+        }
+        for bullet in self.bullets.iter() {
+            if let Some(x) = self.buffer.get_mut(bullet.y as usize * WIDTH + bullet.x as usize) {
+                if *x == Tile::Background {
+                    *x = Tile::Bullet;
+                }
+            }
+        }
+    }
+
+    // everything that affects the game outcome, must stay deterministic for netplay
+    fn simulate(&mut self, inputs: &[PlayerInput; MAX_PLAYERS]) {
+        let pressed_escape = inputs.iter().any(|input| input.key_event.pressed_escape());
+        let pressed_space = inputs.iter().any(|input| input.key_event.pressed_space());
+        let pressed_ctrl = inputs.iter().any(|input| input.key_event.pressed_ctrl());
+
+        if pressed_escape {
+            self.paused = !self.paused;
+            self.full_redraw = true;
+        }
+        if self.paused { return };
+
+        match self.game_state {
+            GameState::StartScreen => {
+                if pressed_ctrl {
+                    self.mode = self.mode.next();
+                }
+                if pressed_space {
+                    self.reset_level();
+                    self.game_state = GameState::Playing;
+                    self.full_redraw = true;
+                }
+            },
+            GameState::Playing => {
+                self.particles.update();
+                self.tick(inputs);
+                self.update_buffer();
+                if let GameState::EndScreen(won) = self.game_state {
+                    self.events.push(self.tick_counter as u32, &events::Event::GameOver { won });
+                }
+            },
+            GameState::EndScreen(_) => {
+                if pressed_space {
+                    self.game_state = GameState::StartScreen;
+                }
+            },
+        }
+    }
+
+    // a tick of sound, the music follows the screen the game is on
+    fn render_audio(&mut self) {
+        let track = match self.game_state {
+            GameState::StartScreen => START_TRACK,
+            GameState::Playing if self.mode == GameMode::Versus || self.enemies.size() == 1 => BOSS_TRACK,
+            GameState::Playing => GAME_TRACK,
+            GameState::EndScreen(_) => GAME_OVER_TRACK,
+        };
+        if self.music_enabled && self.music.playing() != Some(track) {
+            self.music.play(track, &mut self.synth);
+        }
+        // the boss song is fast enough as it is
+        let speedup = match track {
+            GAME_TRACK => MAX_ENEMIES.saturating_sub(self.enemies.size()) as u32 * MUSIC_MAX_SPEEDUP / MAX_ENEMIES as u32,
+            _ => 0,
+        };
+        self.music.set_tempo_percent(100 + speedup);
+        self.music.render(&mut self.synth, SAMPLES_PER_TICK);
+    }
+
+    fn draw(&mut self) {
+        self.camera.shake(std::mem::take(&mut self.pending_shake));
+        // rendering borrows the game, so the layers are moved out meanwhile
+        let mut layers = std::mem::take(&mut self.layers);
+        self.draw_layers(&mut layers);
+        layers.compose(&mut self.framebuffer, self.dirty.take_frame_rects(self.mult));
+        self.layers = layers;
+        if let Some(upscaler) = self.upscaler.as_mut() {
+            upscaler.run(&self.framebuffer, WIDTH * self.mult, (HEIGHT + STATUS_BAR_HEIGHT) * self.mult);
+        }
+    }
+
+    fn set_render_scale(&mut self, mult: usize) {
+        self.mult = mult.clamp(1, MULT);
+        self.framebuffer = vec![0; WIDTH * self.mult * (HEIGHT + STATUS_BAR_HEIGHT) * self.mult];
+        self.layers = compositor::Compositor::new(WIDTH, HEIGHT + STATUS_BAR_HEIGHT, self.mult);
+        self.full_redraw = true;
+        for player in self.players.iter_mut() {
+            player.reset_status_bar = true;
+        }
+        self.draw();
+    }
+
+    // what the host should display, upscaled if an upscaler is set
+    fn output(&self) -> (&[u32], usize, usize) {
+        match &self.upscaler {
+            Some(upscaler) => (upscaler.pixels(), upscaler.width(), upscaler.height()),
+            None => (&self.framebuffer, WIDTH * self.mult, (HEIGHT + STATUS_BAR_HEIGHT) * self.mult),
+        }
+    }
+
+    fn draw_layers(&mut self, layers: &mut compositor::Compositor) {
+        let full_redraw = std::mem::replace(&mut self.full_redraw, false);
+        if self.paused && !full_redraw {
+            // nothing moves while paused
+            return;
+        }
+        if !self.paused {
+            layers.clear(compositor::Layer::Overlay);
+        }
+        let offset = match self.game_state {
+            GameState::Playing => self.camera.next_offset(self.mult),
+            _ => (0, 0),
+        };
+        if layers.set_camera(offset, HEIGHT * self.mult) {
+            self.dirty.mark_rows(0, HEIGHT);
+        }
+
+        match self.game_state {
+            GameState::StartScreen => {
+                self.clear_game_layers(layers);
+                self.draw_start_screen(&mut layers.canvas(compositor::Layer::Background));
+                self.dirty.mark_all();
+            },
+            GameState::Playing => {
+                if full_redraw {
+                    self.clear_game_layers(layers);
+                    self.draw_background(&mut layers.canvas(compositor::Layer::Background));
+                    self.drawn_tiles.fill(compositor::TRANSPARENT);
+                    self.drawn_status_bar = None;
+                    self.drawn_particles.clear();
+                    self.drawn_stars.clear();
+                    self.dirty.mark_all();
+                }
+                self.render_starfield(layers);
+                self.render(layers);
+                self.render_particles(layers);
+            },
+            GameState::EndScreen(has_won) => {
+                self.clear_game_layers(layers);
+                self.draw_end_screen(has_won, &mut layers.canvas(compositor::Layer::Background));
+                self.dirty.mark_all();
+            },
+        }
+
+        if self.paused {
+            // the frozen frame stays visible, dimmed, behind the help screen
+            self.draw_help_screen(&mut layers.canvas(compositor::Layer::Overlay));
+            self.dirty.mark_all();
+        }
+    }
+
+    fn clear_game_layers(&self, layers: &mut compositor::Compositor) {
+        for layer in [compositor::Layer::Playfield, compositor::Layer::Effects, compositor::Layer::Hud] {
+            layers.clear(layer);
+        }
+    }
+
+    // Hash of the whole simulation state. Values are fed in a fixed order and
+    // little endian, so it's the same on every platform and build.
+    fn checksum(&self) -> u32 {
+        let mut hash = Fnv1a::new();
+        hash.write_u32(self.tick_counter as u32);
+        hash.write_u32((self.tick_counter >> 32) as u32);
+        hash.write_u32(self.random_seed);
+        hash.write_u32(match self.game_state {
+            GameState::StartScreen => 0,
+            GameState::Playing => 1,
+            GameState::EndScreen(false) => 2,
+            GameState::EndScreen(true) => 3,
+        });
+        hash.write_u32(match self.mode {
+            GameMode::Single => 0,
+            GameMode::Coop => 1,
+            GameMode::Versus => 2,
+        });
+        hash.write_u32(u32::from_le_bytes([self.moving_right as u8, self.paused as u8, self.friendly_fire as u8, 0]));
+
+        for player in self.players.iter() {
+            hash.write_u32(player.pos as u32);
+            hash.write_u32(player.health as u32);
+            hash.write_u32(player.last_shot_in_ticks);
+            hash.write_u32(player.opacity);
+            hash.write_u32(match player.weapon {
+                Weapon::Pistol => 0,
+                Weapon::Rifle => 1,
+                Weapon::Shotgun => 2,
+            });
+            hash.write_u32(player.rifle_ammo as u32);
+            hash.write_u32(player.shotgun_ammo as u32);
+            hash.write_u32(player.score);
+        }
+
+        hash.write_u32(self.commander.selected_enemy as u32);
+        hash.write_u32(self.commander.last_shot_in_ticks);
+        hash.write_u32(self.commander.special_cooldown);
+        hash.write_u32(self.commander.formation_blocked as u32);
+
+        hash.write_u32(self.enemies.size() as u32);
+        for enemy in self.enemies.iter() {
+            hash.write_u32(u32::from_le_bytes([enemy.x, enemy.y, enemy.health as u8, enemy.max_health]));
+        }
+
+        hash.write_u32(self.bullets.size() as u32);
+        for bullet in self.bullets.iter() {
+            let (status, hit_player) = match bullet.status {
+                BulletStatus::Alive => (0, 0),
+                BulletStatus::HitPlayer(player_idx) => (1, player_idx),
+                BulletStatus::HitEnemy => (2, 0),
+                BulletStatus::HitObstacle => (3, 0),
+                BulletStatus::HitBunker => (4, 0),
+            };
+            let owner = match bullet.owner {
+                BulletOwner::Enemy => 0xFF,
+                BulletOwner::Player(player_idx) => player_idx,
+            };
+            hash.write_u32(u32::from_le_bytes([bullet.x, bullet.y, bullet.speed as u8, bullet.damage]));
+            hash.write_u32(u32::from_le_bytes([status, hit_player, owner, 0]));
+        }
+
+        // everything else on the tile map is redrawn from the objects above
+        for (idx, tile) in self.buffer.iter().enumerate() {
+            if *tile == Tile::Obstacle {
+                hash.write_u32(idx as u32);
+            }
+        }
+        hash.0
+    }
+
+    fn active_players(&self) -> &[Player] {
+        self.players.get(..self.mode.n_players()).unwrap_or(&[])
+    }
+
+    fn active_players_mut(&mut self) -> &mut [Player] {
+        let n_players = self.mode.n_players();
+        self.players.get_mut(..n_players).unwrap_or(&mut [])
+    }
+
     fn reset_level(&mut self) {
-        self.player.reset();
+        let n_players = self.mode.n_players();
+        for (idx, player) in self.active_players_mut().iter_mut().enumerate() {
+            let pos = WIDTH * (idx + 1) / (n_players + 1);
+            player.reset(pos as i32);
+        }
         self.enemies.reset();
         self.bullets.reset();
+        self.particles.clear();
+        self.camera.reset();
+        self.march_steps = 0;
+        self.march_note = 0;
         self.moving_right = true;
+        self.commander = Commander::new();
         self.tick_counter = 0;
 
         let enemy_step = 20;
@@ -272,6 +1440,7 @@ impl Game<'_> {
         }
 
         self.buffer.fill(Tile::Background);
+        self.painted.clear();
         self.buffer_fill_obstacles();
     }
 
@@ -294,28 +1463,59 @@ impl Game<'_> {
         }
     }
 
-    fn tick(&mut self, key_event: KeyEvent) {
-        const MOVE_SIZE: i32 = MULT as i32;
-
-        let player_move_diff = if key_event.pressed_left() { -1 }
-        else if key_event.pressed_right() { 1 }
-        else {0};
-
+    fn tick(&mut self, inputs: &[PlayerInput; MAX_PLAYERS]) {
         self.tick_counter = self.tick_counter.wrapping_add(1);
-        self.player.tick();
-        self.player.try_move(player_move_diff * MOVE_SIZE);
-        if key_event.pressed_ctrl() {
-            self.player.change_weapon();
+        let n_players = self.mode.n_players();
+        let first_new_bullet = self.bullets.size();
+        for (player, input) in self.players.iter_mut().zip(inputs.iter()).take(n_players) {
+            player.tick();
+            if !player.is_alive() {
+                continue;
+            }
+            player.try_move(input.move_diff);
+            if input.key_event.pressed_ctrl() {
+                player.change_weapon();
+                // there are no pickups yet, switching weapons sounds like one
+                self.synth.play(&PICKUP_SOUND);
+                self.events.push(self.tick_counter as u32, &events::Event::WeaponChanged { player: player.id, weapon: player.weapon as u32 });
+            }
+            if input.key_event.pressed_space() {
+                let n_bullets = self.bullets.size();
+                player.try_shoot(&mut self.bullets);
+                for idx in n_bullets..self.bullets.size() {
+                    if let Some(bullet) = self.bullets.get(idx) {
+                        self.particles.emit(bullet.x as i32, bullet.y as i32, &MUZZLE_FLASH);
+                    }
+                }
+                if self.bullets.size() > n_bullets {
+                    self.synth.play(&SHOT_SOUND);
+                    let ammo = match player.weapon {
+                        // never runs out
+                        Weapon::Pistol => 1,
+                        Weapon::Rifle => player.rifle_ammo,
+                        Weapon::Shotgun => player.shotgun_ammo,
+                    };
+                    if ammo == 0 {
+                        self.events.push(self.tick_counter as u32, &events::Event::AmmoDepleted { player: player.id, weapon: player.weapon as u32 });
+                    }
+                }
+            }
         }
-        if key_event.pressed_space() {
-            self.player.try_shoot(&mut self.bullets);
+        if self.friendly_fire {
+            self.shoot_overlapping_ships(first_new_bullet);
         }
-        let shooting_enemy_idx = self.get_random_u32() % (MAX_ENEMIES as u32 * 4);
-        if let Some(enemy) = self.enemies.get(shooting_enemy_idx as usize) {
-            self.bullets.push_back(Bullet { x: enemy.x, y: enemy.y + (1 + ENEMY_BITMAP.height/2) as u8, speed: 1, damage: 1, status: BulletStatus::Alive });
+        if self.mode == GameMode::Versus {
+            if let Some(input) = inputs.get(1) {
+                self.command_invaders(input);
+            }
+        } else {
+            let shooting_enemy_idx = self.get_random_u32() % (MAX_ENEMIES as u32 * 4);
+            self.enemy_shoot(shooting_enemy_idx as usize);
         }
 
-        let enemy_mov_horz: u8 = if self.tick_counter % ENEMY_SPEED_FREQ_IN_TICKS == 0 { 1 } else { 0 };
+        let formation_moves = !(self.mode == GameMode::Versus && self.commander.formation_blocked);
+        // a pixel every tick
+        let enemy_mov_horz: u8 = if formation_moves { 1 } else { 0 };
         let mut enemy_idx = 0;
         let mut head_x = WIDTH as u8 / 2;
         while let Some(enemy) = self.enemies.get_mut(enemy_idx) {
@@ -332,9 +1532,24 @@ impl Game<'_> {
             }
             enemy_idx += 1;
         }
-        if (self.moving_right && head_x > (WIDTH as u8 - 20)) ||
-           (!self.moving_right && head_x < (20)) {
-            self.moving_right = !self.moving_right;
+        if enemy_mov_horz > 0 && self.enemies.size() > 0 {
+            self.march_steps += 1;
+            if self.march_steps >= MARCH_MIN_STEPS + 2 * self.enemies.size() as u32 {
+                self.march_steps = 0;
+                if let Some(freq) = MARCH_NOTES.get(self.march_note) {
+                    self.synth.play(&synth::Effect { freq: (*freq, *freq), ..MARCH_SOUND });
+                }
+                self.march_note = (self.march_note + 1) % MARCH_NOTES.len();
+            }
+        }
+        if formation_moves && ((self.moving_right && head_x > (WIDTH as u8 - 20)) ||
+           (!self.moving_right && head_x < (20))) {
+            if self.mode == GameMode::Versus {
+                // wait at the edge until player 2 steers back
+                self.commander.formation_blocked = true;
+            } else {
+                self.moving_right = !self.moving_right;
+            }
 
             let mut head_y = 0;
             enemy_idx = 0;
@@ -346,7 +1561,7 @@ impl Game<'_> {
                 enemy_idx += 1;
             }
 
-            if head_y >= HEIGHT as u8 - PLAYER_BITMAP.height as u8 {
+            if head_y >= HEIGHT as u8 - assets::PLAYER_BITMAP.height as u8 {
                 // YOU LOSE!
                 self.game_state = GameState::EndScreen(false);
             }
@@ -358,43 +1573,189 @@ impl Game<'_> {
         }
     }
 
+    fn enemy_shoot(&mut self, enemy_idx: usize) {
+        if let Some(enemy) = self.enemies.get(enemy_idx) {
+            let y = enemy.y + (1 + assets::ENEMY_BITMAP.height/2) as u8;
+            self.bullets.push_back(Bullet {
+                x: enemy.x, y,
+                speed: 1, damage: 1, status: BulletStatus::Alive, owner: BulletOwner::Enemy
+            });
+            let flash = particles::Burst { velocity: (0, SUBPIXELS), ..MUZZLE_FLASH };
+            self.particles.emit(enemy.x as i32, y as i32, &flash);
+            self.synth.play(&ENEMY_SHOT_SOUND);
+        }
+    }
+
+    fn command_invaders(&mut self, input: &PlayerInput) {
+        let key_event = &input.key_event;
+        if key_event.pressed_left() && self.moving_right {
+            self.moving_right = false;
+            self.commander.formation_blocked = false;
+        } else if key_event.pressed_right() && !self.moving_right {
+            self.moving_right = true;
+            self.commander.formation_blocked = false;
+        }
+
+        let n_enemies = self.enemies.size();
+        if n_enemies == 0 {
+            return;
+        }
+        self.commander.selected_enemy %= n_enemies;
+        if key_event.pressed_ctrl() {
+            self.commander.selected_enemy = (self.commander.selected_enemy + 1) % n_enemies;
+        }
+
+        self.commander.last_shot_in_ticks = self.commander.last_shot_in_ticks.saturating_add(1);
+        if key_event.pressed_space() && self.commander.last_shot_in_ticks >= COMMANDER_SHOT_COOLDOWN_IN_TICKS {
+            self.commander.last_shot_in_ticks = 0;
+            self.enemy_shoot(self.commander.selected_enemy);
+        }
+
+        if self.commander.special_cooldown > 0 {
+            self.commander.special_cooldown -= 1;
+            self.commander.reset_status_bar = self.commander.special_cooldown == 0;
+        } else {
+            self.commander.reset_status_bar = false;
+            if key_event.pressed_special() {
+                // whole formation opens fire at once
+                for enemy_idx in 0..n_enemies {
+                    self.enemy_shoot(enemy_idx);
+                }
+                self.commander.special_cooldown = COMMANDER_SPECIAL_COOLDOWN_IN_TICKS;
+                self.commander.reset_status_bar = true;
+            }
+        }
+    }
+
+    // only the tiles painted last tick go back to background, obstacles stay
+    fn clear_painted(&mut self) {
+        for rect in self.painted.drain(..) {
+            let (x0, x1) = (rect.x.max(0) as usize, (rect.x + rect.width).clamp(0, WIDTH as i32) as usize);
+            let (y0, y1) = (rect.y.max(0) as usize, (rect.y + rect.height).clamp(0, HEIGHT as i32) as usize);
+            for y in y0..y1 {
+                if let Some(row) = self.buffer.get_mut(y * WIDTH + x0..y * WIDTH + x1.max(x0)) {
+                    for it in row.iter_mut() {
+                        if *it != Tile::Obstacle {
+                            *it = Tile::Background
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // Player bullets start above the ships and only fly up, so a ship can only be
+    // hit by the other player firing while the two overlap.
+    fn shoot_overlapping_ships(&mut self, first_bullet: usize) {
+        let n_players = self.mode.n_players();
+        let mut idx = first_bullet;
+        while let Some(bullet) = self.bullets.get_mut(idx) {
+            if let BulletOwner::Player(owner) = bullet.owner {
+                let target = self.players.iter().take(n_players).find(|player| {
+                    let rect = player.rect();
+                    player.id != owner && player.is_alive() && (rect.x..rect.x + rect.width).contains(&(bullet.x as i32))
+                });
+                if let Some(target) = target {
+                    bullet.status = BulletStatus::HitPlayer(target.id);
+                }
+            }
+            idx += 1;
+        }
+    }
+
     fn update_buffer(&mut self) {
-        for it in self.buffer.iter_mut() {
-            if *it != Tile::Obstacle {
-                *it = Tile::Background
+        self.clear_painted();
+        let n_players = self.mode.n_players();
+        for player in self.players.iter().take(n_players) {
+            if player.is_alive() {
+                player.update(&mut self.buffer);
+                self.painted.push(player.rect());
             }
         }
-        self.player.update(self.buffer);
         for enemy in self.enemies.iter() {
-            enemy.update(self.buffer);
+            enemy.update(&mut self.buffer);
+            self.painted.push(enemy.rect());
+        }
+        if self.mode == GameMode::Versus {
+            if let Some(enemy) = self.enemies.get(self.commander.selected_enemy) {
+                // small marker above the enemy picked by player 2
+                let y = (enemy.y as usize).saturating_sub(assets::ENEMY_BITMAP.height as usize / 2 + 2);
+                let x = (enemy.x as usize).saturating_sub(1);
+                if let Some(tiles) = self.buffer.get_mut(y * WIDTH + x..y * WIDTH + x + 2) {
+                    // the map is all there is of the bunkers, the marker goes behind them
+                    for tile in tiles.iter_mut().filter(|tile| **tile != Tile::Obstacle) {
+                        *tile = Tile::Marker;
+                    }
+                }
+                self.painted.push(canvas::Rect::new(x as i32, y as i32, 2, 1));
+            }
         }
         let mut idx = 0;
         while let Some(bullet) = self.bullets.get_mut(idx) {
-            bullet.update(self.buffer);
+            bullet.update(&mut self.buffer);
+            self.painted.push(canvas::Rect::new(bullet.x as i32, bullet.y as i32, 1, 1));
             idx += 1;
         }
-        let mut idx = (self.bullets.size() - 1) as isize;
+        let mut idx = self.bullets.size() as isize - 1;
         while idx >= 0 {
             if let Some(bullet) = self.bullets.get(idx as usize) {
                 match bullet.status {
-                    BulletStatus::HitPlayer => {
-                        self.player.health -= bullet.damage as i32;
-                        self.player.opacity = 10;
+                    BulletStatus::HitPlayer(player_idx) => {
+                        if let Some(player) = self.players.get_mut(player_idx as usize) {
+                            player.health -= bullet.damage as i32;
+                            player.opacity = 10;
+                            if player.is_alive() {
+                                self.pending_shake = self.pending_shake.max(PLAYER_HIT_SHAKE);
+                                self.synth.play(&PLAYER_HIT_SOUND);
+                            } else {
+                                self.pending_shake = self.pending_shake.max(BIG_EVENT_SHAKE);
+                                self.pending_hit_stop = self.pending_hit_stop.max(BIG_EVENT_HIT_STOP_FRAMES);
+                                self.synth.play(&EXPLOSION_SOUND);
+                            }
+                            let hit = events::Event::PlayerHit { player: player_idx, x: bullet.x, y: bullet.y, health: player.health.max(0) as u32 };
+                            self.events.push(self.tick_counter as u32, &hit);
+                        }
+                        let debris = particles::Burst { color: PARTICLE_DEBRIS + player_idx, ..DEBRIS };
+                        self.particles.emit(bullet.x as i32, bullet.y as i32, &debris);
                     },
                     BulletStatus::HitEnemy => {
+                        let mut score = 0;
+                        let mut killed_at = None;
                         for (enemy_idx, enemy) in self.enemies.iter().enumerate() {
                             let x_dist = enemy.x as i32 - bullet.x as i32;
                             let (x_dist, _) = x_dist.overflowing_abs();
                             let y_dist = enemy.y as i32 - bullet.y as i32;
                             let (y_dist, _) = y_dist.overflowing_abs();
-                            if x_dist <= ENEMY_BITMAP.width as i32/2 && y_dist <= ENEMY_BITMAP.height as i32/2 {
+                            // enemies killed earlier this tick are still in the list
+                            if enemy.health > 0 && x_dist <= assets::ENEMY_BITMAP.width as i32/2 && y_dist <= assets::ENEMY_BITMAP.height as i32/2 {
                                 if let Some(enemy) = self.enemies.get_mut(enemy_idx) {
                                     enemy.health -= bullet.damage as i8;
+                                    score = if enemy.health <= 0 { ENEMY_KILL_SCORE } else { ENEMY_HIT_SCORE };
+                                    if enemy.health <= 0 {
+                                        killed_at = Some((enemy.x, enemy.y));
+                                    }
                                     break;
                                 }
                             }
                         }
+                        if let BulletOwner::Player(owner) = bullet.owner {
+                            if let Some(player) = self.players.get_mut(owner as usize) {
+                                player.add_score(score);
+                            }
+                        }
+                        // kills are heard when the enemy is removed
+                        if score == ENEMY_HIT_SCORE {
+                            self.synth.play(&ENEMY_HIT_SOUND);
+                        }
+                        if let Some((x, y)) = killed_at {
+                            let player = match bullet.owner {
+                                BulletOwner::Player(owner) => owner,
+                                BulletOwner::Enemy => events::NO_PLAYER,
+                            };
+                            self.events.push(self.tick_counter as u32, &events::Event::EnemyKilled { player, x, y, score });
+                        }
                     },
+                    BulletStatus::HitBunker => self.particles.emit(bullet.x as i32, bullet.y as i32, &RUBBLE),
                     BulletStatus::Alive | BulletStatus::HitObstacle => (),
                 }
                 match bullet.status {
@@ -405,111 +1766,246 @@ impl Game<'_> {
             idx -= 1;
         }
 
-        let mut idx = (self.enemies.size() - 1) as isize;
-        while idx >= 0 {
-            if let Some(enemy) = self.enemies.get(idx as usize) {
-                if enemy.health <=0 {
-                    self.enemies.remove(idx as usize);
+        let mut idx = self.enemies.size() as isize - 1;
+        let mut killed_enemy = false;
+        while idx >= 0 {
+            if let Some(enemy) = self.enemies.get(idx as usize) {
+                if enemy.health <=0 {
+                    self.particles.emit(enemy.x as i32, enemy.y as i32, &EXPLOSION);
+                    self.synth.play(&EXPLOSION_SOUND);
+                    self.enemies.remove(idx as usize);
+                    killed_enemy = true;
+                }
+            }
+            idx -= 1;
+        }
+        if killed_enemy && self.enemies.size() == 0 {
+            self.pending_shake = self.pending_shake.max(BIG_EVENT_SHAKE);
+            self.pending_hit_stop = self.pending_hit_stop.max(BIG_EVENT_HIT_STOP_FRAMES);
+            self.events.push(self.tick_counter as u32, &events::Event::WaveCleared);
+        }
+
+        if self.active_players().iter().all(|player| !player.is_alive()) {
+            // YOU LOSE!
+            self.game_state = GameState::EndScreen(false);
+        }
+
+        for player in self.active_players_mut() {
+            if player.opacity < 100 {
+                player.opacity += 1;
+            }
+        }
+
+    }
+
+    fn player_color(&self, player: &Player) -> u32 {
+        match self.palette.players.get(player.id as usize) {
+            Some(color) => *color,
+            None => self.palette.players[0],
+        }
+    }
+
+    // drawn colours are unknown after a palette switch
+    fn set_palette(&mut self, palette: palette::Palette) {
+        self.palette = palette;
+        self.palette_staging = palette.to_words();
+        self.full_redraw = true;
+    }
+
+    fn set_starfield(&mut self, enabled: bool) {
+        self.starfield = if enabled { Some(starfield::Starfield::new(WIDTH, HEIGHT)) } else { None };
+        self.full_redraw = true;
+    }
+
+    fn set_font(&mut self, font: font::Font) {
+        self.font = font;
+        self.full_redraw = true;
+        self.draw();
+    }
+
+    fn tile_color(&self, tile: &Tile) -> u32 {
+        match tile {
+            // the background layer shows through
+            Tile::Background => compositor::TRANSPARENT,
+            Tile::Player(player_idx, index) => {
+                if let Some(player) = self.players.get(*player_idx as usize) {
+                    // players fade back in after a hit
+                    let alpha = player.opacity.min(100) * 0xFF / 100;
+                    (self.sprite_color(self.player_color(player), *index) & 0x00_FF_FF_FF) | alpha << 24
+                } else {
+                    compositor::TRANSPARENT
                 }
-            }
-            idx -= 1;
+            },
+            Tile::Bullet => self.palette.bullet,
+            Tile::Obstacle => self.palette.obstacle,
+            Tile::Enemy(val, index) => self.sprite_color(self.palette.enemy(*val), *index),
+            Tile::Marker => self.palette.marker,
         }
+    }
 
-        if self.player.health <= 0 {
-            // YOU LOSE!
-            self.game_state = GameState::EndScreen(false);
+    // the body is drawn in the player's or enemy's own colour
+    fn sprite_color(&self, body: u32, index: u8) -> u32 {
+        match index {
+            SPRITE_BODY => body,
+            SPRITE_COCKPIT => self.palette.cockpit,
+            SPRITE_ENGINE => self.palette.engine,
+            SPRITE_OUTLINE => self.palette.outline,
+            // no sprite uses other indices yet
+            _ => body,
         }
+    }
 
-        if self.player.opacity < 100 {
-            self.player.opacity += 1;
+    // scrolls every frame, on the background layer so it only shows through empty tiles
+    fn render_starfield(&mut self, layers: &mut compositor::Compositor) {
+        let colors = STAR_LEVELS.map(|level| palette::mix(self.palette.background, self.palette.star, level));
+        let background = self.palette.background;
+        let starfield = match self.starfield.as_mut() {
+            Some(starfield) => starfield,
+            None => return,
+        };
+        starfield.scroll();
+        let mut canvas = layers.canvas(compositor::Layer::Background);
+        for idx in self.drawn_stars.drain(..) {
+            let (x, y) = (idx % WIDTH, idx / WIDTH);
+            canvas.pixel(x as i32, y as i32, background);
+            self.dirty.mark(x, y);
         }
+        let (dirty, drawn_stars) = (&mut self.dirty, &mut self.drawn_stars);
+        starfield.for_each(|x, y, depth| {
+            if let Some(color) = colors.get(depth) {
+                canvas.pixel(x, y, *color);
+                dirty.mark(x as usize, y as usize);
+                drawn_stars.push(y as usize * WIDTH + x as usize);
+            }
+        });
+    }
 
+    // indexed by the PARTICLE_ colours, everything fades out
+    fn particle_ramps(&self) -> [[u32; 2]; 3 + MAX_PLAYERS] {
+        let fade_out = |color: u32| [color, color & 0x00_FF_FF_FF];
+        [
+            [self.palette.engine, self.palette.bullet & 0x00_FF_FF_FF],
+            [self.palette.engine, self.palette.enemy_ramp[0] & 0x00_FF_FF_FF],
+            fade_out(self.palette.obstacle),
+            fade_out(self.palette.players[0]),
+            fade_out(self.palette.players[1]),
+        ]
     }
 
-    fn render(&self, js_buffer: &mut [u32; BUFFER_SIZE]) {
-        for (idx, tile) in self.buffer.iter().enumerate() {
-            let color = match tile {
-                Tile::Background => self.default_color,
-                Tile::Player => {
-                    let opacity_hex = (100 - self.player.opacity) * 255 / 100;
-                    let mask = opacity_hex.wrapping_shl(16) | opacity_hex.wrapping_shl(8) | opacity_hex;
-                    self.player.color | mask
-                },
-                Tile::Bullet => 0xFF_80_80_80,
-                Tile::Obstacle => 0xFF_E0_E0_E0,
-                Tile::Enemy(val) => {
-                    let color = *val as u32;
-                    0xFF_00_00_00 | color.wrapping_shl(16) | color.wrapping_shl(8) | color
-                }
+    // particles move every tick, so they are redrawn every frame on a layer of their own
+    fn render_particles(&mut self, layers: &mut compositor::Compositor) {
+        if self.particles.is_empty() && self.drawn_particles.is_empty() {
+            return;
+        }
+        let mut canvas = layers.canvas(compositor::Layer::Effects);
+        for idx in self.drawn_particles.drain(..) {
+            let (x, y) = (idx % WIDTH, idx / WIDTH);
+            canvas.pixel(x as i32, y as i32, compositor::TRANSPARENT);
+            self.dirty.mark(x, y);
+        }
+        let ramps = self.particle_ramps();
+        let (dirty, drawn_particles) = (&mut self.dirty, &mut self.drawn_particles);
+        self.particles.for_each(&ramps, |x, y, color| {
+            if x < 0 || y < 0 || x >= WIDTH as i32 || y >= HEIGHT as i32 {
+                return;
+            }
+            canvas.pixel(x, y, color);
+            dirty.mark(x as usize, y as usize);
+            drawn_particles.push(y as usize * WIDTH + x as usize);
+        });
+    }
+
+    // repaints only tiles whose colour changed since they were last drawn
+    fn render(&mut self, layers: &mut compositor::Compositor) {
+        let mut canvas = layers.canvas(compositor::Layer::Playfield);
+        for idx in 0..WIDTH * HEIGHT {
+            let color = match self.buffer.get(idx) {
+                Some(tile) => self.tile_color(tile),
+                None => continue,
             };
+            match self.drawn_tiles.get_mut(idx) {
+                Some(drawn) if *drawn != color => *drawn = color,
+                _ => continue,
+            }
             let buffer_row = idx / WIDTH;
             let buffer_col = idx % WIDTH;
-            for row in 0..MULT {
-                let ind0 = ((buffer_row * MULT) + row) * MULT * WIDTH + buffer_col * MULT;
-                for col in 0..MULT {
-                    if let Some(x) = js_buffer.get_mut(ind0 + col) {
-                        *x = color;
-                    }
-                }
-            }
+            self.dirty.mark(buffer_col, buffer_row);
+            canvas.pixel(buffer_col as i32, buffer_row as i32, color);
+        }
+
+        let status_bar = self.status_bar_checksum();
+        if self.drawn_status_bar != Some(status_bar) {
+            self.render_status_bar(&mut layers.canvas(compositor::Layer::Hud));
+            self.drawn_status_bar = Some(status_bar);
+            self.dirty.mark_rows(HEIGHT, STATUS_BAR_HEIGHT);
+        }
+    }
+
+    // everything shown in the status bar, it's only repainted when this changes
+    fn status_bar_checksum(&self) -> u32 {
+        let mut hash = Fnv1a::new();
+        hash.write_u32(u32::from_le_bytes([
+            self.mode.n_players() as u8,
+            (self.mode == GameMode::Versus) as u8,
+            (self.commander.special_cooldown == 0) as u8,
+            (self.commander.reset_status_bar || self.active_players().iter().any(|player| player.reset_status_bar)) as u8,
+        ]));
+        for player in self.active_players().iter() {
+            hash.write_u32(player.health as u32);
+            hash.write_u32(match player.weapon {
+                Weapon::Pistol => 0,
+                Weapon::Rifle => 1,
+                Weapon::Shotgun => 2,
+            });
+            hash.write_u32(player.rifle_ammo as u32);
+            hash.write_u32(player.shotgun_ammo as u32);
+            hash.write_u32(player.score);
         }
-        self.render_status_bar(js_buffer);
+        hash.0
     }
 
-    fn render_health_bar(&self, js_buffer: &mut [u32; BUFFER_SIZE], offset: usize) -> usize {
-        const TXT_COLOR: u32 = 0xFF_00_00_00;
+    fn render_health_bar(&self, canvas: &mut canvas::Canvas, player: &Player, x: i32, y: i32) -> i32 {
+        let txt_color = self.palette.hud_text;
 
-        let health_string_start = offset;
-        let health_string_end = self.render_text(js_buffer, "HP:", health_string_start, 1, TXT_COLOR);
+        let health_string_end = self.render_text(canvas, "HP:", x, y, 1, txt_color);
 
-        const HEALTH_COLORS: [u32; MAX_PLAYER_HEALTH as usize] = [
-            0xFF_10_10_FF,
-            0xFF_10_A0_FF,
-            0xFF_10_FF_10,
-        ];
-        const BAR_WIDTH: usize = 3 * MULT;
-        const BAR_STEP: usize = BAR_WIDTH + 1;
-        let health_bar_start = health_string_end + BAR_STEP;
-        let health_bar_end = health_bar_start + MAX_PLAYER_HEALTH as usize * BAR_STEP;
+        const BAR_WIDTH: i32 = 3;
+        let bar_step = BAR_WIDTH + 1;
+        let health_bar_start = health_string_end + bar_step;
+        let health_bar_end = health_bar_start + MAX_PLAYER_HEALTH * bar_step;
         let default_bar_color;
-        if let Some(bar_color) = HEALTH_COLORS.get((self.player.health - 1) as usize) {
+        if let Some(bar_color) = self.palette.health.get((player.health - 1) as usize) {
             default_bar_color = *bar_color;
         } else {
-            default_bar_color = 0xFF_FF_FF_FF;
-        }
-        for row_idx in 0..(FONT_SIZE as usize * MULT) {
-            let buffer_start = health_bar_start + row_idx * WIDTH * MULT;
-            for bar_idx in 0..MAX_PLAYER_HEALTH as usize {
-                let color;
-                if (bar_idx as i32) < self.player.health {
-                    color = default_bar_color;
-                } else {
-                    color = 0xFF_FF_FF_FF;
-                }
-                let ind0 = buffer_start + bar_idx * BAR_STEP;
-                if let Some(x) = js_buffer.get_mut(ind0..ind0+BAR_WIDTH) {
-                    x.fill(color);
-                }
-
+            default_bar_color = self.palette.health_empty;
+        }
+        for bar_idx in 0..MAX_PLAYER_HEALTH {
+            let color;
+            if bar_idx < player.health {
+                color = default_bar_color;
+            } else {
+                color = self.palette.health_empty;
             }
+            let bar = canvas::Rect::new(health_bar_start + bar_idx * bar_step, y, BAR_WIDTH, FONT_SIZE as i32);
+            canvas.fill_rect(bar, color);
         }
         return health_bar_end;
     }
 
-    fn render_weapon_status(&self, js_buffer: &mut [u32; BUFFER_SIZE], offset: usize) -> usize {
-        const TXT_COLOR: u32 = 0xFF_00_00_00;
+    fn render_weapon_status(&self, canvas: &mut canvas::Canvas, player: &Player, x: i32, y: i32) -> i32 {
+        let txt_color = self.palette.hud_text;
 
-        let weapon_string_start = offset;
-        let weapon_string = match self.player.weapon {
+        let weapon_string_start = x;
+        let weapon_string = match player.weapon {
             Weapon::Pistol => "PISTOL: ",
             Weapon::Rifle => "RIFLE: ",
             Weapon::Shotgun => "SHOTGUN: ",
         };
-        let weapon_string_end = self.render_text(js_buffer, weapon_string, weapon_string_start, 1, TXT_COLOR);
+        let weapon_string_end = self.render_text(canvas, weapon_string, weapon_string_start, y, 1, txt_color);
 
         let weapon_ammo_start = weapon_string_end;
-        let weapon_ammo_end = match self.player.weapon {
-            Weapon::Pistol => self.render_inf_symbol(js_buffer, weapon_ammo_start, 1, TXT_COLOR),
+        let weapon_ammo_end = match player.weapon {
+            Weapon::Pistol => self.render_inf_symbol(canvas, weapon_ammo_start, y, 1, txt_color),
             Weapon::Rifle => {
                 // u32 to char array without panic
                 const MAX_AMMO_STR_LEN: usize = 16;
@@ -520,12 +2016,12 @@ impl Game<'_> {
                 }
                 str_len += 1;
                 if let Some(x) = available_ammo_str.get_mut(0..MAX_AMMO_STR_LEN - str_len) {
-                    str_len += stringify_u32(self.player.rifle_ammo as u32, x);
+                    str_len += stringify_u32(player.rifle_ammo as u32, x);
                 }
                 if let Some(x) = available_ammo_str.get(MAX_AMMO_STR_LEN - str_len..) {
-                    self.render_char_arr(js_buffer, x, weapon_ammo_start, 1, TXT_COLOR)
+                    self.render_char_arr(canvas, x, weapon_ammo_start, y, 1, txt_color)
                 } else {
-                    self.render_text(js_buffer, "0/0", weapon_ammo_start, 1, TXT_COLOR)
+                    self.render_text(canvas, "0/0", weapon_ammo_start, y, 1, txt_color)
                 }
             },
                 Weapon::Shotgun => {
@@ -538,12 +2034,12 @@ impl Game<'_> {
                 }
                 str_len += 1;
                 if let Some(x) = available_ammo_str.get_mut(0..MAX_AMMO_STR_LEN - str_len) {
-                    str_len += stringify_u32(self.player.shotgun_ammo as u32, x);
+                    str_len += stringify_u32(player.shotgun_ammo as u32, x);
                 }
                 if let Some(x) = available_ammo_str.get(MAX_AMMO_STR_LEN - str_len..) {
-                    self.render_char_arr(js_buffer, x, weapon_ammo_start, 1, TXT_COLOR)
+                    self.render_char_arr(canvas, x, weapon_ammo_start, y, 1, txt_color)
                 } else {
-                    self.render_text(js_buffer, "0/0", weapon_ammo_start, 1, TXT_COLOR)
+                    self.render_text(canvas, "0/0", weapon_ammo_start, y, 1, txt_color)
                 }
             }
         };
@@ -551,387 +2047,171 @@ impl Game<'_> {
         return weapon_ammo_end;
     }
 
-    fn render_status_bar(&self, js_buffer: &mut [u32; BUFFER_SIZE]) {
-        let offset = (HEIGHT + 1) * MULT * WIDTH * MULT;
-        if self.player.reset_status_bar {
-            if let Some(x) = js_buffer.get_mut(offset..) {
-                x.fill(0xFF_FF_FF_FF);
-            }
+    fn render_score(&self, canvas: &mut canvas::Canvas, player: &Player, x: i32, y: i32) -> i32 {
+        let txt_color = self.palette.hud_text;
+
+        let score_string_end = self.render_text(canvas, "  SCORE: ", x, y, 1, txt_color);
+        // u32 to char array without panic
+        const MAX_SCORE_STR_LEN: usize = 10;
+        let mut score_str: [char; MAX_SCORE_STR_LEN] = ['0'; MAX_SCORE_STR_LEN];
+        let str_len = stringify_u32(player.score, &mut score_str);
+        if let Some(x) = score_str.get(MAX_SCORE_STR_LEN - str_len..) {
+            self.render_char_arr(canvas, x, score_string_end, y, 1, txt_color)
+        } else {
+            self.render_text(canvas, "0", score_string_end, y, 1, txt_color)
         }
-        let offset = self.render_health_bar(js_buffer, offset);
-        let _offset = self.render_weapon_status(js_buffer, offset);
     }
 
-    fn render_text_aligned(&self, js_buffer: &mut [u32; BUFFER_SIZE], text: &str, y: usize, scale: usize, color: u32) -> usize {
-        let mut text_width = 0;
-        for c in text.chars() {
-            let bm = self.get_char_bitmap(c);
-            text_width += (bm.width + 1) as usize * MULT * scale;
-        }
-        let x_offset = (WIDTH * MULT - text_width) / 2;
-        let start_pos = y * WIDTH * MULT + x_offset;
-        self.render_text(js_buffer, text, start_pos, scale, color)
+    fn render_commander_status(&self, canvas: &mut canvas::Canvas, x: i32, y: i32) -> i32 {
+        let txt_color = self.palette.hud_text;
+
+        let x = self.render_text(canvas, "P2 ", x, y, 1, self.palette.players[1]);
+        let x = self.render_text(canvas, "SPECIAL: ", x, y, 1, txt_color);
+        let special_string = if self.commander.special_cooldown == 0 { "READY" } else { "WAIT" };
+        self.render_text(canvas, special_string, x, y, 1, txt_color)
     }
 
-    fn render_text(&self, js_buffer: &mut [u32; BUFFER_SIZE], text: &str, start_pos: usize, scale: usize, color: u32) -> usize {
-        let mut pos = start_pos;
-        for c in text.chars() {
-            pos = self.render_char(js_buffer, c, pos, scale, color);
+    fn render_status_bar(&self, canvas: &mut canvas::Canvas) {
+        let y = HEIGHT as i32 + 1;
+        // long scores shouldn't spill into the playfield
+        canvas.set_clip(canvas::Rect::new(0, HEIGHT as i32, WIDTH as i32, STATUS_BAR_HEIGHT as i32));
+        if self.commander.reset_status_bar || self.active_players().iter().any(|player| player.reset_status_bar) {
+            canvas.fill_rect(canvas::Rect::new(0, y, WIDTH as i32, STATUS_BAR_HEIGHT as i32 - 1), self.palette.hud_background);
+        }
+        let show_label = self.mode != GameMode::Single;
+        let row_height = FONT_SIZE as i32 + 2;
+        for (idx, player) in self.active_players().iter().enumerate() {
+            let y = y + idx as i32 * row_height;
+            let mut x = 0;
+            if show_label {
+                let label = if idx == 0 { "P1 " } else { "P2 " };
+                x = self.render_text(canvas, label, x, y, 1, self.player_color(player));
+            }
+            let x = self.render_health_bar(canvas, player, x, y);
+            let x = self.render_weapon_status(canvas, player, x, y);
+            let _x = self.render_score(canvas, player, x, y);
+        }
+        if self.mode == GameMode::Versus {
+            let _x = self.render_commander_status(canvas, 0, y + row_height);
         }
-        return pos
+        canvas.reset_clip();
     }
 
-    fn render_char_arr(&self, js_buffer: &mut [u32; BUFFER_SIZE], text: &[char], start_pos: usize, scale: usize, color: u32) -> usize {
-        let mut pos = start_pos;
-        for c in text {
-            pos = self.render_char(js_buffer, *c, pos, scale, color);
+    // lays out `text` inside `bounds`
+    fn draw_text(&self, canvas: &mut canvas::Canvas, text: &str, bounds: &text::TextBox, style: &text::TextStyle) {
+        let offsets: &[(i32, i32)] = match style.effect {
+            text::Effect::Plain => &[],
+            text::Effect::Shadow(_) => &[(1, 1)],
+            text::Effect::Outline(_) => &[(-1, -1), (0, -1), (1, -1), (-1, 0), (1, 0), (-1, 1), (0, 1), (1, 1)],
+        };
+        let effect_color = match style.effect {
+            text::Effect::Plain => style.color,
+            text::Effect::Shadow(color) | text::Effect::Outline(color) => color,
+        };
+        let scale = style.scale as i32;
+        for line in text::layout(&self.font, text, bounds, style) {
+            let line_text = &text[line.start..line.end];
+            for (dx, dy) in offsets.iter() {
+                self.render_text(canvas, line_text, line.x + dx * scale, line.y + dy * scale, style.scale, effect_color);
+            }
+            self.render_text(canvas, line_text, line.x, line.y, style.scale, style.color);
         }
-        return pos
     }
 
-    fn render_char(&self, js_buffer: &mut [u32; BUFFER_SIZE], c: char, start_pos: usize, scale: usize, color: u32) -> usize {
-        let bm = self.get_char_bitmap(c);
+    fn render_text(&self, canvas: &mut canvas::Canvas, text: &str, x: i32, y: i32, scale: usize, color: u32) -> i32 {
+        self.render_chars(canvas, text.chars(), x, y, scale, color)
+    }
 
-        for (row_idx, &row) in bm.bitmap.iter().enumerate() {
-            let buffer_start = start_pos + row_idx * WIDTH * MULT * MULT * scale;
-            for bit_idx in 0..16 {
-                if row & (1 << (16 - bit_idx)) != 0 {
-                    let ind0 = buffer_start + bit_idx*MULT*scale;
-                    for i in 0..MULT*scale {
-                        let ind0 = ind0 + i*WIDTH*MULT;
-                        if let Some(x) = js_buffer.get_mut(ind0..ind0+MULT*scale) {
-                            x.fill(color);
-                        }
-                    }
-                }
-            }
-        }
-        return start_pos + (bm.width + 1) as usize * MULT * scale
-    }
-
-    fn render_inf_symbol(&self, js_buffer: &mut [u32; BUFFER_SIZE], start_pos: usize, scale: usize, color: u32) -> usize {
-        let bm = &Bitmap2D { width: 9, height: FONT_SIZE,
-                    bitmap: &[
-                        0b0110001100000000,
-                        0b1001010010000000,
-                        0b1000100010000000,
-                        0b1001010010000000,
-                        0b0110001100000000,
-                    ] };
-
-        for (row_idx, &row) in bm.bitmap.iter().enumerate() {
-            let buffer_start = start_pos + row_idx * WIDTH * MULT * MULT * scale;
-            for bit_idx in 0..16 {
-                if row & (1 << (16 - bit_idx)) != 0 {
-                    let ind0 = buffer_start + bit_idx*MULT*scale;
-                    for i in 0..MULT*scale {
-                        let ind0 = ind0 + i*WIDTH*MULT;
-                        if let Some(x) = js_buffer.get_mut(ind0..ind0+MULT*scale) {
-                            x.fill(color);
-                        }
-                    }
-                }
-            }
+    fn render_char_arr(&self, canvas: &mut canvas::Canvas, text: &[char], x: i32, y: i32, scale: usize, color: u32) -> i32 {
+        self.render_chars(canvas, text.iter().copied(), x, y, scale, color)
+    }
+
+    fn render_chars<I: Iterator<Item = char>>(&self, canvas: &mut canvas::Canvas, text: I, x: i32, y: i32, scale: usize, color: u32) -> i32 {
+        let mut text = text.peekable();
+        let mut x = x;
+        while let Some(c) = text.next() {
+            let glyph = self.font.glyph(c);
+            self.render_glyph(canvas, glyph.rows, x, y, scale, color);
+            x += self.font.advance(c, text.peek().copied()) * scale as i32;
         }
-        return start_pos + (bm.width + 1) as usize * MULT * scale
-    }
-
-    fn get_char_bitmap(&self, c: char) -> &Bitmap2D {
-        match c {
-            'A' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                        ] },
-            'C' => &Bitmap2D { width: 3, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1110000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1110000000000000,
-                        ] },
-            'D' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1110000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1110000000000000,
-                        ] },
-            'E' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1110000000000000,
-                            0b1000000000000000,
-                            0b1111000000000000,
-                        ] },
-            'F' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1110000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                        ] },
-            'G' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1011000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                        ] },
-            'H' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                        ] },
-            'I' => &Bitmap2D { width: 1, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                        ] },
-            'L' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                            0b1111000000000000,
-                        ] },
-            'M' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000100000000000,
-                            0b1101100000000000,
-                            0b1010100000000000,
-                            0b1000100000000000,
-                            0b1000100000000000,
-                        ] },
-            'N' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000100000000000,
-                            0b1100100000000000,
-                            0b1010100000000000,
-                            0b1001100000000000,
-                            0b1000100000000000,
-                        ] },
-            'O' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                        ] },
-            'P' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1000000000000000,
-                        ] },
-            'R' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                            0b1010000000000000,
-                            0b1001000000000000,
-                        ] },
-            'S' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1111000000000000,
-                            0b0001000000000000,
-                            0b1111000000000000,
-                        ] },
-            'T' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111100000000000,
-                            0b0010000000000000,
-                            0b0010000000000000,
-                            0b0010000000000000,
-                            0b0010000000000000,
-                        ] },
-            'U' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                        ] },
-            'W' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000100000000000,
-                            0b1000100000000000,
-                            0b1010100000000000,
-                            0b1101100000000000,
-                            0b1000100000000000,
-                        ] },
-            'Y' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1000100000000000,
-                            0b0101000000000000,
-                            0b0010000000000000,
-                            0b0010000000000000,
-                            0b0010000000000000,
-                        ] },
-            '0' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b1001000000000000,
-                            0b0110000000000000,
-                        ] },
-            '1' => &Bitmap2D { width: 3, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0100000000000000,
-                            0b1100000000000000,
-                            0b0100000000000000,
-                            0b0100000000000000,
-                            0b1110000000000000,
-                        ] },
-            '2' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b0010000000000000,
-                            0b0100000000000000,
-                            0b1111000000000000,
-                        ] },
-            '3' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b0010000000000000,
-                            0b1001000000000000,
-                            0b0110000000000000,
-                        ] },
-            '4' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0101000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                            0b0001000000000000,
-                            0b0001000000000000,
-                        ] },
-            '5' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1110000000000000,
-                            0b0001000000000000,
-                            0b1110000000000000,
-                        ] },
-            '6' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b1000000000000000,
-                            0b1111000000000000,
-                            0b1001000000000000,
-                            0b1111000000000000,
-                        ] },
-            '7' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1111000000000000,
-                            0b0001000000000000,
-                            0b0010000000000000,
-                            0b0100000000000000,
-                            0b0100000000000000,
-                        ] },
-            '8' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b0110000000000000,
-                        ] },
-            '9' => &Bitmap2D { width: 4, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0110000000000000,
-                            0b1001000000000000,
-                            0b0111000000000000,
-                            0b0010000000000000,
-                            0b0100000000000000,
-                        ] },
-            '/' => &Bitmap2D { width: 5, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0000100000000000,
-                            0b0001000000000000,
-                            0b0010000000000000,
-                            0b0100000000000000,
-                            0b1000000000000000,
-                        ] },
-            ':' => &Bitmap2D { width: 1, height: FONT_SIZE,
-                        bitmap: &[
-                            0b0000000000000000,
-                            0b0000000000000000,
-                            0b1000000000000000,
-                            0b0000000000000000,
-                            0b1000000000000000,
-                        ] },
-            ' ' => &Bitmap2D { width: 2, height: FONT_SIZE,
-                        bitmap: &[
-                        ] },
-            _ => &Bitmap2D { width: 3, height: FONT_SIZE,
-                        bitmap: &[
-                            0b1110000000000000,
-                            0b1110000000000000,
-                            0b1110000000000000,
-                            0b1110000000000000,
-                            0b1110000000000000,
-                        ] },
-        }
-    }
-
-    fn draw_start_screen(&self, js_buffer: &mut [u32; BUFFER_SIZE]) {
-        const BG_COLOR: u32 = 0xFF_88_88_88;
-        const TXT_COLOR: u32 = 0xFF_00_00_00;
-        js_buffer.fill(BG_COLOR);
-        self.render_text_aligned(js_buffer, "PRESS SPACE TO START", HEIGHT * MULT / 2, 2, TXT_COLOR);
-
-        let offset = BUFFER_SIZE - WIDTH * MULT * MULT * (FONT_SIZE  + 1) as usize;
-        self.render_text(js_buffer, "ESC: PAUSE/HELP MENU", offset, 1, TXT_COLOR);
-    }
-
-    fn draw_end_screen(&self, has_won: bool, js_buffer: &mut [u32; BUFFER_SIZE]) {
-        const END_SCREEN_WINNER_COLOR: u32 = 0xFF_00_88_00;
-        const END_SCREEN_LOSER_COLOR: u32 = 0xFF_00_00_88;
-        const TXT_COLOR: u32 = 0xFF_00_00_00;
-        let color = if has_won { END_SCREEN_WINNER_COLOR } else { END_SCREEN_LOSER_COLOR };
-        let text = if has_won { "YOU WIN" } else { "YOU LOSE" };
-        js_buffer.fill(color);
-        self.render_text_aligned(js_buffer, text, HEIGHT * MULT / 2, 2, TXT_COLOR);
-    }
-
-    fn draw_help_screen(&self, js_buffer: &mut [u32; BUFFER_SIZE]) {
-        const BG_COLOR: u32 = 0xFF_AA_AA_AA;
-        const TXT_COLOR: u32 = 0xFF_00_00_00;
-        const TXT_SCALE: usize = 2;
-        js_buffer.fill(BG_COLOR);
-        self.render_text_aligned(js_buffer, "GAME PAUSED", HEIGHT * MULT / 6, TXT_SCALE, TXT_COLOR);
-        let offset = (HEIGHT / 3) * MULT * WIDTH * MULT;// + (WIDTH / 4) * MULT;
-        self.render_text(js_buffer, "SHOOT: SPACE", offset, TXT_SCALE, TXT_COLOR);
-        let offset = offset + (FONT_SIZE as usize + 2) * TXT_SCALE * WIDTH * MULT * MULT;
-        self.render_text(js_buffer, "CHANGE WEAPON: CTRL", offset, TXT_SCALE, TXT_COLOR);
-        let offset = offset + (FONT_SIZE as usize + 2) * TXT_SCALE * WIDTH * MULT * MULT;
-        self.render_text(js_buffer, "PAUSE: ESC", offset, TXT_SCALE, TXT_COLOR);
+        x
+    }
+
+    fn render_inf_symbol(&self, canvas: &mut canvas::Canvas, x: i32, y: i32, scale: usize, color: u32) -> i32 {
+        let bm = &assets::INFINITY_BITMAP;
+        self.render_glyph(canvas, bm.bitmap, x, y, scale, color);
+        x + (bm.width + 1) as i32 * scale as i32
+    }
+
+    fn render_glyph(&self, canvas: &mut canvas::Canvas, rows: &[u16], x: i32, y: i32, scale: usize, color: u32) {
+        // glyphs start one pixel in, the lowest bit is never drawn
+        canvas.bitmap(x + scale as i32, y, rows, 15, scale, color);
+    }
+
+    fn draw_background(&self, canvas: &mut canvas::Canvas) {
+        canvas.clear(self.palette.hud_background);
+        canvas.fill_rect(canvas::Rect::new(0, 0, WIDTH as i32, HEIGHT as i32), self.palette.background);
+    }
+
+    fn draw_start_screen(&self, canvas: &mut canvas::Canvas) {
+        let txt_color = self.palette.menu_text;
+        canvas.clear(self.palette.start_background);
+        let title_style = text::TextStyle::new(2, txt_color).align(text::Align::Center)
+            .effect(text::Effect::Shadow(self.palette.menu_shadow));
+        self.draw_text(canvas, "PRESS SPACE TO START", &screen_box(HEIGHT / 2), &title_style);
+        let mode_text = match self.mode {
+            GameMode::Single => "CTRL: 1 PLAYER",
+            GameMode::Coop => "CTRL: 2 PLAYERS COOP",
+            GameMode::Versus => "CTRL: 2 PLAYERS VERSUS",
+        };
+        let style = text::TextStyle::new(1, txt_color).align(text::Align::Center);
+        self.draw_text(canvas, mode_text, &screen_box(HEIGHT * 2 / 3), &style);
+
+        let style = text::TextStyle::new(1, txt_color);
+        let help_text = "ESC: PAUSE/HELP MENU";
+        let (_, text_height) = text::measure(&self.font, help_text, &style);
+        let bottom_box = screen_box(HEIGHT + STATUS_BAR_HEIGHT - text_height - 1);
+        self.draw_text(canvas, help_text, &bottom_box, &style);
+    }
+
+    fn draw_end_screen(&self, has_won: bool, canvas: &mut canvas::Canvas) {
+        let color = if has_won { self.palette.win_background } else { self.palette.lose_background };
+        let text = match (self.mode, has_won) {
+            (GameMode::Versus, true) => "PLAYER 1 WINS",
+            (GameMode::Versus, false) => "PLAYER 2 WINS",
+            (_, true) => "YOU WIN",
+            (_, false) => "YOU LOSE",
+        };
+        canvas.clear(color);
+        let style = text::TextStyle::new(2, self.palette.menu_text).align(text::Align::Center)
+            .effect(text::Effect::Outline(self.palette.menu_shadow));
+        self.draw_text(canvas, text, &screen_box(HEIGHT / 2), &style);
+    }
+
+    fn draw_help_screen(&self, canvas: &mut canvas::Canvas) {
+        const HELP_TEXT: &str = "SHOOT: SPACE\n\
+                                 CHANGE WEAPON: CTRL\n\
+                                 PAUSE: ESC\n\
+                                 PLAYER 2: A D W S E\n\
+                                 REWIND: R";
+        let txt_color = self.palette.menu_text;
+        canvas.clear((self.palette.pause_background & 0x00_FF_FF_FF) | PAUSE_SCREEN_ALPHA << 24);
+        let title_style = text::TextStyle::new(2, txt_color).align(text::Align::Center)
+            .effect(text::Effect::Shadow(self.palette.menu_shadow));
+        self.draw_text(canvas, "GAME PAUSED", &screen_box(HEIGHT / 6), &title_style);
+        let (title_width, title_height) = text::measure(&self.font, "GAME PAUSED", &title_style);
+        let underline_x = (WIDTH.saturating_sub(title_width) / 2) as i32;
+        let underline_y = (HEIGHT / 6 + title_height + 3) as i32;
+        canvas.line(underline_x, underline_y, underline_x + title_width as i32 - 1, underline_y, txt_color);
+
+        // controls in a framed panel in the middle
+        let style = text::TextStyle::new(2, txt_color);
+        let (width, height) = text::measure(&self.font, HELP_TEXT, &style);
+        let panel = text::TextBox { x: (WIDTH.saturating_sub(width) / 2) as i32, y: (HEIGHT / 3) as i32, width, height };
+        self.draw_text(canvas, HELP_TEXT, &panel, &style);
+        const PANEL_PADDING: i32 = 3;
+        canvas.rect(canvas::Rect::new(panel.x - PANEL_PADDING, panel.y - PANEL_PADDING,
+                                      width as i32 + 2 * PANEL_PADDING, height as i32 + 2 * PANEL_PADDING), txt_color);
     }
 
     fn get_random_u32(&mut self) -> u32 {
@@ -942,14 +2222,34 @@ impl Game<'_> {
 }
 
 impl Player {
-    fn reset(&mut self) {
-        self.pos = (WIDTH as i32)/2;
-        self.health = 3;
+    fn new(id: u8) -> Player {
+        Player {
+            id, pos: (WIDTH as i32)/2, health: MAX_PLAYER_HEALTH,
+            last_shot_in_ticks: 0, opacity: 100, weapon: Weapon::Pistol,
+            rifle_ammo: MAX_RIFLE_AMMO, shotgun_ammo: MAX_SHOTGUN_AMMO,
+            reset_status_bar: false, score: 0
+        }
+    }
+
+    fn reset(&mut self, pos: i32) {
+        self.pos = pos;
+        self.health = MAX_PLAYER_HEALTH;
         self.last_shot_in_ticks = 0;
+        self.opacity = 100;
         self.weapon = Weapon::Pistol;
         self.rifle_ammo = MAX_RIFLE_AMMO;
         self.shotgun_ammo = MAX_SHOTGUN_AMMO;
         self.reset_status_bar = false;
+        self.score = 0;
+    }
+
+    fn is_alive(&self) -> bool {
+        self.health > 0
+    }
+
+    fn add_score(&mut self, score: u32) {
+        self.score = self.score.saturating_add(score);
+        self.reset_status_bar = true;
     }
 
     fn tick(&mut self) {
@@ -968,7 +2268,7 @@ impl Player {
 
     fn try_move(&mut self, diff: i32) {
         let new_pos = self.pos + diff;
-        let width = PLAYER_BITMAP.width as i32;
+        let width = assets::PLAYER_BITMAP.width as i32;
         if new_pos >= (width / 2) && new_pos <= (WIDTH as i32 - width / 2) {
             self.pos = new_pos;
         }
@@ -990,69 +2290,68 @@ impl Player {
             Weapon::Shotgun => {self.shotgun_ammo -= 1; self.reset_status_bar = true;},
         };
         if self.weapon == Weapon::Shotgun {
-            let x0 = self.pos as u32 - PLAYER_BITMAP.width / 4;
-            let x1 = self.pos as u32 + PLAYER_BITMAP.width / 4;
+            let x0 = self.pos as u32 - assets::PLAYER_BITMAP.width / 4;
+            let x1 = self.pos as u32 + assets::PLAYER_BITMAP.width / 4;
             bullets.push_back(
                 Bullet {
-                    x: x0 as u8, y: (HEIGHT as u32 - PLAYER_BITMAP.height - 1) as u8,
-                    speed: -1, damage, status: BulletStatus::Alive
+                    x: x0 as u8, y: (HEIGHT as u32 - assets::PLAYER_BITMAP.height - 1) as u8,
+                    speed: -1, damage, status: BulletStatus::Alive,
+                    owner: BulletOwner::Player(self.id)
                 }
             );
             bullets.push_back(
                 Bullet {
-                    x: x1 as u8, y: (HEIGHT as u32 - PLAYER_BITMAP.height - 1) as u8,
-                    speed: -1, damage, status: BulletStatus::Alive
+                    x: x1 as u8, y: (HEIGHT as u32 - assets::PLAYER_BITMAP.height - 1) as u8,
+                    speed: -1, damage, status: BulletStatus::Alive,
+                    owner: BulletOwner::Player(self.id)
                 }
             );
         } else {
             bullets.push_back(
                 Bullet {
-                    x: self.pos as u8, y: (HEIGHT as u32 - PLAYER_BITMAP.height - 1) as u8,
-                    speed: -1, damage, status: BulletStatus::Alive
+                    x: self.pos as u8, y: (HEIGHT as u32 - assets::PLAYER_BITMAP.height - 1) as u8,
+                    speed: -1, damage, status: BulletStatus::Alive,
+                    owner: BulletOwner::Player(self.id)
                 }
             );
         }
     }
 
+    fn rect(&self) -> canvas::Rect {
+        let x0 = self.pos - assets::PLAYER_BITMAP.width as i32 / 2;
+        let y0 = (HEIGHT - assets::PLAYER_BITMAP.height as usize) as i32;
+        canvas::Rect::new(x0, y0, assets::PLAYER_BITMAP.width as i32, assets::PLAYER_BITMAP.height as i32)
+    }
+
     fn update(&self, buffer: &mut [Tile; WIDTH * HEIGHT]) {
-        let x0 = self.pos as u32 - PLAYER_BITMAP.width / 2;
-        let mut y = HEIGHT as u32 - PLAYER_BITMAP.height;
-        for row in PLAYER_BITMAP.bitmap.iter() {
-            // Player's bitmap is contiguous so I can do this
-            let offset = row.leading_zeros();
-            let length = row.count_ones();
-            
-            let start_pos = (y * (WIDTH as u32) + x0 + offset) as usize;
-            if let Some(x) = buffer.get_mut(start_pos..start_pos+length as usize) {
-                x.fill(Tile::Player);
-            }
-            y += 1;
-        }
+        let rect = self.rect();
+        sprite::blit(buffer, WIDTH, rect.x, rect.y, &assets::PLAYER_BITMAP, &sprite::Transform::IDENTITY, |index| Tile::Player(self.id, index));
     }
 }
 
 impl Enemy {
+    fn rect(&self) -> canvas::Rect {
+        // enemies have always been drawn one pixel right of their centre
+        let x0 = self.x as i32 - assets::ENEMY_BITMAP.width as i32 / 2 + 1;
+        let y0 = self.y as i32 - assets::ENEMY_BITMAP.height as i32 / 2;
+        canvas::Rect::new(x0, y0, assets::ENEMY_BITMAP.width as i32, assets::ENEMY_BITMAP.height as i32)
+    }
+
     fn update(&self, buffer: &mut [Tile; WIDTH * HEIGHT]) {
         let max_health = if self.max_health == 0 { 1 } else { self.max_health as u32 };
         let color = 255 * (100 - (self.health as u32 * 100 / max_health));
-        let enemy_tile = Tile::Enemy(color as u8);
-        let x0 = self.x as u32 - ENEMY_BITMAP.width / 2;
-        let y0 = self.y as u32 - ENEMY_BITMAP.height / 2;
-        for (row_idx, &row) in ENEMY_BITMAP.bitmap.iter().enumerate() {
-            let row_start = ((y0 + row_idx as u32) * (WIDTH as u32) + x0) as usize;
-            for bit_idx in 0..15 {
-                if row & (1 << (16 - bit_idx)) != 0 {
-                    if let Some(x) = buffer.get_mut(row_start + bit_idx) {
-                        *x = enemy_tile;
-                    }
-                }
-            }
-        }
+        let level = color as u8;
+        let rect = self.rect();
+        sprite::blit(buffer, WIDTH, rect.x, rect.y, &assets::ENEMY_BITMAP, &sprite::Transform::IDENTITY, |index| Tile::Enemy(level, index));
     }
 }
 
 impl Bullet {
     fn update(&mut self, buffer: &mut [Tile; WIDTH * HEIGHT]) {
+        // hit before it moved, see shoot_overlapping_ships
+        if !matches!(self.status, BulletStatus::Alive) {
+            return;
+        }
         if self.y == 0 || self.y == HEIGHT as u8 {
             self.status = BulletStatus::HitObstacle;
             return;
@@ -1061,10 +2360,15 @@ impl Bullet {
         let pos = self.y as usize * WIDTH + self.x as usize;
         if let Some(x) = buffer.get_mut(pos) {
             match *x {
-                Tile::Background | Tile::Bullet => *x = Tile::Bullet,
-                Tile::Player => self.status = BulletStatus::HitPlayer,
-                Tile::Enemy(_) => {
-                    if self.speed > 0 {
+                Tile::Background | Tile::Bullet | Tile::Marker => *x = Tile::Bullet,
+                Tile::Player(player_idx, _) => {
+                    // player bullets fly through ships, friendly fire is settled when they're fired
+                    if self.owner == BulletOwner::Enemy {
+                        self.status = BulletStatus::HitPlayer(player_idx);
+                    }
+                },
+                Tile::Enemy(..) => {
+                    if self.owner == BulletOwner::Enemy {
                         // Enemy hit enemy
                         self.status = BulletStatus::HitObstacle;
                     } else {
@@ -1073,7 +2377,7 @@ impl Bullet {
                 },
                 Tile::Obstacle => {
                     *x = Tile::Background;
-                    self.status = BulletStatus::HitObstacle;
+                    self.status = BulletStatus::HitBunker;
                 },
             }
         }
@@ -1089,4 +2393,717 @@ mod tests {
         let result = 420;
         assert_eq!(result, 420);
     }
+
+    #[test]
+    fn test_analog_move_diff() {
+        assert_eq!(analog_move_diff(0, DEFAULT_DEAD_ZONE), 0);
+        assert_eq!(analog_move_diff(DEFAULT_DEAD_ZONE, DEFAULT_DEAD_ZONE), 0);
+        assert_eq!(analog_move_diff(-DEFAULT_DEAD_ZONE, DEFAULT_DEAD_ZONE), 0);
+
+        assert_eq!(analog_move_diff(AXIS_RESOLUTION, DEFAULT_DEAD_ZONE), MOVE_SIZE);
+        assert_eq!(analog_move_diff(-AXIS_RESOLUTION, DEFAULT_DEAD_ZONE), -MOVE_SIZE);
+        assert_eq!(analog_move_diff(2 * AXIS_RESOLUTION, DEFAULT_DEAD_ZONE), MOVE_SIZE);
+
+        assert_eq!(analog_move_diff(DEFAULT_DEAD_ZONE + 1, DEFAULT_DEAD_ZONE), 1);
+        assert_eq!(analog_move_diff(AXIS_RESOLUTION / 2, 0), MOVE_SIZE / 2);
+        assert_eq!(analog_move_diff(-AXIS_RESOLUTION / 2, 0), -MOVE_SIZE / 2);
+    }
+
+    #[test]
+    fn test_pointer_mapping() {
+        assert_eq!(canvas_to_playfield_x(0), 0);
+        assert_eq!(canvas_to_playfield_x(MULT as i32 - 1), 0);
+        assert_eq!(canvas_to_playfield_x(MULT as i32), 1);
+        assert_eq!(canvas_to_playfield_x(-10), 0);
+        assert_eq!(canvas_to_playfield_x((WIDTH * MULT) as i32 + 10), WIDTH as i32 - 1);
+
+        let half_width = assets::PLAYER_BITMAP.width as i32 / 2;
+        assert_eq!(pointer_move_diff(100, 102), 2);
+        assert_eq!(pointer_move_diff(100, 0), -MAX_POINTER_SPEED);
+        assert_eq!(pointer_move_diff(100, WIDTH as i32), MAX_POINTER_SPEED);
+        assert_eq!(pointer_move_diff(half_width + 1, 0), -1);
+    }
+
+    // start, then strafe while cycling weapons and shooting
+    fn recorded_inputs(tick: u32) -> [PlayerInput; MAX_PLAYERS] {
+        let flags = match tick {
+            0 => 4,
+            _ => match (tick / 11) % 5 {
+                0 => 1 | 4,
+                1 => 2 | 4,
+                2 => if tick.is_multiple_of(11) { 8 } else { 4 },
+                3 => 2,
+                _ => 1,
+            },
+        };
+        [PlayerInput::from_keys(flags), PlayerInput::from_keys(0)]
+    }
+
+    fn run_recorded_inputs(game: &mut Game, n_ticks: u32) {
+        for tick in 0..n_ticks {
+            game.simulate(&recorded_inputs(tick));
+        }
+    }
+
+    #[test]
+    fn test_checksum_regression() {
+        let mut game = Game::new();
+        run_recorded_inputs(&mut game, 400);
+        assert!(game.game_state == GameState::Playing);
+        // update only if the simulation is meant to behave differently
+        assert_eq!(game.checksum(), 0xCA5C_1C7D);
+    }
+
+    #[test]
+    fn test_checksum_covers_obstacles() {
+        let mut game = Game::new();
+        run_recorded_inputs(&mut game, 1);
+        let checksum = game.checksum();
+        assert_eq!(checksum, game.checksum());
+
+        if let Some(tile) = game.buffer.iter_mut().find(|tile| **tile == Tile::Obstacle) {
+            *tile = Tile::Background;
+        }
+        assert_ne!(checksum, game.checksum());
+    }
+
+    #[test]
+    fn test_rewind_resimulates_same_state() {
+        let mut game = Game::new();
+        let mut checksums = [0; 300];
+        for (tick, checksum) in checksums.iter_mut().enumerate() {
+            game.advance(&recorded_inputs(tick as u32));
+            *checksum = game.checksum();
+        }
+
+        assert_eq!(game.rewind(45), 45);
+        assert_eq!(game.checksum(), checksums[300 - 45 - 1]);
+        for tick in 255..300 {
+            game.advance(&recorded_inputs(tick));
+        }
+        assert_eq!(game.checksum(), checksums[299]);
+
+        // different inputs from the rewound frame on lead somewhere else
+        assert_eq!(game.rewind(20), 20);
+        for _ in 280..300 {
+            game.advance(&[PlayerInput::from_keys(2), PlayerInput::from_keys(0)]);
+        }
+        assert_ne!(game.checksum(), checksums[299]);
+
+        assert_eq!(game.rewind(2 * rewind::MAX_REWIND_FRAMES), rewind::MAX_REWIND_FRAMES);
+    }
+
+    #[test]
+    fn test_games_are_independent() {
+        let mut games = [Game::new(), Game::new(), Game::new()];
+        for tick in 0..200 {
+            games[0].step(recorded_inputs(tick));
+            games[1].step(recorded_inputs(tick));
+        }
+        assert_eq!(games[0].checksum(), games[1].checksum());
+        assert_eq!(games[0].framebuffer, games[1].framebuffer);
+        assert!(games[2].game_state == GameState::StartScreen);
+        assert_ne!(games[0].checksum(), games[2].checksum());
+
+        games[1].step([PlayerInput::from_keys(16), PlayerInput::from_keys(0)]);
+        assert!(!games[0].paused);
+        assert!(games[1].paused);
+    }
+
+    #[test]
+    fn test_native_resolution_matches_scaled_playfield() {
+        let mut games = [Game::new(), Game::new()];
+        games[1].set_render_scale(1);
+        for tick in 0..150 {
+            games[0].step(recorded_inputs(tick));
+            games[1].step(recorded_inputs(tick));
+        }
+        assert_eq!(games[1].framebuffer.len(), WIDTH * (HEIGHT + STATUS_BAR_HEIGHT));
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                assert_eq!(games[1].framebuffer[y * WIDTH + x], games[0].framebuffer[y * MULT * WIDTH * MULT + x * MULT]);
+            }
+        }
+
+        games[1].upscaler = Some(upscale::Upscaler::new(upscale::Mode::IntegerFit, WIDTH * MULT, (HEIGHT + STATUS_BAR_HEIGHT) * MULT));
+        games[1].draw();
+        let (pixels, width, _height) = games[1].output();
+        assert_eq!(width, WIDTH * MULT);
+        assert_eq!(pixels[..HEIGHT * MULT * WIDTH * MULT], games[0].framebuffer[..HEIGHT * MULT * WIDTH * MULT]);
+    }
+
+    #[test]
+    fn test_dirty_rendering_matches_full_redraw() {
+        let mut games = [Game::new(), Game::new()];
+        for tick in 0..120 {
+            games[0].step(recorded_inputs(tick));
+            games[1].advance(&recorded_inputs(tick));
+        }
+        games[0].dirty.clear();
+        games[0].step(recorded_inputs(120));
+        games[1].advance(&recorded_inputs(120));
+        games[1].full_redraw = true;
+        games[1].draw();
+        assert!(games[0].framebuffer == games[1].framebuffer);
+
+        // only a few bullets and enemies moved
+        let dirty_area: u32 = games[0].dirty.rects(1).iter().map(|rect| rect[2] * rect[3]).sum();
+        assert!(dirty_area > 0);
+        assert!(dirty_area < (WIDTH * HEIGHT / 2) as u32);
+
+        games[0].dirty.clear();
+        games[0].draw();
+        assert!(games[0].dirty.is_empty());
+    }
+
+    #[test]
+    fn test_tile_map_repaints_only_what_moved() {
+        let mut games = [Game::new(), Game::new()];
+        games[0].mode = GameMode::Versus;
+        games[1].mode = GameMode::Versus;
+        for tick in 0..400 {
+            // the second game clears the whole map every tick
+            for tile in games[1].buffer.iter_mut() {
+                if *tile != Tile::Obstacle {
+                    *tile = Tile::Background;
+                }
+            }
+            games[1].painted.clear();
+            games[0].advance(&recorded_inputs(tick));
+            games[1].advance(&recorded_inputs(tick));
+            assert!(games[0].buffer == games[1].buffer, "tick {tick}");
+            let painted_area: i32 = games[0].painted.iter().map(|rect| rect.width * rect.height).sum();
+            assert!(painted_area < (WIDTH * HEIGHT / 8) as i32);
+        }
+        // a rewind repaints from scratch
+        assert_eq!(games[0].rewind(50), 50);
+        assert_eq!(games[1].rewind(50), 50);
+        assert!(games[0].buffer == games[1].buffer);
+    }
+
+    #[test]
+    fn test_translucent_player_and_pause_screen() {
+        let mut game = Game::new();
+        for tick in 0..60 {
+            game.step(recorded_inputs(tick));
+        }
+        assert_eq!(game.players[0].opacity, 100);
+        let tile_pixel = |tile| {
+            let idx = game.buffer.iter().position(|it| *it == tile).unwrap();
+            (idx / WIDTH) * MULT * WIDTH * MULT + (idx % WIDTH) * MULT
+        };
+        let pixel = tile_pixel(Tile::Player(0, SPRITE_BODY));
+        let cockpit = tile_pixel(Tile::Player(0, SPRITE_COCKPIT));
+        let frame = game.framebuffer.clone();
+        // sprites are coloured per pixel
+        assert_eq!(frame[pixel], palette::CLASSIC.players[0]);
+        assert_eq!(frame[cockpit], palette::CLASSIC.cockpit);
+
+        // players fading in after a hit are blended over the background
+        game.players[0].opacity = 50;
+        game.draw();
+        let player_color = (palette::CLASSIC.players[0] & 0x00_FF_FF_FF) | (50 * 0xFF / 100) << 24;
+        assert_eq!(game.framebuffer[pixel], compositor::blend(palette::CLASSIC.background, player_color));
+        game.players[0].opacity = 100;
+        game.draw();
+        assert!(game.framebuffer == frame);
+
+        // the help screen dims the game instead of hiding it
+        game.step([PlayerInput::from_keys(16), PlayerInput::from_keys(0)]);
+        assert!(game.paused);
+        let dim = (palette::CLASSIC.pause_background & 0x00_FF_FF_FF) | PAUSE_SCREEN_ALPHA << 24;
+        assert_eq!(game.framebuffer[pixel], compositor::blend(frame[pixel], dim));
+        assert_eq!(game.framebuffer[0], compositor::blend(frame[0], dim));
+    }
+
+    #[test]
+    fn test_particles_are_cosmetic() {
+        let mut game = Game::new();
+        let mut plain = Game::new();
+        // held frames would put the two games out of step
+        game.camera.hit_stop_enabled = false;
+        let mut drew_particles = false;
+        for tick in 0..300 {
+            game.step(recorded_inputs(tick));
+            drew_particles |= !game.drawn_particles.is_empty();
+            plain.advance(&recorded_inputs(tick));
+            plain.particles.clear();
+        }
+        assert!(drew_particles);
+        assert_eq!(game.checksum(), plain.checksum());
+        assert!(game.buffer == plain.buffer);
+    }
+
+    #[test]
+    fn test_shake_and_hit_stop_leave_simulation_alone() {
+        let mut game = Game::new();
+        for tick in 0..60 {
+            game.step(recorded_inputs(tick));
+        }
+        let checksum = game.checksum();
+        let frame = game.framebuffer.clone();
+        let playfield_len = WIDTH * MULT * HEIGHT * MULT;
+
+        game.camera.hit_stop(2);
+        game.step(recorded_inputs(60));
+        game.step(recorded_inputs(61));
+        assert_eq!(game.checksum(), checksum);
+        assert!(game.framebuffer == frame);
+
+        // the playfield moves, the status bar doesn't
+        game.camera.shake(BIG_EVENT_SHAKE);
+        game.draw();
+        assert!(game.framebuffer[..playfield_len] != frame[..playfield_len]);
+        assert!(game.framebuffer[playfield_len..] == frame[playfield_len..]);
+        game.camera.shake_enabled = false;
+        game.draw();
+        assert!(game.framebuffer == frame);
+        assert_eq!(game.checksum(), checksum);
+    }
+
+    #[test]
+    fn test_shake_starts_when_drawn() {
+        let mut game = Game::new();
+        let mut tick = 0;
+        while game.pending_shake == 0 && tick < 3000 {
+            game.advance(&recorded_inputs(tick));
+            tick += 1;
+        }
+        assert!(game.pending_shake > 0);
+        assert!(!game.camera.shaking());
+        game.draw();
+        assert!(game.camera.shaking());
+        assert_eq!(game.pending_shake, 0);
+    }
+
+    #[test]
+    fn test_presses_during_hit_stop_are_kept() {
+        let mut game = Game::new();
+        game.camera.hit_stop(1);
+        game.step([PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+        assert!(game.game_state == GameState::StartScreen);
+        game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        assert!(game.game_state == GameState::Playing);
+
+        // and only once
+        game.camera.hit_stop(1);
+        game.step([PlayerInput::from_keys(16 | 1), PlayerInput::from_keys(0)]);
+        assert!(!game.paused);
+        let pos = game.players[0].pos;
+        game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        assert!(game.paused);
+        assert_eq!(game.players[0].pos, pos);
+        game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        assert!(game.paused);
+    }
+
+    #[test]
+    fn test_starfield_is_cosmetic() {
+        let mut game = Game::new();
+        let mut plain = Game::new();
+        game.set_starfield(true);
+        for tick in 0..60 {
+            game.step(recorded_inputs(tick));
+            plain.step(recorded_inputs(tick));
+        }
+        assert_eq!(game.checksum(), plain.checksum());
+
+        // stars only show through empty tiles
+        let mut n_star_tiles = 0;
+        for (idx, tile) in game.buffer.iter().enumerate() {
+            let pixel = (idx / WIDTH) * MULT * WIDTH * MULT + (idx % WIDTH) * MULT;
+            if game.framebuffer[pixel] != plain.framebuffer[pixel] {
+                assert!(*tile == Tile::Background);
+                n_star_tiles += 1;
+            }
+        }
+        assert!(n_star_tiles > 0);
+
+        game.set_starfield(false);
+        game.draw();
+        assert!(game.framebuffer == plain.framebuffer);
+    }
+
+    #[test]
+    fn test_sounds_follow_the_game() {
+        let hash_audio = |game: &Game| {
+            let mut hash = Fnv1a::new();
+            for sample in game.synth.ring() {
+                hash.write_u32(*sample as u16 as u32);
+            }
+            hash.0
+        };
+        // no sound effects on the start screen
+        let mut game = Game::new();
+        game.music_enabled = false;
+        for _ in 0..10 {
+            game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        }
+        assert_eq!(game.synth.written(), 10 * SAMPLES_PER_TICK as u32);
+        assert!(game.synth.ring().iter().all(|sample| *sample == 0));
+
+        let mut hashes = Vec::new();
+        for _ in 0..2 {
+            let mut game = Game::new();
+            game.camera.hit_stop_enabled = false;
+            for tick in 0..300 {
+                game.step(recorded_inputs(tick));
+            }
+            assert!(game.synth.ring().iter().any(|sample| *sample != 0));
+            hashes.push(hash_audio(&game));
+        }
+        assert_eq!(hashes[0], hashes[1]);
+    }
+
+    #[test]
+    fn test_music_follows_the_game_state() {
+        assert!(music::Songbook::parse(include_str!("../assets/music.trk")).is_ok());
+        let mut game = Game::new();
+        let mut plain = Game::new();
+        plain.music_enabled = false;
+        game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        assert_eq!(game.music.playing(), Some(START_TRACK));
+        for tick in 0..60 {
+            game.step(recorded_inputs(tick));
+            plain.step(recorded_inputs(tick));
+        }
+        assert_eq!(game.music.playing(), Some(GAME_TRACK));
+        assert_eq!(plain.music.playing(), None);
+        assert_eq!(game.checksum(), plain.checksum());
+        // faster as the wave thins out
+        let tempo = game.music.tempo_percent();
+        assert!(tempo > 100);
+        while game.enemies.size() > 2 {
+            game.enemies.remove(0);
+        }
+        game.step(recorded_inputs(60));
+        assert_eq!(game.music.playing(), Some(GAME_TRACK));
+        assert_eq!(game.music.tempo_percent(), 100 + MUSIC_MAX_SPEEDUP * (MAX_ENEMIES as u32 - 2) / MAX_ENEMIES as u32);
+        // down to the boss
+        game.enemies.remove(0);
+        game.step(recorded_inputs(61));
+        assert_eq!(game.music.playing(), Some(BOSS_TRACK));
+        assert_eq!(game.music.tempo_percent(), 100);
+
+        game.players[0].health = 0;
+        game.step(recorded_inputs(62));
+        assert!(game.game_state == GameState::EndScreen(false));
+        game.step([PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        assert_eq!(game.music.playing(), Some(GAME_OVER_TRACK));
+
+        game.step([PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+        game.step([PlayerInput::from_keys(8), PlayerInput::from_keys(0)]);
+        game.step([PlayerInput::from_keys(8), PlayerInput::from_keys(0)]);
+        game.step([PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+        assert!(game.mode == GameMode::Versus);
+        assert_eq!(game.music.playing(), Some(BOSS_TRACK));
+
+        game.music_enabled = false;
+        game.music.stop(&mut game.synth);
+        game.step(recorded_inputs(63));
+        assert_eq!(game.music.playing(), None);
+    }
+
+    #[test]
+    fn test_events_report_gameplay() {
+        let decode = |game: &Game| {
+            game.events.bytes().chunks_exact(events::RECORD_SIZE).filter_map(events::Event::decode).collect::<Vec<(u32, events::Event)>>()
+        };
+        let mut game = Game::new();
+        run_recorded_inputs(&mut game, 400);
+        let recorded = decode(&game);
+        assert_eq!(recorded.len() * events::RECORD_SIZE, game.events.bytes().len());
+        assert_eq!(recorded.first(), Some(&(22, events::Event::WeaponChanged { player: 0, weapon: 1 })));
+        assert!(recorded.contains(&(131, events::Event::EnemyKilled { player: 0, x: 101, y: 64, score: ENEMY_KILL_SCORE })));
+        assert!(recorded.contains(&(241, events::Event::PlayerHit { player: 0, x: 106, y: 144, health: 2 })));
+        assert!(recorded.contains(&(384, events::Event::GameOver { won: false })));
+
+        // replayed frames don't report anything again
+        let mut game = Game::new();
+        for tick in 0..300 {
+            game.advance(&recorded_inputs(tick));
+        }
+        let before = game.events.bytes().to_vec();
+        assert_eq!(game.rewind(120), 120);
+        assert!(game.events.bytes() == &before[..]);
+
+        game.events.clear();
+        game.reset_level();
+        game.game_state = GameState::Playing;
+        game.players[0].weapon = Weapon::Rifle;
+        game.players[0].rifle_ammo = 1;
+        game.players[0].last_shot_in_ticks = 100;
+        game.simulate(&[PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+        assert_eq!(decode(&game), vec![(1, events::Event::AmmoDepleted { player: 0, weapon: 1 })]);
+
+        game.events.clear();
+        while game.enemies.size() > 1 {
+            game.enemies.remove(0);
+        }
+        if let Some(enemy) = game.enemies.get_mut(0) {
+            enemy.health = 0;
+        }
+        game.simulate(&recorded_inputs(3));
+        game.simulate(&recorded_inputs(3));
+        assert_eq!(decode(&game), vec![(2, events::Event::WaveCleared), (3, events::Event::GameOver { won: true })]);
+    }
+
+    #[test]
+    fn test_palette_switch_repaints() {
+        let mut game = Game::new();
+        for tick in 0..60 {
+            game.step(recorded_inputs(tick));
+        }
+        let checksum = game.checksum();
+        let background_idx = game.buffer.iter().position(|tile| *tile == Tile::Background).unwrap();
+        let pixel = (background_idx / WIDTH) * MULT * WIDTH * MULT + (background_idx % WIDTH) * MULT;
+        assert_eq!(game.framebuffer[pixel], palette::CLASSIC.background);
+
+        game.set_palette(palette::NIGHT);
+        game.draw();
+        assert_eq!(game.framebuffer[pixel], palette::NIGHT.background);
+        assert!(!game.framebuffer.contains(&palette::CLASSIC.background));
+
+        // host palettes go through the staging words
+        game.palette_staging[0] = 0xFF_12_34_56;
+        game.set_palette(palette::Palette::from_words(&game.palette_staging));
+        game.draw();
+        assert_eq!(game.framebuffer[pixel], 0xFF_12_34_56);
+        assert_eq!(game.palette.hud_text, palette::NIGHT.hud_text);
+
+        // colours are presentation only
+        assert_eq!(game.checksum(), checksum);
+    }
+
+    #[test]
+    fn test_netplay_loopback_games_stay_in_sync() {
+        let mut games = [Game::new(), Game::new()];
+        let (a, b) = netplay::loopback::loopback_pair();
+        let mut peers = [netplay::Lockstep::new(a, 0, 2), netplay::Lockstep::new(b, 1, 2)];
+
+        // pick co-op on the start screen, start, then move around and shoot
+        let script = |player: usize, tick: u32| -> u32 {
+            match tick {
+                0 if player == 1 => 8,
+                5 if player == 0 => 4,
+                6..=20 => 0,
+                _ => match (tick / 7 + player as u32) % 4 { 0 => 1, 1 => 2 | 4, 2 => 4, _ => 0 },
+            }
+        };
+        const N_TICKS: u32 = 600;
+        let mut frame = 0;
+        while peers[0].current_tick() < N_TICKS || peers[1].current_tick() < N_TICKS {
+            for (player, (game, peer)) in games.iter_mut().zip(peers.iter_mut()).enumerate() {
+                // second peer lags behind every few frames
+                if player == 1 && frame % 3 == 0 {
+                    continue;
+                }
+                peer.add_local_input(script(player, peer.current_tick()));
+                while peer.current_tick() < N_TICKS {
+                    let Some((tick, flags)) = peer.next_inputs() else { break };
+                    game.simulate(&[PlayerInput::from_keys(flags[0]), PlayerInput::from_keys(flags[1])]);
+                    peer.report_checksum(tick, game.checksum());
+                }
+            }
+            frame += 1;
+        }
+        assert!(games[0].mode == GameMode::Coop);
+        assert!(games[0].game_state != GameState::StartScreen);
+        assert_eq!(peers[0].desync_tick(), None);
+        assert_eq!(peers[1].desync_tick(), None);
+        assert_eq!(games[0].checksum(), games[1].checksum());
+    }
+
+    #[test]
+    fn test_net_session_starts_from_a_new_game() {
+        let new_session = || Box::new(netplay::Lockstep::new(netplay::HostTransport::new(), 0, 3));
+        let mut game = Game::new();
+        for tick in 0..200 {
+            game.step(recorded_inputs(tick));
+        }
+        assert!(game.game_state == GameState::Playing);
+        game.start_net(new_session());
+        let mut fresh = Game::new();
+        fresh.start_net(new_session());
+        assert!(game.game_state == GameState::StartScreen);
+        assert_eq!(game.history.frame(), 0);
+        assert!(game.events.bytes().is_empty());
+        assert_eq!(game.checksum(), fresh.checksum());
+        assert!(game.buffer == fresh.buffer);
+    }
+
+    #[test]
+    fn test_no_rewind_during_netplay() {
+        let mut game = Game::new();
+        for tick in 0..100 {
+            game.step(recorded_inputs(tick));
+        }
+        // the session is set up by hand so the history of the local game is still there
+        game.net = Some(Box::new(netplay::Lockstep::new(netplay::HostTransport::new(), 0, 0)));
+        let checksum = game.checksum();
+        assert_eq!(game.rewind(30), 0);
+        assert_eq!(game.checksum(), checksum);
+        game.net = None;
+        assert_eq!(game.rewind(30), 30);
+    }
+
+    #[test]
+    fn test_friendly_fire() {
+        let play = |friendly_fire: bool| {
+            let mut game = Game::new();
+            game.friendly_fire = friendly_fire;
+            // ctrl picks co-op, space starts it
+            game.simulate(&[PlayerInput::from_keys(8), PlayerInput::from_keys(0)]);
+            game.simulate(&[PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+            assert!(game.mode == GameMode::Coop && game.game_state == GameState::Playing);
+            // the ships fly into each other and player 1 fires
+            while game.players[1].pos - game.players[0].pos > MOVE_SIZE {
+                game.simulate(&[PlayerInput::from_keys(2), PlayerInput::from_keys(1)]);
+            }
+            // pistol cooldown
+            for _ in 0..15 {
+                game.simulate(&[PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+            }
+            game.simulate(&[PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+            game
+        };
+        let (game, plain) = (play(true), play(false));
+        assert!(game.players[0].last_shot_in_ticks == 0);
+        assert_eq!(game.players[0].health, plain.players[0].health);
+        assert_eq!(game.players[1].health, plain.players[1].health - 1);
+        assert_eq!(game.bullets.size() + 1, plain.bullets.size());
+    }
+
+    fn new_versus_round() -> Game {
+        let mut game = Game::new();
+        game.mode = GameMode::Versus;
+        game.reset_level();
+        game.game_state = GameState::Playing;
+        // nothing between the formation and player 1
+        game.buffer.fill(Tile::Background);
+        game
+    }
+
+    // player 2 fires from the bottom row, the shot is aimed by moving player 1 under it
+    fn commander_fires_at_player(game: &mut Game) {
+        let no_input = [PlayerInput::from_keys(0), PlayerInput::from_keys(0)];
+        game.commander.selected_enemy = 10;
+        game.commander.last_shot_in_ticks = COMMANDER_SHOT_COOLDOWN_IN_TICKS;
+        let n_bullets = game.bullets.size();
+        game.simulate(&[PlayerInput::from_keys(0), PlayerInput::from_keys(4)]);
+        assert_eq!(game.bullets.size(), n_bullets + 1);
+        if let Some(bullet) = game.bullets.get(n_bullets) {
+            assert!(bullet.owner == BulletOwner::Enemy);
+            game.players[0].pos = bullet.x as i32;
+        }
+        let health = game.players[0].health;
+        for _ in 0..HEIGHT {
+            game.simulate(&no_input);
+            if game.players[0].health < health {
+                break;
+            }
+        }
+        assert_eq!(game.players[0].health, health - 1);
+    }
+
+    #[test]
+    fn test_versus_shots_between_players() {
+        let mut game = new_versus_round();
+        commander_fires_at_player(&mut game);
+        assert!(game.events.bytes().chunks_exact(events::RECORD_SIZE).filter_map(events::Event::decode)
+            .any(|(_, event)| matches!(event, events::Event::PlayerHit { player: 0, .. })));
+        assert!(game.game_state == GameState::Playing);
+
+        // player 1 shoots back at the enemy player 2 picked
+        let (x, n_enemies) = match game.enemies.get(game.commander.selected_enemy) {
+            Some(enemy) => (enemy.x, game.enemies.size()),
+            None => panic!("no enemy selected"),
+        };
+        game.players[0].pos = x as i32;
+        game.players[0].last_shot_in_ticks = 100;
+        // the formation stays put against the edge
+        game.commander.formation_blocked = true;
+        game.simulate(&[PlayerInput::from_keys(4), PlayerInput::from_keys(0)]);
+        for _ in 0..HEIGHT {
+            game.simulate(&[PlayerInput::from_keys(0), PlayerInput::from_keys(0)]);
+        }
+        assert!(game.players[0].score > 0);
+        assert!(game.enemies.size() < n_enemies || game.enemies.iter().any(|enemy| enemy.health < enemy.max_health as i8));
+    }
+
+    #[test]
+    fn test_versus_marker_leaves_bunkers_alone() {
+        let mut game = new_versus_round();
+        let no_input = [PlayerInput::from_keys(0), PlayerInput::from_keys(0)];
+        game.commander.formation_blocked = true;
+        game.simulate(&no_input);
+        let marker = match game.buffer.iter().position(|tile| *tile == Tile::Marker) {
+            Some(marker) => marker,
+            None => panic!("no marker"),
+        };
+        game.buffer[marker] = Tile::Obstacle;
+        game.simulate(&no_input);
+        assert!(game.buffer[marker] == Tile::Obstacle);
+        assert!(game.buffer[marker + 1] == Tile::Marker);
+
+        // still there once the marker moved on
+        game.simulate(&[PlayerInput::from_keys(0), PlayerInput::from_keys(8)]);
+        game.simulate(&no_input);
+        assert!(game.buffer[marker] == Tile::Obstacle);
+        assert!(game.buffer[marker + 1] != Tile::Marker);
+    }
+
+    #[test]
+    fn test_versus_win_conditions() {
+        let no_input = [PlayerInput::from_keys(0), PlayerInput::from_keys(0)];
+
+        // player 2 wins by shooting player 1 down
+        let mut game = new_versus_round();
+        game.players[0].health = 1;
+        commander_fires_at_player(&mut game);
+        assert!(game.game_state == GameState::EndScreen(false));
+
+        // or by reaching the bottom
+        let mut game = new_versus_round();
+        let mut idx = 0;
+        while let Some(enemy) = game.enemies.get_mut(idx) {
+            enemy.x = WIDTH as u8 - 20;
+            enemy.y = HEIGHT as u8 - assets::PLAYER_BITMAP.height as u8;
+            idx += 1;
+        }
+        game.simulate(&no_input);
+        assert!(game.game_state == GameState::EndScreen(false));
+
+        // player 1 wins by clearing the wave
+        let mut game = new_versus_round();
+        let mut idx = 0;
+        while let Some(enemy) = game.enemies.get_mut(idx) {
+            enemy.health = 0;
+            idx += 1;
+        }
+        game.simulate(&no_input);
+        game.simulate(&no_input);
+        assert!(game.game_state == GameState::EndScreen(true));
+    }
+
+    #[test]
+    fn test_versus_next_round_respawns_everything() {
+        let mut game = new_versus_round();
+        let n_enemies = game.enemies.size();
+        game.players[0].health = 1;
+        game.commander.special_cooldown = 0;
+        commander_fires_at_player(&mut game);
+        assert!(game.game_state == GameState::EndScreen(false));
+
+        let space = [PlayerInput::from_keys(4), PlayerInput::from_keys(0)];
+        game.simulate(&space);
+        assert!(game.game_state == GameState::StartScreen);
+        game.simulate(&space);
+        assert!(game.game_state == GameState::Playing);
+        assert!(game.mode == GameMode::Versus);
+        assert!(game.players[0].is_alive() && game.players[0].health == MAX_PLAYER_HEALTH);
+        assert_eq!(game.enemies.size(), n_enemies);
+        assert_eq!(game.commander.special_cooldown, COMMANDER_SPECIAL_COOLDOWN_IN_TICKS);
+        assert!(!game.commander.formation_blocked);
+    }
 }
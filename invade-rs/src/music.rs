@@ -0,0 +1,411 @@
+// Chiptune songs and the sequencer that plays them on the synth's music
+// channels. Songs are written in a small tracker-like text format:
+//
+//   # comment
+//   instrument NAME WAVE ATTACK HOLD RELEASE VOLUME SWEEP
+//   song NAME
+//   tempo BPM
+//   once
+//   pattern NAME
+//   C-4 lead | A-2 bass | ---
+//   order NAME NAME ...
+//
+// Instruments are shared by all songs. WAVE is square, triangle or noise,
+// times are in ms, VOLUME goes up to 255 and SWEEP is the pitch a note ends on
+// in percent of the one it starts on. A song loops unless it says `once`, it
+// plays the patterns listed by `order`, which come after the patterns. Each
+// pattern row has a cell per channel split by `|`, four rows to a beat. A cell
+// is a note and an instrument or `---`, empty cells at the end can be left out.
+// A blank line ends a pattern.
+
+use crate::synth::{self, Synth};
+
+const ROWS_PER_BEAT: u32 = 4;
+const MAX_TEMPO: u32 = 1000;
+// frequencies of the eighth octave in Hz, lower ones are halved from these
+const OCTAVE_8: [u32; 12] = [4186, 4435, 4699, 4978, 5274, 5588, 5920, 6272, 6645, 7040, 7459, 7902];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct SongError {
+    pub line: usize,
+    pub reason: &'static str,
+}
+
+struct Instrument {
+    name: String,
+    // everything but the frequency
+    sound: synth::Effect,
+    sweep: u32,
+}
+
+impl Instrument {
+    fn note(&self, freq: u32) -> synth::Effect {
+        synth::Effect { freq: (freq, freq * self.sweep / 100), ..self.sound }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Cell {
+    freq: u32,
+    instrument: usize,
+}
+
+struct Pattern {
+    name: String,
+    rows: Vec<[Option<Cell>; synth::MUSIC_CHANNELS]>,
+}
+
+struct Song {
+    name: String,
+    // where the song starts, for errors found once it's complete
+    line: usize,
+    tempo: u32,
+    looping: bool,
+    patterns: Vec<Pattern>,
+    // indices into `patterns`
+    order: Vec<usize>,
+}
+
+#[derive(Default)]
+pub struct Songbook {
+    instruments: Vec<Instrument>,
+    songs: Vec<Song>,
+}
+
+impl Songbook {
+    pub fn parse(text: &str) -> Result<Songbook, SongError> {
+        let mut book = Songbook::default();
+        let mut in_pattern = false;
+        for (line_idx, line) in text.lines().enumerate() {
+            let line_no = line_idx + 1;
+            let error = |reason| SongError { line: line_no, reason };
+            let line = line.trim();
+            if line.starts_with('#') {
+                continue;
+            }
+            if line.is_empty() {
+                in_pattern = false;
+                continue;
+            }
+            let mut words = line.split_whitespace();
+            let keyword = words.next().unwrap_or("");
+            let args: Vec<&str> = words.collect();
+            match keyword {
+                "instrument" => {
+                    let instrument = parse_instrument(&args).ok_or(error("expected NAME WAVE ATTACK HOLD RELEASE VOLUME SWEEP"))?;
+                    if book.instrument(&instrument.name).is_some() {
+                        return Err(error("instrument is defined more than once"));
+                    }
+                    book.instruments.push(instrument);
+                    in_pattern = false;
+                },
+                "song" => {
+                    if let Some(song) = book.songs.last() {
+                        song.check()?;
+                    }
+                    let name = match args[..] {
+                        [name] => name,
+                        _ => return Err(error("expected a song name")),
+                    };
+                    if book.songs.iter().any(|song| song.name == name) {
+                        return Err(error("song is defined more than once"));
+                    }
+                    book.songs.push(Song { name: name.to_string(), line: line_no, tempo: 0, looping: true, patterns: Vec::new(), order: Vec::new() });
+                    in_pattern = false;
+                },
+                "tempo" | "once" | "pattern" | "order" => {
+                    let song = book.songs.last_mut().ok_or(error("outside of a song"))?;
+                    match (keyword, &args[..]) {
+                        ("tempo", [bpm]) => {
+                            song.tempo = bpm.parse().ok().filter(|bpm| (1..=MAX_TEMPO).contains(bpm)).ok_or(error("tempo is 1 to 1000 beats per minute"))?;
+                        },
+                        ("once", []) => song.looping = false,
+                        ("pattern", [name]) => {
+                            if song.patterns.iter().any(|pattern| pattern.name == *name) {
+                                return Err(error("pattern is defined more than once"));
+                            }
+                            song.patterns.push(Pattern { name: name.to_string(), rows: Vec::new() });
+                            in_pattern = true;
+                            continue;
+                        },
+                        ("order", names) if !names.is_empty() => {
+                            for name in names {
+                                let idx = song.patterns.iter().position(|pattern| pattern.name == *name).ok_or(error("unknown pattern"))?;
+                                song.order.push(idx);
+                            }
+                        },
+                        _ => return Err(error("wrong number of arguments")),
+                    }
+                    in_pattern = false;
+                },
+                _ => {
+                    let row = book.parse_row(line).ok_or(error("expected up to four cells like C-4 lead or ---"))?;
+                    match book.songs.last_mut().and_then(|song| song.patterns.last_mut()) {
+                        Some(pattern) if in_pattern => pattern.rows.push(row),
+                        _ => return Err(error("row outside of a pattern")),
+                    }
+                },
+            }
+        }
+        if let Some(song) = book.songs.last() {
+            song.check()?;
+        }
+        Ok(book)
+    }
+
+    fn instrument(&self, name: &str) -> Option<usize> {
+        self.instruments.iter().position(|instrument| instrument.name == name)
+    }
+
+    fn song(&self, name: &str) -> Option<usize> {
+        self.songs.iter().position(|song| song.name == name)
+    }
+
+    fn parse_row(&self, line: &str) -> Option<[Option<Cell>; synth::MUSIC_CHANNELS]> {
+        let mut row = [None; synth::MUSIC_CHANNELS];
+        let mut cells = line.split('|');
+        for slot in row.iter_mut() {
+            let mut words = cells.next().unwrap_or("").split_whitespace();
+            *slot = match (words.next(), words.next(), words.next()) {
+                (None, _, _) | (Some("---"), None, _) => None,
+                (Some(note), Some(instrument), None) => Some(Cell { freq: note_freq(note)?, instrument: self.instrument(instrument)? }),
+                _ => return None,
+            };
+        }
+        match cells.next() {
+            Some(_) => None,
+            None => Some(row),
+        }
+    }
+}
+
+impl Song {
+    fn check(&self) -> Result<(), SongError> {
+        let error = |reason| SongError { line: self.line, reason };
+        if self.tempo == 0 {
+            return Err(error("song has no tempo"));
+        }
+        if self.order.is_empty() {
+            return Err(error("song has no order"));
+        }
+        if self.patterns.iter().any(|pattern| pattern.rows.is_empty()) {
+            return Err(error("song has an empty pattern"));
+        }
+        Ok(())
+    }
+}
+
+fn parse_instrument(args: &[&str]) -> Option<Instrument> {
+    let (name, wave, numbers) = match args {
+        [name, wave, numbers @ ..] if numbers.len() == 5 => (name, wave, numbers),
+        _ => return None,
+    };
+    let wave = match *wave {
+        "square" => synth::Wave::Square,
+        "triangle" => synth::Wave::Triangle,
+        "noise" => synth::Wave::Noise,
+        _ => return None,
+    };
+    let numbers = numbers.iter().map(|number| number.parse::<u32>().ok()).collect::<Option<Vec<u32>>>()?;
+    let (attack, hold, release, volume, sweep) = match numbers[..] {
+        [attack, hold, release, volume, sweep] => (attack, hold, release, u8::try_from(volume).ok()?, sweep),
+        _ => return None,
+    };
+    let sound = synth::Effect { wave, freq: (0, 0), attack, hold, release, volume };
+    Some(Instrument { name: name.to_string(), sound, sweep })
+}
+
+// notes are C-4 or C#4, octaves 0 to 8
+fn note_freq(note: &str) -> Option<u32> {
+    let (semitone, accidental, octave) = match note.as_bytes() {
+        [name, accidental, octave] => ("C D EF G A B".find(*name as char)?, *accidental, (*octave as char).to_digit(10)?),
+        _ => return None,
+    };
+    let semitone = match accidental {
+        b'-' => semitone,
+        b'#' if semitone != 4 && semitone != 11 => semitone + 1,
+        _ => return None,
+    };
+    if octave > 8 {
+        return None;
+    }
+    OCTAVE_8.get(semitone).map(|freq| freq >> (8 - octave))
+}
+
+pub struct Sequencer {
+    book: Songbook,
+    song: Option<usize>,
+    order_pos: usize,
+    row: usize,
+    // samples until the next row starts
+    row_left: u32,
+    finished: bool,
+    // the song's tempo is scaled by this
+    tempo_percent: u32,
+}
+
+impl Sequencer {
+    pub fn new(book: Songbook) -> Sequencer {
+        Sequencer { book, song: None, order_pos: 0, row: 0, row_left: 0, finished: false, tempo_percent: 100 }
+    }
+
+    pub fn playing(&self) -> Option<&str> {
+        self.song.and_then(|idx| self.book.songs.get(idx)).map(|song| song.name.as_str())
+    }
+
+    // starts the song from the top, unknown songs are silence
+    pub fn play(&mut self, name: &str, synth: &mut Synth) {
+        self.stop(synth);
+        self.song = self.book.song(name);
+    }
+
+    pub fn stop(&mut self, synth: &mut Synth) {
+        synth.stop_channels();
+        self.song = None;
+        self.order_pos = 0;
+        self.row = 0;
+        self.row_left = 0;
+        self.finished = false;
+    }
+
+    // applies from the next row on
+    pub fn set_tempo_percent(&mut self, percent: u32) {
+        self.tempo_percent = percent.max(1);
+    }
+
+    #[cfg(test)]
+    pub fn tempo_percent(&self) -> u32 {
+        self.tempo_percent
+    }
+
+    // renders `n_samples` of the synth, starting the song's rows on time
+    pub fn render(&mut self, synth: &mut Synth, n_samples: usize) {
+        let mut left = n_samples;
+        while left > 0 {
+            if self.row_left == 0 {
+                self.next_row(synth);
+            }
+            let n = left.min(self.row_left as usize);
+            synth.render(n);
+            left -= n;
+            self.row_left -= n as u32;
+        }
+    }
+
+    fn next_row(&mut self, synth: &mut Synth) {
+        let song = match self.song.and_then(|idx| self.book.songs.get(idx)) {
+            Some(song) if !self.finished => song,
+            _ => {
+                self.row_left = u32::MAX;
+                return;
+            },
+        };
+        let pattern = match song.order.get(self.order_pos).and_then(|idx| song.patterns.get(*idx)) {
+            Some(pattern) => pattern,
+            None => {
+                self.row_left = u32::MAX;
+                return;
+            },
+        };
+        if let Some(row) = pattern.rows.get(self.row) {
+            for (channel, cell) in row.iter().enumerate() {
+                if let Some(cell) = cell {
+                    if let Some(instrument) = self.book.instruments.get(cell.instrument) {
+                        synth.play_channel(channel, &instrument.note(cell.freq));
+                    }
+                }
+            }
+        }
+        self.row += 1;
+        if self.row >= pattern.rows.len() {
+            self.row = 0;
+            self.order_pos += 1;
+            if self.order_pos >= song.order.len() {
+                self.order_pos = 0;
+                self.finished = !song.looping;
+            }
+        }
+        let beats_per_minute = song.tempo as u64 * self.tempo_percent as u64 / 100;
+        let row_len = synth::SAMPLE_RATE as u64 * 60 / (beats_per_minute * ROWS_PER_BEAT as u64).max(1);
+        self.row_left = row_len.clamp(1, u32::MAX as u64) as u32;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SONG: &str = "
+# test song
+instrument beep square 1 10 10 100 100
+instrument drum noise 1 5 10 100 50
+
+song test
+tempo 150
+pattern a
+C-4 beep | ---      | C-7 drum
+---
+A#3 beep
+
+pattern b
+A-4 beep |          |
+order a b a
+";
+
+    #[test]
+    fn test_parse() {
+        let book = Songbook::parse(SONG).unwrap();
+        assert_eq!(book.instruments.len(), 2);
+        let song = &book.songs[0];
+        assert_eq!((song.tempo, song.looping, song.order.clone()), (150, true, vec![0, 1, 0]));
+        assert_eq!(song.patterns[0].rows.len(), 3);
+        let first_row: Vec<Option<(u32, usize)>> = song.patterns[0].rows[0].iter().map(|cell| cell.map(|cell| (cell.freq, cell.instrument))).collect();
+        assert_eq!(first_row, vec![Some((261, 0)), None, Some((2093, 1)), None]);
+        assert_eq!(song.patterns[0].rows[2][0].map(|cell| cell.freq), Some(233));
+        assert_eq!(song.patterns[1].rows[0][0].map(|cell| cell.freq), Some(440));
+        assert_eq!(book.instruments[1].note(2000).freq, (2000, 1000));
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let error = |text: &str| Songbook::parse(text).err();
+        let line = |line, reason| Some(SongError { line, reason });
+        assert_eq!(error("tempo 100"), line(1, "outside of a song"));
+        assert_eq!(error("song a\ntempo 100\npattern p\nC-4 beep\norder p"), line(4, "expected up to four cells like C-4 lead or ---"));
+        assert_eq!(error("instrument x sine 1 1 1 1 100"), line(1, "expected NAME WAVE ATTACK HOLD RELEASE VOLUME SWEEP"));
+        assert_eq!(error("song a\npattern p\n---\norder q"), line(4, "unknown pattern"));
+        assert_eq!(error("song a\ntempo 100\npattern p\n---\n\n---"), line(6, "row outside of a pattern"));
+        assert_eq!(error("song a\npattern p\n---\norder p\nsong b"), line(1, "song has no tempo"));
+        assert!(note_freq("E#4").is_none() && note_freq("H-4").is_none() && note_freq("C-9").is_none());
+    }
+
+    #[test]
+    fn test_rows_follow_the_tempo() {
+        let mut synth = Synth::new();
+        let mut sequencer = Sequencer::new(Songbook::parse(SONG).unwrap());
+        sequencer.play("test", &mut synth);
+        assert_eq!(sequencer.playing(), Some("test"));
+        // 150 beats per minute are 10 rows a second
+        let row_len = synth::SAMPLE_RATE as usize / 10;
+        sequencer.render(&mut synth, 1);
+        assert_eq!((sequencer.order_pos, sequencer.row), (0, 1));
+        sequencer.render(&mut synth, row_len);
+        assert_eq!((sequencer.order_pos, sequencer.row), (0, 2));
+        sequencer.render(&mut synth, 2 * row_len);
+        assert_eq!((sequencer.order_pos, sequencer.row), (2, 0));
+        // twice as fast
+        sequencer.set_tempo_percent(200);
+        sequencer.render(&mut synth, row_len - 1);
+        sequencer.render(&mut synth, row_len);
+        assert_eq!((sequencer.order_pos, sequencer.row), (2, 2));
+        // it loops
+        sequencer.render(&mut synth, row_len / 2);
+        assert_eq!((sequencer.order_pos, sequencer.row, sequencer.finished), (0, 0, false));
+        assert!(synth.ring().iter().any(|sample| *sample != 0));
+
+        sequencer.play("none", &mut synth);
+        assert_eq!(sequencer.playing(), None);
+        sequencer.render(&mut synth, synth::RING_LEN);
+        assert!(synth.ring().iter().all(|sample| *sample == 0));
+    }
+}
@@ -0,0 +1,383 @@
+use crate::MAX_PLAYERS;
+
+// Lockstep netplay for two peers. Every tick is simulated only once inputs of
+// both players are known, local inputs are scheduled `input_delay` ticks ahead
+// to hide the latency. The transport is expected to be reliable and ordered.
+
+pub const PACKET_SIZE: usize = 9;
+pub const MAX_INPUT_DELAY: u32 = 32;
+pub const CHECKSUM_INTERVAL_IN_TICKS: u32 = 30;
+const INPUT_BUFFER_LEN: usize = 128;
+const CHECKSUM_BUFFER_LEN: usize = 16;
+const HOST_BUFFER_SIZE: usize = 4096;
+
+const MSG_INPUT: u8 = 1;
+const MSG_CHECKSUM: u8 = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Message {
+    Input { tick: u32, flags: u32 },
+    Checksum { tick: u32, checksum: u32 },
+}
+
+impl Message {
+    // [kind: u8][tick: u32 le][value: u32 le]
+    pub fn encode(&self) -> [u8; PACKET_SIZE] {
+        let (kind, tick, value) = match *self {
+            Message::Input { tick, flags } => (MSG_INPUT, tick, flags),
+            Message::Checksum { tick, checksum } => (MSG_CHECKSUM, tick, checksum),
+        };
+        let mut packet = [0; PACKET_SIZE];
+        packet[0] = kind;
+        packet[1..5].copy_from_slice(&tick.to_le_bytes());
+        packet[5..9].copy_from_slice(&value.to_le_bytes());
+        packet
+    }
+
+    pub fn decode(packet: &[u8; PACKET_SIZE]) -> Option<Message> {
+        let tick = u32::from_le_bytes([packet[1], packet[2], packet[3], packet[4]]);
+        let value = u32::from_le_bytes([packet[5], packet[6], packet[7], packet[8]]);
+        match packet[0] {
+            MSG_INPUT => Some(Message::Input { tick, flags: value }),
+            MSG_CHECKSUM => Some(Message::Checksum { tick, checksum: value }),
+            _ => None,
+        }
+    }
+}
+
+pub trait Transport {
+    fn send(&mut self, packet: &[u8; PACKET_SIZE]);
+    fn receive(&mut self) -> Option<[u8; PACKET_SIZE]>;
+}
+
+#[derive(Clone, Copy)]
+struct Slot {
+    tick: u32,
+    value: u32,
+    valid: bool,
+}
+
+struct TickRing<const N: usize> {
+    slots: [Slot; N],
+}
+
+impl<const N: usize> TickRing<N> {
+    fn new() -> TickRing<N> {
+        TickRing { slots: [Slot { tick: 0, value: 0, valid: false }; N] }
+    }
+
+    fn get(&self, tick: u32) -> Option<u32> {
+        match self.slots.get(tick as usize % N) {
+            Some(slot) if slot.valid && slot.tick == tick => Some(slot.value),
+            _ => None,
+        }
+    }
+
+    fn set(&mut self, tick: u32, value: u32) {
+        if let Some(slot) = self.slots.get_mut(tick as usize % N) {
+            *slot = Slot { tick, value, valid: true };
+        }
+    }
+}
+
+pub struct Lockstep<T: Transport> {
+    transport: T,
+    local_player: usize,
+    input_delay: u32,
+    current_tick: u32,
+    next_local_tick: u32,
+    inputs: [TickRing<INPUT_BUFFER_LEN>; MAX_PLAYERS],
+    local_checksums: TickRing<CHECKSUM_BUFFER_LEN>,
+    remote_checksums: TickRing<CHECKSUM_BUFFER_LEN>,
+    desync_tick: Option<u32>,
+}
+
+impl<T: Transport> Lockstep<T> {
+    pub fn new(transport: T, local_player: usize, input_delay: u32) -> Lockstep<T> {
+        let input_delay = input_delay.min(MAX_INPUT_DELAY);
+        let mut inputs = [TickRing::new(), TickRing::new()];
+        // nobody can have input for the first ticks, both peers start with empty ones
+        for ring in inputs.iter_mut() {
+            for tick in 0..input_delay {
+                ring.set(tick, 0);
+            }
+        }
+        Lockstep {
+            transport,
+            local_player: local_player.min(MAX_PLAYERS - 1),
+            input_delay,
+            current_tick: 0,
+            next_local_tick: input_delay,
+            inputs,
+            local_checksums: TickRing::new(),
+            remote_checksums: TickRing::new(),
+            desync_tick: None,
+        }
+    }
+
+    pub fn current_tick(&self) -> u32 {
+        self.current_tick
+    }
+
+    pub fn desync_tick(&self) -> Option<u32> {
+        self.desync_tick
+    }
+
+    pub fn transport_mut(&mut self) -> &mut T {
+        &mut self.transport
+    }
+
+    pub fn add_local_input(&mut self, flags: u32) {
+        // don't run further ahead than the input delay while waiting for the peer
+        while self.next_local_tick <= self.current_tick + self.input_delay {
+            let tick = self.next_local_tick;
+            if let Some(ring) = self.inputs.get_mut(self.local_player) {
+                ring.set(tick, flags);
+            }
+            self.transport.send(&Message::Input { tick, flags }.encode());
+            self.next_local_tick += 1;
+        }
+    }
+
+    // returns inputs of all players for the next tick to simulate, if they already arrived
+    pub fn next_inputs(&mut self) -> Option<(u32, [u32; MAX_PLAYERS])> {
+        self.poll();
+        let tick = self.current_tick;
+        let mut flags = [0; MAX_PLAYERS];
+        for (flag, ring) in flags.iter_mut().zip(self.inputs.iter()) {
+            *flag = ring.get(tick)?;
+        }
+        self.current_tick += 1;
+        Some((tick, flags))
+    }
+
+    pub fn report_checksum(&mut self, tick: u32, checksum: u32) {
+        if !tick.is_multiple_of(CHECKSUM_INTERVAL_IN_TICKS) {
+            return;
+        }
+        self.local_checksums.set(tick, checksum);
+        self.transport.send(&Message::Checksum { tick, checksum }.encode());
+        self.compare_checksums(tick);
+    }
+
+    fn poll(&mut self) {
+        let remote_player = 1 - self.local_player;
+        while let Some(packet) = self.transport.receive() {
+            match Message::decode(&packet) {
+                Some(Message::Input { tick, flags }) => {
+                    if let Some(ring) = self.inputs.get_mut(remote_player) {
+                        ring.set(tick, flags);
+                    }
+                },
+                Some(Message::Checksum { tick, checksum }) => {
+                    self.remote_checksums.set(tick, checksum);
+                    self.compare_checksums(tick);
+                },
+                None => (),
+            }
+        }
+    }
+
+    fn compare_checksums(&mut self, tick: u32) {
+        if let (Some(local), Some(remote)) = (self.local_checksums.get(tick), self.remote_checksums.get(tick)) {
+            if local != remote && self.desync_tick.is_none() {
+                self.desync_tick = Some(tick);
+            }
+        }
+    }
+}
+
+// Packets are exchanged with the host through two byte queues in linear memory.
+// Host appends received bytes at `inbox_free_ptr` and commits them, and drains
+// `outbox` after every tick. Nothing may get lost on the way: bytes that don't
+// fit into the inbox wait on the host until the next tick has made room, and
+// the outbox grows while the host can't send.
+pub struct HostTransport {
+    inbox: [u8; HOST_BUFFER_SIZE],
+    inbox_len: usize,
+    inbox_read: usize,
+    outbox: Vec<u8>,
+}
+
+impl HostTransport {
+    pub fn new() -> HostTransport {
+        HostTransport {
+            inbox: [0; HOST_BUFFER_SIZE], inbox_len: 0, inbox_read: 0,
+            outbox: Vec::with_capacity(HOST_BUFFER_SIZE),
+        }
+    }
+
+    pub fn inbox_free_ptr(&mut self) -> *mut u8 {
+        self.compact_inbox();
+        self.inbox.as_mut_ptr().wrapping_add(self.inbox_len)
+    }
+
+    // counts the bytes already read too, inbox_free_ptr moves the rest to the front
+    pub fn inbox_free_len(&self) -> usize {
+        HOST_BUFFER_SIZE - (self.inbox_len - self.inbox_read)
+    }
+
+    pub fn commit_inbox(&mut self, len: usize) {
+        self.inbox_len = (self.inbox_len + len).min(HOST_BUFFER_SIZE);
+    }
+
+    pub fn outbox(&self) -> &[u8] {
+        &self.outbox
+    }
+
+    pub fn clear_outbox(&mut self) {
+        self.outbox.clear();
+    }
+
+    fn compact_inbox(&mut self) {
+        self.inbox.copy_within(self.inbox_read..self.inbox_len, 0);
+        self.inbox_len -= self.inbox_read;
+        self.inbox_read = 0;
+    }
+}
+
+impl Transport for HostTransport {
+    fn send(&mut self, packet: &[u8; PACKET_SIZE]) {
+        self.outbox.extend_from_slice(packet);
+    }
+
+    fn receive(&mut self) -> Option<[u8; PACKET_SIZE]> {
+        if self.inbox_read + PACKET_SIZE > self.inbox_len {
+            return None;
+        }
+        let x = self.inbox.get(self.inbox_read..self.inbox_read + PACKET_SIZE)?;
+        let mut packet = [0; PACKET_SIZE];
+        packet.copy_from_slice(x);
+        self.inbox_read += PACKET_SIZE;
+        Some(packet)
+    }
+}
+
+#[cfg(test)]
+pub mod loopback {
+    use super::*;
+    use std::cell::RefCell;
+    use std::collections::VecDeque;
+    use std::rc::Rc;
+
+    type Queue = Rc<RefCell<VecDeque<[u8; PACKET_SIZE]>>>;
+
+    pub struct Loopback {
+        tx: Queue,
+        rx: Queue,
+    }
+
+    impl Transport for Loopback {
+        fn send(&mut self, packet: &[u8; PACKET_SIZE]) {
+            self.tx.borrow_mut().push_back(*packet);
+        }
+
+        fn receive(&mut self) -> Option<[u8; PACKET_SIZE]> {
+            self.rx.borrow_mut().pop_front()
+        }
+    }
+
+    pub fn loopback_pair() -> (Loopback, Loopback) {
+        let a: Queue = Rc::new(RefCell::new(VecDeque::new()));
+        let b: Queue = Rc::new(RefCell::new(VecDeque::new()));
+        (Loopback { tx: a.clone(), rx: b.clone() }, Loopback { tx: b, rx: a })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::loopback::loopback_pair;
+
+    #[test]
+    fn test_message_roundtrip() {
+        let msg = Message::Input { tick: 0x01020304, flags: 0xA5 };
+        assert_eq!(Message::decode(&msg.encode()), Some(msg));
+        let msg = Message::Checksum { tick: 7, checksum: 0xDEADBEEF };
+        assert_eq!(Message::decode(&msg.encode()), Some(msg));
+        assert_eq!(Message::decode(&[0; PACKET_SIZE]), None);
+    }
+
+    #[test]
+    fn test_waits_for_remote_input() {
+        let (a, b) = loopback_pair();
+        let mut peer0 = Lockstep::new(a, 0, 2);
+        let mut peer1 = Lockstep::new(b, 1, 2);
+
+        peer0.add_local_input(1);
+        assert_eq!(peer0.next_inputs(), Some((0, [0, 0])));
+        assert_eq!(peer0.next_inputs(), Some((1, [0, 0])));
+        assert_eq!(peer0.next_inputs(), None);
+
+        peer1.add_local_input(2);
+        assert_eq!(peer0.next_inputs(), Some((2, [1, 2])));
+        assert_eq!(peer1.next_inputs(), Some((0, [0, 0])));
+        assert_eq!(peer1.next_inputs(), Some((1, [0, 0])));
+        assert_eq!(peer1.next_inputs(), Some((2, [1, 2])));
+        assert_eq!(peer1.next_inputs(), None);
+    }
+
+    #[test]
+    fn test_detects_desync() {
+        let (a, b) = loopback_pair();
+        let mut peer0 = Lockstep::new(a, 0, 0);
+        let mut peer1 = Lockstep::new(b, 1, 0);
+
+        peer0.report_checksum(0, 10);
+        peer1.report_checksum(0, 10);
+        peer0.report_checksum(CHECKSUM_INTERVAL_IN_TICKS, 11);
+        peer1.report_checksum(CHECKSUM_INTERVAL_IN_TICKS, 12);
+        peer0.poll();
+        peer1.poll();
+        assert_eq!(peer0.desync_tick(), Some(CHECKSUM_INTERVAL_IN_TICKS));
+        assert_eq!(peer1.desync_tick(), Some(CHECKSUM_INTERVAL_IN_TICKS));
+    }
+
+    #[test]
+    fn test_host_transport() {
+        let mut transport = HostTransport::new();
+        transport.send(&Message::Input { tick: 3, flags: 4 }.encode());
+        assert_eq!(transport.outbox().len(), PACKET_SIZE);
+
+        let packet = Message::Checksum { tick: 1, checksum: 2 }.encode();
+        let inbox = transport.inbox_free_ptr();
+        unsafe { inbox.copy_from(packet.as_ptr(), 5) };
+        transport.commit_inbox(5);
+        assert_eq!(transport.receive(), None);
+
+        let inbox = transport.inbox_free_ptr();
+        unsafe { inbox.copy_from(packet.as_ptr().wrapping_add(5), PACKET_SIZE - 5) };
+        transport.commit_inbox(PACKET_SIZE - 5);
+        assert_eq!(transport.receive(), Some(packet));
+        assert_eq!(transport.receive(), None);
+    }
+
+    #[test]
+    fn test_host_transport_keeps_every_packet() {
+        let mut transport = HostTransport::new();
+        let n_packets = 2 * HOST_BUFFER_SIZE / PACKET_SIZE;
+        for tick in 0..n_packets as u32 {
+            transport.send(&Message::Input { tick, flags: 0 }.encode());
+        }
+        let ticks: Vec<u32> = transport.outbox().chunks_exact(PACKET_SIZE)
+            .filter_map(|packet| Message::decode(packet.try_into().ok()?))
+            .map(|msg| match msg {
+                Message::Input { tick, .. } => tick,
+                Message::Checksum { tick, .. } => tick,
+            })
+            .collect();
+        assert_eq!(ticks, (0..n_packets as u32).collect::<Vec<u32>>());
+        transport.clear_outbox();
+        assert!(transport.outbox().is_empty());
+
+        // read bytes count as free before the inbox is compacted
+        let packet = Message::Input { tick: 1, flags: 2 }.encode();
+        while transport.inbox_free_len() >= PACKET_SIZE {
+            let inbox = transport.inbox_free_ptr();
+            unsafe { inbox.copy_from(packet.as_ptr(), PACKET_SIZE) };
+            transport.commit_inbox(PACKET_SIZE);
+        }
+        assert_eq!(transport.receive(), Some(packet));
+        assert_eq!(transport.inbox_free_len(), HOST_BUFFER_SIZE % PACKET_SIZE + PACKET_SIZE);
+    }
+}
@@ -0,0 +1,241 @@
+// Every colour the renderer uses, by role. Colours are 0xAA_BB_GG_RR, the
+// byte order of the RGBA framebuffer.
+
+pub const N_WORDS: usize = 24;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Palette {
+    pub background: u32,
+    pub players: [u32; 2],
+    // enemies fade from the first colour at full health to the second
+    pub enemy_ramp: [u32; 2],
+    pub bullet: u32,
+    pub obstacle: u32,
+    pub marker: u32,
+    pub hud_background: u32,
+    pub hud_text: u32,
+    // indexed by health - 1
+    pub health: [u32; 3],
+    pub health_empty: u32,
+    pub start_background: u32,
+    pub pause_background: u32,
+    pub win_background: u32,
+    pub lose_background: u32,
+    pub menu_text: u32,
+    // shadows and outlines of menu titles
+    pub menu_shadow: u32,
+    // sprite parts, the rest of a sprite is in the player or enemy colour
+    pub cockpit: u32,
+    pub engine: u32,
+    pub outline: u32,
+    // nearest stars of the starfield, farther ones fade into the background
+    pub star: u32,
+}
+
+pub const CLASSIC: Palette = Palette {
+    background: 0xFF_FF_FF_FF,
+    players: [0xFF_00_00_FF, 0xFF_FF_00_00],
+    enemy_ramp: [0xFF_00_00_00, 0xFF_FF_FF_FF],
+    bullet: 0xFF_80_80_80,
+    obstacle: 0xFF_E0_E0_E0,
+    marker: 0xFF_FF_00_00,
+    hud_background: 0xFF_FF_FF_FF,
+    hud_text: 0xFF_00_00_00,
+    health: [0xFF_10_10_FF, 0xFF_10_A0_FF, 0xFF_10_FF_10],
+    health_empty: 0xFF_FF_FF_FF,
+    start_background: 0xFF_88_88_88,
+    pause_background: 0xFF_AA_AA_AA,
+    win_background: 0xFF_00_88_00,
+    lose_background: 0xFF_00_00_88,
+    menu_text: 0xFF_00_00_00,
+    menu_shadow: 0xFF_FF_FF_FF,
+    cockpit: 0xFF_FF_C0_40,
+    engine: 0xFF_00_A0_FF,
+    outline: 0xFF_00_00_00,
+    star: 0xFF_A0_A0_A0,
+};
+
+pub const NIGHT: Palette = Palette {
+    background: 0xFF_20_10_10,
+    players: [0xFF_40_C0_FF, 0xFF_FF_C0_40],
+    enemy_ramp: [0xFF_60_FF_60, 0xFF_20_30_20],
+    bullet: 0xFF_C0_C0_C0,
+    obstacle: 0xFF_60_50_50,
+    marker: 0xFF_FF_C0_40,
+    hud_background: 0xFF_10_08_08,
+    hud_text: 0xFF_E0_E0_E0,
+    health: [0xFF_30_30_E0, 0xFF_30_A0_E0, 0xFF_30_E0_30],
+    health_empty: 0xFF_30_20_20,
+    start_background: 0xFF_30_18_18,
+    pause_background: 0xFF_40_28_28,
+    win_background: 0xFF_10_50_10,
+    lose_background: 0xFF_10_10_50,
+    menu_text: 0xFF_E0_E0_E0,
+    menu_shadow: 0xFF_00_00_00,
+    cockpit: 0xFF_FF_FF_C0,
+    engine: 0xFF_20_60_FF,
+    outline: 0xFF_90_70_70,
+    star: 0xFF_FF_F0_E0,
+};
+
+// four shades of green, like an old handheld
+pub const HANDHELD: Palette = Palette {
+    background: 0xFF_0F_BC_9B,
+    players: [0xFF_0F_38_0F, 0xFF_30_62_30],
+    enemy_ramp: [0xFF_0F_38_0F, 0xFF_0F_AC_8B],
+    bullet: 0xFF_30_62_30,
+    obstacle: 0xFF_0F_AC_8B,
+    marker: 0xFF_0F_38_0F,
+    hud_background: 0xFF_0F_AC_8B,
+    hud_text: 0xFF_0F_38_0F,
+    health: [0xFF_0F_38_0F, 0xFF_0F_38_0F, 0xFF_0F_38_0F],
+    health_empty: 0xFF_0F_BC_9B,
+    start_background: 0xFF_0F_AC_8B,
+    pause_background: 0xFF_0F_AC_8B,
+    win_background: 0xFF_0F_BC_9B,
+    lose_background: 0xFF_30_62_30,
+    menu_text: 0xFF_0F_38_0F,
+    menu_shadow: 0xFF_0F_BC_9B,
+    cockpit: 0xFF_0F_AC_8B,
+    engine: 0xFF_0F_AC_8B,
+    outline: 0xFF_0F_38_0F,
+    star: 0xFF_30_62_30,
+};
+
+pub const HIGH_CONTRAST: Palette = Palette {
+    background: 0xFF_00_00_00,
+    players: [0xFF_00_FF_FF, 0xFF_FF_00_FF],
+    enemy_ramp: [0xFF_FF_FF_FF, 0xFF_80_80_80],
+    bullet: 0xFF_00_FF_FF,
+    obstacle: 0xFF_FF_00_00,
+    marker: 0xFF_FF_00_FF,
+    hud_background: 0xFF_00_00_00,
+    hud_text: 0xFF_FF_FF_FF,
+    health: [0xFF_00_00_FF, 0xFF_00_FF_FF, 0xFF_00_FF_00],
+    health_empty: 0xFF_40_40_40,
+    start_background: 0xFF_00_00_00,
+    pause_background: 0xFF_00_00_00,
+    win_background: 0xFF_00_80_00,
+    lose_background: 0xFF_00_00_80,
+    menu_text: 0xFF_FF_FF_FF,
+    menu_shadow: 0xFF_80_80_80,
+    cockpit: 0xFF_FF_FF_FF,
+    engine: 0xFF_00_FF_FF,
+    outline: 0xFF_FF_FF_FF,
+    star: 0xFF_FF_FF_FF,
+};
+
+pub const THEMES: [Palette; 4] = [CLASSIC, NIGHT, HANDHELD, HIGH_CONTRAST];
+
+impl Palette {
+    // grey level of an enemy tile mapped onto the ramp, 0 is full health
+    pub fn enemy(&self, level: u8) -> u32 {
+        mix(self.enemy_ramp[0], self.enemy_ramp[1], level)
+    }
+
+    // flat layout the host writes palettes in, fields in declaration order
+    pub fn to_words(self) -> [u32; N_WORDS] {
+        [
+            self.background,
+            self.players[0], self.players[1],
+            self.enemy_ramp[0], self.enemy_ramp[1],
+            self.bullet,
+            self.obstacle,
+            self.marker,
+            self.hud_background,
+            self.hud_text,
+            self.health[0], self.health[1], self.health[2],
+            self.health_empty,
+            self.start_background,
+            self.pause_background,
+            self.win_background,
+            self.lose_background,
+            self.menu_text,
+            self.menu_shadow,
+            self.cockpit,
+            self.engine,
+            self.outline,
+            self.star,
+        ]
+    }
+
+    // Alpha bytes are ignored, every colour of a palette is opaque. Translucent
+    // parts of a frame take their alpha from the renderer.
+    pub fn from_words(words: &[u32; N_WORDS]) -> Palette {
+        let [background, player0, player1, enemy0, enemy1, bullet, obstacle, marker, hud_background, hud_text,
+             health0, health1, health2, health_empty, start_background, pause_background, win_background,
+             lose_background, menu_text, menu_shadow, cockpit, engine, outline, star] = words.map(|word| word | 0xFF_00_00_00);
+        Palette {
+            background,
+            players: [player0, player1],
+            enemy_ramp: [enemy0, enemy1],
+            bullet,
+            obstacle,
+            marker,
+            hud_background,
+            hud_text,
+            health: [health0, health1, health2],
+            health_empty,
+            start_background,
+            pause_background,
+            win_background,
+            lose_background,
+            menu_text,
+            menu_shadow,
+            cockpit,
+            engine,
+            outline,
+            star,
+        }
+    }
+}
+
+// every channel, alpha too, from `from` at 0 to `to` at 255
+pub fn mix(from: u32, to: u32, level: u8) -> u32 {
+    let (from, to) = (from.to_le_bytes(), to.to_le_bytes());
+    let mut color = [0; 4];
+    for (channel, (from, to)) in color.iter_mut().zip(from.iter().zip(to.iter())) {
+        let (from, to) = (*from as i32, *to as i32);
+        *channel = (from + (to - from) * level as i32 / 255) as u8;
+    }
+    u32::from_le_bytes(color)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words_roundtrip() {
+        for theme in THEMES.iter() {
+            assert_eq!(Palette::from_words(&theme.to_words()), *theme);
+        }
+        // a host palette can't make anything translucent
+        let mut words = CLASSIC.to_words();
+        words[0] = 0x00_20_40_60;
+        words[N_WORDS - 1] = 0x80_20_40_60;
+        let palette = Palette::from_words(&words);
+        assert_eq!(palette.background, 0xFF_20_40_60);
+        assert_eq!(palette.star, 0xFF_20_40_60);
+    }
+
+    #[test]
+    fn test_sprites_stand_out() {
+        // damaged enemies may fade into the background, healthy ones don't
+        for theme in THEMES.iter() {
+            let sprite_colors = [theme.players[0], theme.players[1], theme.enemy_ramp[0], theme.cockpit, theme.engine, theme.outline];
+            assert!(!sprite_colors.contains(&theme.background), "{:08X?}", theme);
+        }
+    }
+
+    #[test]
+    fn test_enemy_ramp() {
+        // classic ramp is the plain grey scale
+        for level in [0, 1, 100, 254, 255] {
+            let grey = level as u32;
+            assert_eq!(CLASSIC.enemy(level), 0xFF_00_00_00 | grey << 16 | grey << 8 | grey);
+        }
+        assert_eq!(NIGHT.enemy(0), NIGHT.enemy_ramp[0]);
+        assert_eq!(NIGHT.enemy(255), NIGHT.enemy_ramp[1]);
+    }
+}
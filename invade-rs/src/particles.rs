@@ -0,0 +1,171 @@
+// Cosmetic particles for explosions, debris and muzzle flashes. They aren't
+// part of the simulation: they have their own random numbers, are left out of
+// snapshots and checksums and never touch the tile map.
+
+use crate::palette;
+use crate::rng::Rng;
+use crate::static_allocator::{StaticAllocator, SVector};
+use crate::SUBPIXELS;
+
+// small enough for 48 of them to fit in one static allocation
+#[derive(Clone, Copy)]
+struct Particle {
+    // position and velocity in subpixels
+    x: i16,
+    y: i16,
+    vx: i8,
+    vy: i8,
+    // added to vy every tick
+    gravity: i8,
+    age: u8,
+    lifetime: u8,
+    // index into the colour ramps passed to `for_each`
+    color: u8,
+}
+
+// what an emitter sends out at once
+#[derive(Clone, Copy)]
+pub struct Burst {
+    pub count: u32,
+    // in subpixels, every particle gets up to `spread` more or less in both directions
+    pub velocity: (i32, i32),
+    pub spread: i32,
+    pub gravity: i32,
+    // particles live between half of this and all of it, in ticks
+    pub lifetime: u8,
+    pub color: u8,
+}
+
+pub struct Particles {
+    particles: SVector<Particle>,
+    capacity: usize,
+    rng: Rng,
+}
+
+impl Particles {
+    /// # Safety
+    ///
+    /// Same as `SVector::new`, the allocator has to stay put and outlive the particles.
+    pub unsafe fn new(allocator: &mut StaticAllocator, capacity: usize) -> Particles {
+        Particles { particles: SVector::new(allocator, capacity), capacity, rng: Rng::new(0) }
+    }
+
+    pub fn clear(&mut self) {
+        self.particles.reset();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.particles.size() == 0
+    }
+
+    // at `x`, `y` in playfield pixels, particles that don't fit are dropped
+    pub fn emit(&mut self, x: i32, y: i32, burst: &Burst) {
+        for _ in 0..burst.count {
+            if self.particles.size() >= self.capacity {
+                return;
+            }
+            let vx = burst.velocity.0 + self.rng.below(2 * burst.spread + 1) - burst.spread;
+            let vy = burst.velocity.1 + self.rng.below(2 * burst.spread + 1) - burst.spread;
+            let min_lifetime = (burst.lifetime / 2).max(1);
+            let lifetime = min_lifetime + self.rng.below((burst.lifetime - min_lifetime) as i32 + 1) as u8;
+            self.particles.push_back(Particle {
+                x: clamp_i16(x * SUBPIXELS + SUBPIXELS / 2),
+                y: clamp_i16(y * SUBPIXELS + SUBPIXELS / 2),
+                vx: clamp_i8(vx),
+                vy: clamp_i8(vy),
+                gravity: clamp_i8(burst.gravity),
+                age: 0,
+                lifetime,
+                color: burst.color,
+            });
+        }
+    }
+
+    // moves every particle by a tick and drops the expired ones
+    pub fn update(&mut self) {
+        let mut idx = self.particles.size() as isize - 1;
+        while idx >= 0 {
+            if let Some(particle) = self.particles.get_mut(idx as usize) {
+                particle.age += 1;
+                if particle.age >= particle.lifetime {
+                    self.particles.remove(idx as usize);
+                } else {
+                    particle.x = particle.x.saturating_add(particle.vx as i16);
+                    particle.y = particle.y.saturating_add(particle.vy as i16);
+                    particle.vy = particle.vy.saturating_add(particle.gravity);
+                }
+            }
+            idx -= 1;
+        }
+    }
+
+    // Calls `plot` with the playfield position and colour of every particle.
+    // Colours fade along `ramps[color]` over a particle's lifetime.
+    pub fn for_each(&self, ramps: &[[u32; 2]], mut plot: impl FnMut(i32, i32, u32)) {
+        for particle in self.particles.iter() {
+            if let Some([from, to]) = ramps.get(particle.color as usize) {
+                let level = particle.age as u32 * 255 / particle.lifetime.max(1) as u32;
+                let color = palette::mix(*from, *to, level as u8);
+                plot((particle.x as i32).div_euclid(SUBPIXELS), (particle.y as i32).div_euclid(SUBPIXELS), color);
+            }
+        }
+    }
+}
+
+fn clamp_i8(val: i32) -> i8 {
+    val.clamp(i8::MIN as i32, i8::MAX as i32) as i8
+}
+
+fn clamp_i16(val: i32) -> i16 {
+    val.clamp(i16::MIN as i32, i16::MAX as i32) as i16
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(particles: &Particles) -> Vec<(i32, i32, u32)> {
+        let mut out = Vec::new();
+        particles.for_each(&[[0xFF_00_00_FF, 0x00_00_00_FF]], |x, y, color| out.push((x, y, color)));
+        out
+    }
+
+    #[test]
+    fn test_motion_gravity_and_fade() {
+        let mut memory = Box::new(StaticAllocator::new());
+        let mut particles = unsafe { Particles::new(&mut memory, 4) };
+        let burst = Burst { count: 1, velocity: (SUBPIXELS, -2 * SUBPIXELS), spread: 0, gravity: SUBPIXELS, lifetime: 4, color: 0 };
+        particles.emit(10, 20, &burst);
+        // no spread, so only the lifetime is random
+        let lifetime = particles.particles.get(0).unwrap().lifetime as u32;
+        assert!((2..=4).contains(&lifetime));
+        assert_eq!(positions(&particles), [(10, 20, 0xFF_00_00_FF)]);
+        particles.update();
+        assert_eq!(positions(&particles), [(11, 18, palette::mix(0xFF_00_00_FF, 0x00_00_00_FF, (255 / lifetime) as u8))]);
+        if lifetime > 2 {
+            particles.update();
+            // gravity has slowed it down
+            let (x, y, _) = positions(&particles)[0];
+            assert_eq!((x, y), (12, 17));
+        }
+        for _ in 0..4 {
+            particles.update();
+        }
+        assert!(particles.is_empty());
+    }
+
+    #[test]
+    fn test_capacity() {
+        let mut memory = Box::new(StaticAllocator::new());
+        let mut particles = unsafe { Particles::new(&mut memory, 5) };
+        let burst = Burst { count: 3, velocity: (0, 0), spread: 4, gravity: 0, lifetime: 10, color: 0 };
+        particles.emit(0, 0, &burst);
+        particles.emit(0, 0, &burst);
+        // the second burst only partly fits
+        assert_eq!(positions(&particles).len(), 5);
+        particles.clear();
+        // colours without a ramp aren't drawn
+        particles.emit(0, 0, &Burst { color: 1, ..burst });
+        assert!(positions(&particles).is_empty());
+    }
+}
@@ -0,0 +1,127 @@
+// Recent simulation history: a snapshot every few frames plus the inputs of
+// every frame, so any frame in the window can be rebuilt by restoring the
+// closest earlier snapshot and simulating the recorded inputs on top of it.
+
+pub const SNAPSHOT_INTERVAL_IN_FRAMES: u32 = 10;
+// ~5 seconds at 30 ms per frame
+pub const MAX_REWIND_FRAMES: u32 = 170;
+const N_SNAPSHOTS: usize = (MAX_REWIND_FRAMES / SNAPSHOT_INTERVAL_IN_FRAMES) as usize + 2;
+const N_INPUTS: usize = (MAX_REWIND_FRAMES + SNAPSHOT_INTERVAL_IN_FRAMES) as usize;
+
+pub struct History<S: Clone, I: Copy> {
+    frame: u32,
+    snapshots: Vec<Option<(u32, S)>>,
+    inputs: Vec<Option<(u32, I)>>,
+}
+
+impl<S: Clone, I: Copy> History<S, I> {
+    pub fn new() -> History<S, I> {
+        let mut snapshots = Vec::with_capacity(N_SNAPSHOTS);
+        snapshots.resize_with(N_SNAPSHOTS, || None);
+        History { frame: 0, snapshots, inputs: vec![None; N_INPUTS] }
+    }
+
+    pub fn frame(&self) -> u32 {
+        self.frame
+    }
+
+    pub fn needs_snapshot(&self) -> bool {
+        self.frame.is_multiple_of(SNAPSHOT_INTERVAL_IN_FRAMES)
+    }
+
+    pub fn save_snapshot(&mut self, snapshot: S) {
+        let slot_idx = (self.frame / SNAPSHOT_INTERVAL_IN_FRAMES) as usize % N_SNAPSHOTS;
+        if let Some(slot) = self.snapshots.get_mut(slot_idx) {
+            *slot = Some((self.frame, snapshot));
+        }
+    }
+
+    // stores input of the current frame and moves to the next one
+    pub fn record(&mut self, input: I) {
+        if let Some(slot) = self.inputs.get_mut(self.frame as usize % N_INPUTS) {
+            *slot = Some((self.frame, input));
+        }
+        self.frame = self.frame.wrapping_add(1);
+    }
+
+    pub fn input(&self, frame: u32) -> Option<I> {
+        match self.inputs.get(frame as usize % N_INPUTS) {
+            Some(Some((recorded_frame, input))) if *recorded_frame == frame => Some(*input),
+            _ => None,
+        }
+    }
+
+    // latest snapshot at or before `target` from which `target` can be re-simulated
+    pub fn snapshot_before(&self, target: u32) -> Option<(u32, S)> {
+        if target > self.frame || self.frame - target > MAX_REWIND_FRAMES {
+            return None;
+        }
+        let snapshot_frame = target - target % SNAPSHOT_INTERVAL_IN_FRAMES;
+        let slot_idx = (snapshot_frame / SNAPSHOT_INTERVAL_IN_FRAMES) as usize % N_SNAPSHOTS;
+        let (frame, snapshot) = match self.snapshots.get(slot_idx) {
+            Some(Some((frame, snapshot))) if *frame == snapshot_frame => (*frame, snapshot),
+            _ => return None,
+        };
+        if (frame..target).any(|frame| self.input(frame).is_none()) {
+            return None;
+        }
+        Some((frame, snapshot.clone()))
+    }
+
+    // frames after `frame` are forgotten, new inputs will overwrite them
+    pub fn set_frame(&mut self, frame: u32) {
+        self.frame = frame;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_before() {
+        let mut history: History<u32, u32> = History::new();
+        for frame in 0..25 {
+            if history.needs_snapshot() {
+                history.save_snapshot(frame * 100);
+            }
+            history.record(frame);
+        }
+        assert_eq!(history.frame(), 25);
+        assert_eq!(history.snapshot_before(25), Some((20, 2000)));
+        assert_eq!(history.snapshot_before(19), Some((10, 1000)));
+        assert_eq!(history.snapshot_before(0), Some((0, 0)));
+        assert_eq!(history.snapshot_before(26), None);
+        assert_eq!(history.input(7), Some(7));
+        assert_eq!(history.input(25), None);
+    }
+
+    #[test]
+    fn test_window_is_limited() {
+        let mut history: History<u32, u32> = History::new();
+        for frame in 0..(3 * MAX_REWIND_FRAMES) {
+            if history.needs_snapshot() {
+                history.save_snapshot(frame);
+            }
+            history.record(frame);
+        }
+        let frame = history.frame();
+        assert!(history.snapshot_before(frame - MAX_REWIND_FRAMES).is_some());
+        assert!(history.snapshot_before(frame - MAX_REWIND_FRAMES - 1).is_none());
+    }
+
+    #[test]
+    fn test_rewound_frames_are_forgotten() {
+        let mut history: History<u32, u32> = History::new();
+        for frame in 0..15 {
+            if history.needs_snapshot() {
+                history.save_snapshot(frame);
+            }
+            history.record(frame);
+        }
+        history.set_frame(5);
+        assert_eq!(history.snapshot_before(12), None);
+        history.record(50);
+        assert_eq!(history.input(5), Some(50));
+    }
+}
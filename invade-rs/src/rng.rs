@@ -0,0 +1,40 @@
+// Random numbers for things that are only for show. Gameplay draws from the
+// seed in the game state, so cosmetic streams can be added, removed or used
+// more or less often without changing the simulation.
+
+pub struct Rng {
+    seed: u32,
+}
+
+impl Rng {
+    pub fn new(seed: u32) -> Rng {
+        Rng { seed }
+    }
+
+    // same LCG as the gameplay stream, the low bits are too regular to use
+    pub fn next_u32(&mut self) -> u32 {
+        self.seed = self.seed.wrapping_mul(1664525).wrapping_add(1013904223);
+        self.seed >> 16
+    }
+
+    // in 0..range, 0 for empty ranges
+    pub fn below(&mut self, range: i32) -> i32 {
+        let value = self.next_u32();
+        if range <= 0 { 0 } else { (value % range as u32) as i32 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_streams_are_independent() {
+        let (mut first, mut second) = (Rng::new(7), Rng::new(7));
+        let values: Vec<i32> = (0..100).map(|_| first.below(10)).collect();
+        assert!(values.iter().all(|value| (0..10).contains(value)));
+        assert!(values.iter().any(|value| *value != values[0]));
+        assert_eq!(values, (0..100).map(|_| second.below(10)).collect::<Vec<i32>>());
+        assert_eq!(first.below(0), 0);
+    }
+}
@@ -0,0 +1,276 @@
+// Sprites and the transforms they can be drawn with. Sprites end up in
+// the simulation's tile buffer, so everything here is integer maths and comes
+// out the same on every platform.
+
+// 1 bit per pixel, rows are read MSB first, bits past `width` are ignored
+pub struct Bitmap2D {
+    pub width: u32,
+    pub height: u32,
+    pub bitmap: &'static [u16],
+}
+
+// Palette indices packed `bits_per_pixel` (2 or 4) to a byte, leftmost pixel
+// in the top bits. Every row starts on a new byte, so any width works.
+pub struct IndexedBitmap {
+    pub width: u32,
+    pub height: u32,
+    pub bits_per_pixel: u32,
+    pub pixels: &'static [u8],
+}
+
+pub trait Sprite {
+    fn width(&self) -> u32;
+    fn height(&self) -> u32;
+    // palette index of a pixel, 0 is transparent and so is anything outside
+    fn index(&self, x: i32, y: i32) -> u8;
+}
+
+impl Sprite for Bitmap2D {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: i32, y: i32) -> u8 {
+        if x < 0 || y < 0 || x >= (self.width as i32).min(16) || y >= self.height as i32 {
+            return 0;
+        }
+        match self.bitmap.get(y as usize) {
+            Some(row) => (row & (0x8000 >> x) != 0) as u8,
+            None => 0,
+        }
+    }
+}
+
+impl Sprite for IndexedBitmap {
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn index(&self, x: i32, y: i32) -> u8 {
+        let bits = self.bits_per_pixel as usize;
+        if x < 0 || y < 0 || x >= self.width as i32 || y >= self.height as i32 || !matches!(bits, 1 | 2 | 4 | 8) {
+            return 0;
+        }
+        let stride = (self.width as usize * bits).div_ceil(8);
+        let bit = x as usize * bits;
+        match self.pixels.get(y as usize * stride + bit / 8) {
+            Some(byte) => (byte >> (8 - bits - bit % 8)) & (0xFF >> (8 - bits)),
+            None => 0,
+        }
+    }
+}
+
+// Applied in field order: flips, quarter turns, scaling and then the free
+// rotation around the centre, which samples the nearest pixel.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Transform {
+    pub flip_x: bool,
+    pub flip_y: bool,
+    // clockwise
+    pub quarter_turns: u8,
+    pub scale: u32,
+    // clockwise in 1/256 of a turn
+    pub angle: u8,
+}
+
+impl Transform {
+    pub const IDENTITY: Transform = Transform { flip_x: false, flip_y: false, quarter_turns: 0, scale: 1, angle: 0 };
+}
+
+const ONE: i32 = 1 << 14;
+// sin of the first quarter turn in 1/256 turns, times ONE
+const QUARTER_SINE: [i32; 65] = [
+    0, 402, 804, 1205, 1606, 2006, 2404, 2801,
+    3196, 3590, 3981, 4370, 4756, 5139, 5520, 5897,
+    6270, 6639, 7005, 7366, 7723, 8076, 8423, 8765,
+    9102, 9434, 9760, 10080, 10394, 10702, 11003, 11297,
+    11585, 11866, 12140, 12406, 12665, 12916, 13160, 13395,
+    13623, 13842, 14053, 14256, 14449, 14635, 14811, 14978,
+    15137, 15286, 15426, 15557, 15679, 15791, 15893, 15986,
+    16069, 16143, 16207, 16261, 16305, 16340, 16364, 16379,
+    16384,
+];
+
+fn sin(angle: u8) -> i32 {
+    let idx = (angle % 64) as usize;
+    match angle / 64 {
+        0 => QUARTER_SINE[idx],
+        1 => QUARTER_SINE[64 - idx],
+        2 => -QUARTER_SINE[idx],
+        _ => -QUARTER_SINE[64 - idx],
+    }
+}
+
+fn cos(angle: u8) -> i32 {
+    sin(angle.wrapping_add(64))
+}
+
+// size before the free rotation
+fn upright_size(sprite: &impl Sprite, transform: &Transform) -> (i32, i32) {
+    let scale = transform.scale.max(1) as i32;
+    let (width, height) = (sprite.width() as i32 * scale, sprite.height() as i32 * scale);
+    if transform.quarter_turns.is_multiple_of(2) { (width, height) } else { (height, width) }
+}
+
+// pixel of the flipped, turned and scaled sprite
+fn upright_index(sprite: &impl Sprite, transform: &Transform, x: i32, y: i32) -> u8 {
+    let (upright_width, upright_height) = upright_size(sprite, transform);
+    if x < 0 || y < 0 || x >= upright_width || y >= upright_height {
+        return 0;
+    }
+    let scale = transform.scale.max(1) as i32;
+    let (x, y) = (x / scale, y / scale);
+    let (width, height) = (sprite.width() as i32, sprite.height() as i32);
+    let (x, y) = match transform.quarter_turns % 4 {
+        0 => (x, y),
+        1 => (y, height - 1 - x),
+        2 => (width - 1 - x, height - 1 - y),
+        _ => (width - 1 - y, x),
+    };
+    let x = if transform.flip_x { width - 1 - x } else { x };
+    let y = if transform.flip_y { height - 1 - y } else { y };
+    sprite.index(x, y)
+}
+
+// bounding box of the transformed sprite
+pub fn size(sprite: &impl Sprite, transform: &Transform) -> (i32, i32) {
+    let (width, height) = upright_size(sprite, transform);
+    if transform.angle == 0 {
+        return (width, height);
+    }
+    let (sin, cos) = (sin(transform.angle).abs(), cos(transform.angle).abs());
+    ((width * cos + height * sin + ONE - 1) / ONE, (width * sin + height * cos + ONE - 1) / ONE)
+}
+
+// calls `plot` with every opaque pixel and its palette index, relative to the
+// top left of the bounding box
+pub fn for_each_pixel(sprite: &impl Sprite, transform: &Transform, mut plot: impl FnMut(i32, i32, u8)) {
+    let (out_width, out_height) = size(sprite, transform);
+    let (width, height) = upright_size(sprite, transform);
+    let (sin, cos) = (sin(transform.angle), cos(transform.angle));
+    for y in 0..out_height {
+        for x in 0..out_width {
+            let index = if transform.angle == 0 {
+                upright_index(sprite, transform, x, y)
+            } else {
+                // pixel centres relative to the centre, in half pixels
+                let (dx, dy) = (2 * x + 1 - out_width, 2 * y + 1 - out_height);
+                let u = (dx * cos + dy * sin + width * ONE).div_euclid(2 * ONE);
+                let v = (dy * cos - dx * sin + height * ONE).div_euclid(2 * ONE);
+                upright_index(sprite, transform, u, v)
+            };
+            if index != 0 {
+                plot(x, y, index);
+            }
+        }
+    }
+}
+
+// Draws into a row major grid with the bounding box at `x`, `y`, clipped at
+// the edges. `value` maps palette indices to what ends up in the grid.
+pub fn blit<T>(grid: &mut [T], grid_width: usize, x: i32, y: i32, sprite: &impl Sprite, transform: &Transform, value: impl Fn(u8) -> T) {
+    for_each_pixel(sprite, transform, |dx, dy, index| {
+        let (grid_x, grid_y) = (x + dx, y + dy);
+        if grid_x < 0 || grid_y < 0 || grid_x as usize >= grid_width {
+            return;
+        }
+        if let Some(cell) = grid.get_mut(grid_y as usize * grid_width + grid_x as usize) {
+            *cell = value(index);
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /*
+     * OOO
+     * O..
+     */
+    const CORNER: Bitmap2D = Bitmap2D { width: 3, height: 2, bitmap: &[0b1110_0000_0000_0000, 0b1000_0000_0000_0000] };
+
+    fn render(sprite: &impl Sprite, transform: Transform) -> Vec<String> {
+        let (width, height) = size(sprite, &transform);
+        let mut grid = vec!['.'; (width * height) as usize];
+        blit(&mut grid, width as usize, 0, 0, sprite, &transform, |_| 'O');
+        grid.chunks(width as usize).map(|row| row.iter().collect()).collect()
+    }
+
+    #[test]
+    fn test_flips_and_quarter_turns() {
+        assert_eq!(render(&CORNER, Transform::IDENTITY), ["OOO", "O.."]);
+        assert_eq!(render(&CORNER, Transform { flip_x: true, ..Transform::IDENTITY }), ["OOO", "..O"]);
+        assert_eq!(render(&CORNER, Transform { flip_y: true, ..Transform::IDENTITY }), ["O..", "OOO"]);
+        assert_eq!(render(&CORNER, Transform { quarter_turns: 1, ..Transform::IDENTITY }), ["OO", ".O", ".O"]);
+        assert_eq!(render(&CORNER, Transform { quarter_turns: 2, ..Transform::IDENTITY }), ["..O", "OOO"]);
+        assert_eq!(render(&CORNER, Transform { quarter_turns: 3, ..Transform::IDENTITY }), ["O.", "O.", "OO"]);
+        // flipped before turning
+        assert_eq!(render(&CORNER, Transform { flip_x: true, quarter_turns: 1, ..Transform::IDENTITY }), [".O", ".O", "OO"]);
+    }
+
+    #[test]
+    fn test_scale_and_clipping() {
+        assert_eq!(render(&CORNER, Transform { scale: 2, ..Transform::IDENTITY }), ["OOOOOO", "OOOOOO", "OO....", "OO...."]);
+
+        let mut grid = vec!['.'; 4 * 2];
+        blit(&mut grid, 4, 2, -1, &CORNER, &Transform::IDENTITY, |_| 'O');
+        blit(&mut grid, 4, -2, 1, &CORNER, &Transform::IDENTITY, |_| 'O');
+        assert_eq!(grid.iter().collect::<String>(), "..O.O...");
+    }
+
+    #[test]
+    fn test_free_rotation() {
+        // whole quarter turns sample exactly like the quarter turn transform
+        for turns in 1..4u8 {
+            let rotated = render(&CORNER, Transform { angle: turns * 64, ..Transform::IDENTITY });
+            assert_eq!(rotated, render(&CORNER, Transform { quarter_turns: turns, ..Transform::IDENTITY }));
+        }
+
+        let bar = Bitmap2D { width: 9, height: 1, bitmap: &[0b1111_1111_1000_0000] };
+        // an eighth of a turn, the 9 pixel bar is shorter than the box's diagonal
+        assert_eq!(render(&bar, Transform { angle: 32, ..Transform::IDENTITY }), [
+            "........",
+            ".O......",
+            "..O.....",
+            "...O....",
+            "....O...",
+            ".....O..",
+            "......O.",
+            "........",
+        ]);
+    }
+
+    #[test]
+    fn test_full_width_rows() {
+        // the lowest bit of a row is the 16th column, it used to overflow the shift
+        let frame = Bitmap2D { width: 16, height: 2, bitmap: &[0xFFFF, 0x8001] };
+        assert_eq!(render(&frame, Transform::IDENTITY), ["OOOOOOOOOOOOOOOO", "O..............O"]);
+        // there's no 17th bit to read
+        let wide = Bitmap2D { width: 17, height: 1, bitmap: &[0xFFFF] };
+        assert_eq!(render(&wide, Transform::IDENTITY), ["OOOOOOOOOOOOOOOO."]);
+    }
+
+    #[test]
+    fn test_packed_palette_indices() {
+        // 2 bits per pixel, wider than a u16 row: 1 2 3 0 ... 0 3
+        let wide = IndexedBitmap { width: 17, height: 1, bits_per_pixel: 2, pixels: &[0b01_10_11_00, 0, 0, 0, 0b11_00_00_00] };
+        let mut grid = vec![0; 17];
+        blit(&mut grid, 17, 0, 0, &wide, &Transform::IDENTITY, |index| index);
+        assert_eq!(grid, [1, 2, 3, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 3]);
+
+        // 4 bits per pixel, odd widths pad every row to a whole byte
+        let nibbles = IndexedBitmap { width: 3, height: 2, bits_per_pixel: 4, pixels: &[0x1F, 0x20, 0x00, 0xA0] };
+        let mut grid = vec![0; 3 * 2];
+        blit(&mut grid, 3, 0, 0, &nibbles, &Transform { flip_x: true, ..Transform::IDENTITY }, |index| index);
+        assert_eq!(grid, [2, 15, 1, 10, 0, 0]);
+    }
+}
@@ -0,0 +1,92 @@
+// Stars scrolling down behind the playfield, nearer ones faster. Only for
+// show, the stars draw from a random stream of their own.
+
+use crate::rng::Rng;
+use crate::SUBPIXELS;
+
+pub const N_DEPTHS: usize = 3;
+// far to near
+const STARS_PER_DEPTH: [usize; N_DEPTHS] = [40, 24, 12];
+const SPEEDS: [i32; N_DEPTHS] = [SUBPIXELS / 8, SUBPIXELS / 4, SUBPIXELS / 2];
+const SEED: u32 = 0x5747_F1E1;
+
+struct Star {
+    // in subpixels
+    x: i32,
+    y: i32,
+    depth: usize,
+}
+
+pub struct Starfield {
+    width: i32,
+    height: i32,
+    stars: Vec<Star>,
+    rng: Rng,
+}
+
+impl Starfield {
+    // `width` and `height` in playfield pixels
+    pub fn new(width: usize, height: usize) -> Starfield {
+        let mut rng = Rng::new(SEED);
+        let (width, height) = (width as i32, height as i32);
+        let mut stars = Vec::new();
+        for (depth, n_stars) in STARS_PER_DEPTH.iter().enumerate() {
+            for _ in 0..*n_stars {
+                let x = rng.below(width) * SUBPIXELS;
+                let y = rng.below(height * SUBPIXELS);
+                stars.push(Star { x, y, depth });
+            }
+        }
+        Starfield { width, height, stars, rng }
+    }
+
+    // moves the stars by a frame, stars leaving at the bottom come back at the top somewhere else
+    pub fn scroll(&mut self) {
+        let bottom = self.height * SUBPIXELS;
+        for star in self.stars.iter_mut() {
+            star.y += SPEEDS.get(star.depth).copied().unwrap_or(SUBPIXELS);
+            if star.y >= bottom {
+                star.y -= bottom;
+                star.x = self.rng.below(self.width) * SUBPIXELS;
+            }
+        }
+    }
+
+    // calls `plot` with the playfield position and depth of every star, 0 is the farthest
+    pub fn for_each(&self, mut plot: impl FnMut(i32, i32, usize)) {
+        for star in self.stars.iter() {
+            plot(star.x / SUBPIXELS, star.y / SUBPIXELS, star.depth);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn positions(starfield: &Starfield) -> Vec<(i32, i32, usize)> {
+        let mut out = Vec::new();
+        starfield.for_each(|x, y, depth| out.push((x, y, depth)));
+        out
+    }
+
+    #[test]
+    fn test_parallax_scroll() {
+        let mut starfield = Starfield::new(20, 10);
+        let before = positions(&starfield);
+        assert_eq!(before.len(), STARS_PER_DEPTH.iter().sum::<usize>());
+        for _ in 0..8 {
+            starfield.scroll();
+        }
+        // 8 frames move the depths by 1, 2 and 4 pixels
+        for ((x0, y0, depth), (x1, y1, _)) in before.iter().zip(positions(&starfield).iter()) {
+            assert!((0..20).contains(x1) && (0..10).contains(y1));
+            if y1 > y0 {
+                assert_eq!(x1, x0);
+                assert_eq!(y1 - y0, 1 << depth);
+            }
+        }
+        // the same stars every time
+        assert_eq!(before, positions(&Starfield::new(20, 10)));
+    }
+}
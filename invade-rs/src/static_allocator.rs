@@ -1,58 +1,15 @@
 use std::mem;
-use std::marker::PhantomData;
-
-pub fn static_alloc<T: Sized>() -> &'static mut T {
-    unsafe { STATIC_ALLOCATOR.alloc_obj() }
-}
-
-pub fn static_dealloc<T: Sized>(obj: &mut T) {
-    unsafe { STATIC_ALLOCATOR.dealloc_obj(obj) }
-}
-
-fn static_alloc_mult<T: Sized>(n: usize) -> *mut T {
-    unsafe { STATIC_ALLOCATOR.alloc_obj_mult(n) }
-}
-
-fn static_dealloc_mult<T: Sized>(obj_ptr: *mut T, n: usize) {
-    unsafe { STATIC_ALLOCATOR.dealloc_obj_mult(obj_ptr, n) }
-}
 
 const MEM_SIZE: usize = 2 << 10;
-struct StaticAllocator {
+// fixed pool the SVectors of one Game live in, every Game owns one
+pub struct StaticAllocator {
     memory: [u64; MEM_SIZE],
     used_bitmap: [u64; MEM_SIZE / 64],
 }
 
-static mut STATIC_ALLOCATOR: StaticAllocator = StaticAllocator{ memory: [0; MEM_SIZE], used_bitmap: [0; MEM_SIZE / 64] };
-
 impl StaticAllocator {
-    unsafe fn alloc_obj<T: Sized>(&mut self) -> &mut T {
-        let (size, align) = Self::round_bitmap_size_align::<T>();
-        for (idx, bm) in self.used_bitmap.iter_mut().enumerate() {
-            if let Ok(bit0) = Self::find_free_bitrange(*bm, size, align) {
-                let memory_offset = idx * 64 + bit0;
-                let obj_ptr = self.memory.as_mut_ptr().wrapping_add(memory_offset);
-                *bm |= ((1 << size) - 1) << bit0;
-                return &mut*(obj_ptr as *mut T)
-            }
-        }
-        // this never happens but Rust doesn't know it, 
-        // (it may happen in general case, but not in this project)
-        loop {}
-        //let obj_ptr = self.memory.as_mut_ptr();
-        //return &mut*(obj_ptr as *mut T)
-    }
-
-    unsafe fn dealloc_obj<T: Sized>(&mut self, obj: &mut T) {
-        let (size, _) = Self::round_bitmap_size_align::<T>();
-        let obj_ptr = (obj as *mut T) as *mut u64;
-        let memory_offset = obj_ptr.offset_from(self.memory.as_ptr()) as usize;
-        let idx = memory_offset / 64;
-        let bit0 = memory_offset % 64;
-        if let Some(bm) = self.used_bitmap.get_mut(idx) {
-            let freeing_bm = !(((1 << size) - 1) << bit0);
-            *bm &= freeing_bm;
-        }
+    pub fn new() -> StaticAllocator {
+        StaticAllocator { memory: [0; MEM_SIZE], used_bitmap: [0; MEM_SIZE / 64] }
     }
 
     unsafe fn alloc_obj_mult<T: Sized>(&mut self, n: usize) -> *mut T {
@@ -62,16 +19,13 @@ impl StaticAllocator {
             if let Ok(bit0) = Self::find_free_bitrange(*bm, size, align) {
                 let memory_offset = idx * 64 + bit0;
                 let mem_ptr = self.memory.as_mut_ptr().wrapping_add(memory_offset);
-                *bm |= ((1 << size) - 1) << bit0;
+                *bm |= Self::bitrange_mask(size, bit0);
                 let obj_ptr = mem_ptr as *mut T;
                 return obj_ptr
             }
         }
-        // this never happens but Rust doesn't know it, 
-        // (it may happen in general case, but not in this project)
-        loop {}
-        //let obj_ptr = self.memory.as_mut_ptr();
-        //return &mut*(obj_ptr as *mut T)
+        // a Game's vectors are sized up front, so running out is a bug
+        panic!("static allocator is out of memory, {} words wanted", size)
     }
 
     unsafe fn dealloc_obj_mult<T: Sized>(&mut self, obj_ptr: *mut T, n: usize) {
@@ -81,11 +35,22 @@ impl StaticAllocator {
         let idx = memory_offset / 64;
         let bit0 = memory_offset % 64;
         if let Some(bm) = self.used_bitmap.get_mut(idx) {
-            let freeing_bm = !(((1 << size) - 1) << bit0);
+            let freeing_bm = !Self::bitrange_mask(size, bit0);
             *bm &= freeing_bm;
         }
     }
 
+    // single objects, what the pool was first written for
+    #[cfg(test)]
+    unsafe fn alloc_obj<T: Sized>(&mut self) -> *mut T {
+        self.alloc_obj_mult(1)
+    }
+
+    #[cfg(test)]
+    unsafe fn dealloc_obj<T: Sized>(&mut self, obj_ptr: *mut T) {
+        self.dealloc_obj_mult(obj_ptr, 1)
+    }
+
     fn find_free_bitrange(bm: u64, size: usize, align: usize) -> Result<usize, ()> {
         let mut first_zero = Self::find_next_zero(bm, 0);
         while first_zero < 64 {
@@ -99,6 +64,14 @@ impl StaticAllocator {
         Err(())
     }
 
+    // object may take a whole bitmap word, where (1 << size) - 1 overflows
+    fn bitrange_mask(size: usize, bit0: usize) -> u64 {
+        if size == 0 {
+            return 0;
+        }
+        (u64::MAX >> (64 - size)) << bit0
+    }
+
     fn find_next_zero(bm: u64, bit_start: usize) -> usize {
         for i in bit_start..64 {
             if (bm & 1 << i) == 0 {
@@ -121,17 +94,16 @@ impl StaticAllocator {
         let unit_size = mem::size_of::<u64>();
         let t_size = mem::size_of::<T>();
         let t_align = mem::size_of::<T>();
-        let size = (t_size + unit_size - 1) / unit_size;
-        let align = (t_align + unit_size - 1) / unit_size;
+        let size = t_size.div_ceil(unit_size);
+        let align = t_align.div_ceil(unit_size);
         (size, align)
     }
 
     fn round_bitmap_size_align_mult<T: Sized>(n: usize) -> (usize, usize) {
         let unit_size = mem::size_of::<u64>();
         let t_size = mem::size_of::<T>();
-        let t_align = mem::size_of::<T>();
-        let size = (n * t_size + unit_size - 1) / unit_size;
-        let align = (t_align + unit_size - 1) / unit_size;
+        let (_, align) = Self::round_bitmap_size_align::<T>();
+        let size = (n * t_size).div_ceil(unit_size);
         (size, align)
     }
 }
@@ -140,12 +112,20 @@ pub struct SVector<T: Sized> {
     elements: *mut T,
     size: usize,
     capacity: usize,
+    allocator: *mut StaticAllocator,
 }
 
 impl<T> SVector<T> {
-    pub fn new(n: usize) -> SVector<T> {
-        let obj_ptr = static_alloc_mult(n);
-        SVector { elements: obj_ptr, size: 0, capacity: n }
+    /// # Safety
+    ///
+    /// The vector keeps a raw pointer to `allocator` to give its storage back
+    /// on drop, so the allocator must neither move nor be dropped before the
+    /// vector is. Boxing it keeps its address when the box moves, and
+    /// declaring the box after the vector in the struct owning both drops it
+    /// last.
+    pub unsafe fn new(allocator: &mut StaticAllocator, n: usize) -> SVector<T> {
+        let obj_ptr = allocator.alloc_obj_mult(n);
+        SVector { elements: obj_ptr, size: 0, capacity: n, allocator }
     }
 
     pub fn get(&self, idx: usize) -> Option<&T> {
@@ -197,7 +177,7 @@ impl<T> SVector<T> {
                 *x = val;
             }
         } else {
-            loop {}
+            panic!("SVector is full, capacity {}", self.capacity)
         }
     }
 
@@ -216,7 +196,7 @@ impl<T> SVector<T> {
 
 impl<T> Drop for SVector<T> {
     fn drop(&mut self) {
-        static_dealloc_mult(self.elements, self.capacity);
+        unsafe { (*self.allocator).dealloc_obj_mult(self.elements, self.capacity) }
     }
 }
 
@@ -248,6 +228,22 @@ mod tests {
         assert_eq!(StaticAllocator::round_bitmap_size_align::<u128>(), (2, 2));
     }
 
+    #[test]
+    fn test_round_mult_fn() {
+        assert_eq!(StaticAllocator::round_bitmap_size_align_mult::<u8>(1), (1, 1));
+        assert_eq!(StaticAllocator::round_bitmap_size_align_mult::<u8>(9), (2, 1));
+        assert_eq!(StaticAllocator::round_bitmap_size_align_mult::<u32>(3), (2, 1));
+        assert_eq!(StaticAllocator::round_bitmap_size_align_mult::<u128>(3), (6, 2));
+    }
+
+    #[test]
+    fn test_bitrange_mask() {
+        assert_eq!(StaticAllocator::bitrange_mask(0, 0), 0);
+        assert_eq!(StaticAllocator::bitrange_mask(1, 0), 0b1);
+        assert_eq!(StaticAllocator::bitrange_mask(2, 3), 0b11000);
+        assert_eq!(StaticAllocator::bitrange_mask(64, 0), !0);
+    }
+
     #[test]
     fn test_find_next_zero() {
         assert_eq!(StaticAllocator::find_next_zero(0b0, 0), 0);
@@ -291,56 +287,110 @@ mod tests {
 
     #[test]
     fn alloc_dealloc_u8() {
-        let obj_u8 = static_alloc::<u8>();
-        let obj_ptr = (obj_u8 as *mut u8) as *mut u64;
-        unsafe { assert_eq!(obj_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr()) }
-        unsafe { assert_eq!(0b1, STATIC_ALLOCATOR.used_bitmap[0]) }
-
-        static_dealloc(obj_u8);
-        unsafe { assert_eq!(0b0, STATIC_ALLOCATOR.used_bitmap[0]) }
+        let mut allocator = Box::new(StaticAllocator::new());
+        let obj_u8 = unsafe { allocator.alloc_obj::<u8>() };
+        let obj_ptr = obj_u8 as *mut u64;
+        assert_eq!(obj_ptr, allocator.memory.as_mut_ptr());
+        assert_eq!(0b1, allocator.used_bitmap[0]);
+
+        unsafe { allocator.dealloc_obj(obj_u8) }
+        assert_eq!(0b0, allocator.used_bitmap[0]);
     }
 
     #[test]
     fn alloc_dealloc_2_u64s() {
-        let obj0_u64 = static_alloc::<u64>();
-        let obj0_ptr = obj0_u64 as *mut u64;
-        unsafe { assert_eq!(obj0_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr()) }
-        unsafe { assert_eq!(0b1, STATIC_ALLOCATOR.used_bitmap[0]) }
+        let mut allocator = Box::new(StaticAllocator::new());
+        let obj0_u64 = unsafe { allocator.alloc_obj::<u64>() };
+        let obj0_ptr = obj0_u64;
+        assert_eq!(obj0_ptr, allocator.memory.as_mut_ptr());
+        assert_eq!(0b1, allocator.used_bitmap[0]);
+
+        let obj1_u64 = unsafe { allocator.alloc_obj::<u64>() };
+        let obj1_ptr = obj1_u64;
+        assert_eq!(obj1_ptr, allocator.memory.as_mut_ptr().wrapping_add(1));
+        assert_eq!(0b11, allocator.used_bitmap[0]);
+
+        unsafe { allocator.dealloc_obj(obj0_u64) }
+        assert_eq!(0b10, allocator.used_bitmap[0]);
+
+        unsafe { allocator.dealloc_obj(obj1_u64) }
+        assert_eq!(0b0, allocator.used_bitmap[0]);
+    }
 
-        let obj1_u64 = static_alloc::<u64>();
-        let obj1_ptr = obj1_u64 as *mut u64;
-        unsafe { assert_eq!(obj1_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr().wrapping_add(1)) }
-        unsafe { assert_eq!(0b11, STATIC_ALLOCATOR.used_bitmap[0]) }
+    #[test]
+    fn alloc_dealloc_u8_arrays() {
+        let mut allocator = Box::new(StaticAllocator::new());
+        let arr0_u8 = unsafe { allocator.alloc_obj_mult::<u8>(7) };
+        let arr0_ptr = arr0_u8 as *mut u64;
+        assert_eq!(arr0_ptr, allocator.memory.as_mut_ptr());
+        assert_eq!(0b1, allocator.used_bitmap[0]);
+
+        let arr1_u8 = unsafe { allocator.alloc_obj_mult::<u8>(8) };
+        let arr1_ptr = arr0_u8 as *mut u64;
+        assert_eq!(arr1_ptr, allocator.memory.as_mut_ptr());
+        assert_eq!(0b11, allocator.used_bitmap[0]);
+
+        let arr2_u8 = unsafe { allocator.alloc_obj_mult::<u8>(9) };
+        let arr2_ptr = arr0_u8 as *mut u64;
+        assert_eq!(arr2_ptr, allocator.memory.as_mut_ptr());
+        assert_eq!(0b1111, allocator.used_bitmap[0]);
+
+        unsafe { allocator.dealloc_obj_mult(arr1_u8, 8) }
+        assert_eq!(0b1101, allocator.used_bitmap[0]);
+        unsafe { allocator.dealloc_obj_mult(arr0_u8, 7) }
+        assert_eq!(0b1100, allocator.used_bitmap[0]);
+        unsafe { allocator.dealloc_obj_mult(arr2_u8, 9) }
+        assert_eq!(0b0, allocator.used_bitmap[0]);
+    }
+
+    #[test]
+    fn alloc_past_a_bitmap_word() {
+        let mut allocator = Box::new(StaticAllocator::new());
+        let arr0_u64 = unsafe { allocator.alloc_obj_mult::<u64>(60) };
+        // doesn't fit in the rest of the first word
+        let arr1_u64 = unsafe { allocator.alloc_obj_mult::<u64>(8) };
+        assert_eq!(arr1_u64, allocator.memory.as_mut_ptr().wrapping_add(64));
+        assert_eq!(u64::MAX >> 4, allocator.used_bitmap[0]);
+        assert_eq!(0xFF, allocator.used_bitmap[1]);
+
+        unsafe { allocator.dealloc_obj_mult(arr0_u64, 60) }
+        unsafe { allocator.dealloc_obj_mult(arr1_u64, 8) }
+        assert_eq!([0, 0], allocator.used_bitmap[..2]);
+    }
 
-        static_dealloc(obj0_u64);
-        unsafe { assert_eq!(0b10, STATIC_ALLOCATOR.used_bitmap[0]) }
+    #[test]
+    #[should_panic(expected = "out of memory")]
+    fn alloc_when_full_panics() {
+        let mut allocator = Box::new(StaticAllocator::new());
+        for _ in 0..MEM_SIZE / 64 {
+            unsafe { allocator.alloc_obj_mult::<u64>(64) };
+        }
+        unsafe { allocator.alloc_obj_mult::<u8>(1) };
+    }
 
-        static_dealloc(obj1_u64);
-        unsafe { assert_eq!(0b0, STATIC_ALLOCATOR.used_bitmap[0]) }
+    #[test]
+    fn svectors_free_into_their_own_pool() {
+        let mut allocator0 = Box::new(StaticAllocator::new());
+        let mut allocator1 = Box::new(StaticAllocator::new());
+        {
+            let mut svec0 = unsafe { SVector::<u32>::new(&mut allocator0, 4) };
+            let svec1 = unsafe { SVector::<u32>::new(&mut allocator1, 16) };
+            svec0.push_back(7);
+            assert_eq!(svec0.iter().copied().collect::<Vec<_>>(), [7]);
+            assert_eq!(svec1.size(), 0);
+            assert_eq!(0b11, allocator0.used_bitmap[0]);
+            assert_eq!(0xFF, allocator1.used_bitmap[0]);
+        }
+        assert_eq!(0, allocator0.used_bitmap[0]);
+        assert_eq!(0, allocator1.used_bitmap[0]);
     }
 
     #[test]
-    fn alloc_dealloc_u8_arrays() {
-        let arr0_u8 = static_alloc_mult::<u8>(7);
-        let arr0_ptr = (arr0_u8 as *mut u8) as *mut u64;
-        unsafe { assert_eq!(arr0_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr()) }
-        unsafe { assert_eq!(0b1, STATIC_ALLOCATOR.used_bitmap[0]) }
-
-        let arr1_u8 = static_alloc_mult::<u8>(8);
-        let arr1_ptr = (arr0_u8 as *mut u8) as *mut u64;
-        unsafe { assert_eq!(arr1_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr()) }
-        unsafe { assert_eq!(0b11, STATIC_ALLOCATOR.used_bitmap[0]) }
-
-        let arr2_u8 = static_alloc_mult::<u8>(9);
-        let arr2_ptr = (arr0_u8 as *mut u8) as *mut u64;
-        unsafe { assert_eq!(arr2_ptr, STATIC_ALLOCATOR.memory.as_mut_ptr()) }
-        unsafe { assert_eq!(0b1111, STATIC_ALLOCATOR.used_bitmap[0]) }
-
-        static_dealloc_mult(arr1_u8, 8);
-        unsafe { assert_eq!(0b1101, STATIC_ALLOCATOR.used_bitmap[0]) }
-        static_dealloc_mult(arr0_u8, 7);
-        unsafe { assert_eq!(0b1100, STATIC_ALLOCATOR.used_bitmap[0]) }
-        static_dealloc_mult(arr2_u8, 9);
-        unsafe { assert_eq!(0b0, STATIC_ALLOCATOR.used_bitmap[0]) }
+    #[should_panic(expected = "SVector is full")]
+    fn push_past_capacity_panics() {
+        let mut allocator = Box::new(StaticAllocator::new());
+        let mut svec = unsafe { SVector::<u8>::new(&mut allocator, 1) };
+        svec.push_back(1);
+        svec.push_back(2);
     }
 }
@@ -0,0 +1,255 @@
+// Sound effects made up on the fly: a handful of voices, each a square,
+// triangle or noise oscillator with an envelope and a pitch sweep, mixed into
+// a ring of 16 bit PCM samples. The host follows the write position and copies
+// whatever is new to the audio thread. Everything is integer maths, so the same
+// sounds come out as the same samples everywhere.
+
+pub const SAMPLE_RATE: u32 = 48000;
+// a few ticks' worth, the host copies new samples after every tick
+pub const RING_LEN: usize = 8192;
+const MAX_VOICES: usize = 8;
+// voices of their own for the music, so effects never cut off a note
+pub const MUSIC_CHANNELS: usize = 4;
+const MAX_LEVEL: i32 = 255;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Wave {
+    Square,
+    Triangle,
+    Noise,
+}
+
+// one sound, times are in milliseconds
+#[derive(Clone, Copy)]
+pub struct Effect {
+    pub wave: Wave,
+    // start and end frequency in Hz, swept linearly over the whole sound,
+    // for noise this is how often a new random level is drawn
+    pub freq: (u32, u32),
+    pub attack: u32,
+    // at full volume after the attack
+    pub hold: u32,
+    // fade out to silence
+    pub release: u32,
+    pub volume: u8,
+}
+
+#[derive(Clone, Copy)]
+struct Voice {
+    effect: Effect,
+    // samples played so far
+    pos: u32,
+    len: u32,
+    phase: u32,
+    // 15 bit shift register for the noise
+    lfsr: u16,
+}
+
+impl Voice {
+    fn new(effect: &Effect) -> Voice {
+        let len = ms_to_samples(effect.attack + effect.hold + effect.release).max(1);
+        Voice { effect: *effect, pos: 0, len, phase: 0, lfsr: 1 }
+    }
+
+    fn is_done(&self) -> bool {
+        self.pos >= self.len
+    }
+
+    // envelope level in 0..=MAX_LEVEL at the current position
+    fn level(&self) -> i32 {
+        let attack = ms_to_samples(self.effect.attack);
+        let hold = ms_to_samples(self.effect.hold);
+        if self.pos < attack {
+            (self.pos as i64 * MAX_LEVEL as i64 / attack as i64) as i32
+        } else if self.pos < attack + hold {
+            MAX_LEVEL
+        } else {
+            let release = self.len.saturating_sub(attack + hold).max(1);
+            let left = self.len.saturating_sub(self.pos);
+            (left as i64 * MAX_LEVEL as i64 / release as i64) as i32
+        }
+    }
+
+    fn next_sample(&mut self) -> i32 {
+        let (from, to) = (self.effect.freq.0 as i64, self.effect.freq.1 as i64);
+        let freq = from + (to - from) * self.pos as i64 / self.len as i64;
+        let step = ((freq.max(0) as u64) << 32) / SAMPLE_RATE as u64;
+        let (phase, wrapped) = self.phase.overflowing_add(step as u32);
+        self.phase = phase;
+        if wrapped {
+            let bit = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        }
+        // full scale of i16
+        let wave = match self.effect.wave {
+            Wave::Square => if self.phase < 1 << 31 { 32767 } else { -32767 },
+            Wave::Triangle => {
+                let ramp = (self.phase >> 15) as i32;
+                if ramp < 65536 { ramp - 32768 } else { 98303 - ramp }
+            },
+            Wave::Noise => if self.lfsr & 1 != 0 { 32767 } else { -32767 },
+        };
+        let sample = wave * self.level() / MAX_LEVEL * self.effect.volume as i32 / 255;
+        self.pos += 1;
+        sample
+    }
+}
+
+fn ms_to_samples(ms: u32) -> u32 {
+    (ms as u64 * SAMPLE_RATE as u64 / 1000) as u32
+}
+
+pub struct Synth {
+    // effects first, then one for every music channel
+    voices: [Option<Voice>; MAX_VOICES + MUSIC_CHANNELS],
+    ring: Vec<i16>,
+    // samples written since the start, wraps around
+    written: u32,
+}
+
+impl Synth {
+    pub fn new() -> Synth {
+        Synth { voices: [None; MAX_VOICES + MUSIC_CHANNELS], ring: vec![0; RING_LEN], written: 0 }
+    }
+
+    // with every voice busy the one that has played longest is cut off
+    pub fn play(&mut self, effect: &Effect) {
+        let effect_voices = self.voices.get(..MAX_VOICES).unwrap_or(&[]);
+        let slot = match effect_voices.iter().position(|voice| voice.is_none()) {
+            Some(idx) => idx,
+            None => {
+                let oldest = effect_voices.iter().enumerate().max_by_key(|(_, voice)| voice.map_or(0, |voice| voice.pos));
+                match oldest {
+                    Some((idx, _)) => idx,
+                    None => return,
+                }
+            },
+        };
+        if let Some(voice) = self.voices.get_mut(slot) {
+            *voice = Some(Voice::new(effect));
+        }
+    }
+
+    // a new note cuts off the one still playing on the channel
+    pub fn play_channel(&mut self, channel: usize, effect: &Effect) {
+        if let Some(voice) = self.voices.get_mut(MAX_VOICES + channel) {
+            *voice = Some(Voice::new(effect));
+        }
+    }
+
+    pub fn stop_channels(&mut self) {
+        for voice in self.voices.iter_mut().skip(MAX_VOICES) {
+            *voice = None;
+        }
+    }
+
+    pub fn stop_all(&mut self) {
+        self.voices = [None; MAX_VOICES + MUSIC_CHANNELS];
+    }
+
+    // mixes the next `n_samples` into the ring, silence when nothing plays
+    pub fn render(&mut self, n_samples: usize) {
+        for _ in 0..n_samples {
+            let mut mix = 0;
+            for slot in self.voices.iter_mut() {
+                if let Some(voice) = slot {
+                    mix += voice.next_sample();
+                    if voice.is_done() {
+                        *slot = None;
+                    }
+                }
+            }
+            if let Some(x) = self.ring.get_mut(self.written as usize % RING_LEN) {
+                *x = mix.clamp(-32767, 32767) as i16;
+            }
+            self.written = self.written.wrapping_add(1);
+        }
+    }
+
+    pub fn ring(&self) -> &[i16] {
+        &self.ring
+    }
+
+    pub fn written(&self) -> u32 {
+        self.written
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BEEP: Effect = Effect { wave: Wave::Square, freq: (440, 880), attack: 5, hold: 20, release: 25, volume: 128 };
+
+    fn hash_ring(synth: &Synth, n_samples: usize) -> u32 {
+        let mut hash = crate::Fnv1a::new();
+        for idx in 0..n_samples {
+            if let Some(sample) = synth.ring().get(idx % RING_LEN) {
+                hash.write_u32(*sample as u16 as u32);
+            }
+        }
+        hash.0
+    }
+
+    #[test]
+    fn test_waves_sound_different() {
+        let n_samples = ms_to_samples(50) as usize;
+        let mut hashes = Vec::new();
+        for wave in [Wave::Square, Wave::Triangle, Wave::Noise] {
+            let mut synth = Synth::new();
+            synth.play(&Effect { wave, ..BEEP });
+            synth.render(n_samples);
+            let samples = synth.ring().get(..n_samples).unwrap_or(&[]);
+            assert!(samples.iter().any(|sample| *sample > 1000) && samples.iter().any(|sample| *sample < -1000));
+            hashes.push(hash_ring(&synth, n_samples));
+        }
+        assert!(hashes[0] != hashes[1] && hashes[1] != hashes[2] && hashes[0] != hashes[2]);
+
+        // the same sound gives the same samples
+        let mut synth = Synth::new();
+        synth.play(&BEEP);
+        synth.render(n_samples);
+        assert_eq!(hash_ring(&synth, n_samples), hashes[0]);
+        // update only if the synth is meant to sound different
+        assert_eq!(hash_ring(&synth, n_samples), 0xC2A9_19B0);
+    }
+
+    #[test]
+    fn test_envelope_ends_in_silence() {
+        let mut synth = Synth::new();
+        synth.play(&BEEP);
+        // the sound is 50 ms long, the rest of the ring stays silent
+        synth.render(RING_LEN);
+        let len = ms_to_samples(50) as usize;
+        let peak = |samples: &[i16]| samples.iter().map(|sample| sample.unsigned_abs()).max().unwrap_or(0);
+        // attack rises, release falls off
+        assert!(peak(&synth.ring()[..len / 20]) < peak(&synth.ring()[len / 10..len / 2]));
+        assert!(peak(&synth.ring()[len * 9 / 10..len]) < peak(&synth.ring()[len / 10..len / 2]));
+        assert_eq!(peak(&synth.ring()[len..]), 0);
+        assert!(synth.voices.iter().all(|voice| voice.is_none()));
+    }
+
+    #[test]
+    fn test_ring_wraps_and_voices_are_stolen() {
+        let mut synth = Synth::new();
+        synth.play_channel(0, &BEEP);
+        for _ in 0..MAX_VOICES + 3 {
+            synth.play(&BEEP);
+            synth.render(10);
+        }
+        let (effects, channels) = synth.voices.split_at(MAX_VOICES);
+        assert!(effects.iter().all(|voice| voice.is_some()));
+        // the newest sound got a voice, the oldest was cut off
+        assert!(effects.iter().any(|voice| voice.is_some_and(|voice| voice.pos == 10)));
+        assert!(effects.iter().all(|voice| voice.is_some_and(|voice| voice.pos <= 10 * MAX_VOICES as u32)));
+        // but never a note of the music
+        assert!(channels[0].is_some_and(|voice| voice.pos == 10 * (MAX_VOICES + 3) as u32));
+        synth.stop_channels();
+        assert!(synth.voices.iter().skip(MAX_VOICES).all(|voice| voice.is_none()));
+
+        synth.stop_all();
+        synth.render(RING_LEN);
+        assert_eq!(synth.written(), (RING_LEN + 10 * (MAX_VOICES + 3)) as u32);
+        assert!(synth.ring().iter().all(|sample| *sample == 0));
+    }
+}
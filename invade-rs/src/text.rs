@@ -0,0 +1,199 @@
+// Text layout in playfield pixels: measuring, word wrapping and alignment.
+// Drawing the laid out lines is up to the renderer.
+
+use crate::font::Font;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Align {
+    Left,
+    Center,
+    // no screen is right aligned
+    #[cfg(test)]
+    Right,
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Effect {
+    Plain,
+    // copy of the text one font pixel down and right
+    Shadow(u32),
+    // copies of the text around it in all eight directions
+    Outline(u32),
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextStyle {
+    // playfield pixels per font pixel
+    pub scale: usize,
+    pub color: u32,
+    pub align: Align,
+    // empty font pixels between two lines
+    pub line_spacing: usize,
+    pub effect: Effect,
+}
+
+impl TextStyle {
+    pub fn new(scale: usize, color: u32) -> TextStyle {
+        TextStyle { scale, color, align: Align::Left, line_spacing: 2, effect: Effect::Plain }
+    }
+
+    pub fn align(self, align: Align) -> TextStyle {
+        TextStyle { align, ..self }
+    }
+
+    pub fn effect(self, effect: Effect) -> TextStyle {
+        TextStyle { effect, ..self }
+    }
+
+    pub fn line_height(&self, font: &Font) -> usize {
+        (font.height() + self.line_spacing) * self.scale
+    }
+}
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct TextBox {
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+    pub height: usize,
+}
+
+// a laid out line, `text[start..end]` drawn at `x`, `y`
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub struct Line {
+    pub start: usize,
+    pub end: usize,
+    pub x: i32,
+    pub y: i32,
+    pub width: usize,
+}
+
+pub fn measure_line(font: &Font, text: &str, scale: usize) -> usize {
+    font.measure(text.chars()) * scale
+}
+
+// size of the text without wrapping, lines are split on '\n'
+pub fn measure(font: &Font, text: &str, style: &TextStyle) -> (usize, usize) {
+    let width = text.split('\n').map(|line| measure_line(font, line, style.scale)).max().unwrap_or(0);
+    let n_lines = text.split('\n').count();
+    (width, n_lines * style.line_height(font) - style.line_spacing * style.scale)
+}
+
+// Greedy word wrap inside `bounds`. Words longer than a line are broken
+// between characters, lines that don't fit in the height are dropped.
+pub fn layout(font: &Font, text: &str, bounds: &TextBox, style: &TextStyle) -> Vec<Line> {
+    let mut spans: Vec<(usize, usize)> = Vec::new();
+    let mut offset = 0;
+    for paragraph in text.split('\n') {
+        wrap(font, paragraph, offset, bounds.width, style.scale, &mut spans);
+        offset += paragraph.len() + 1;
+    }
+
+    let max_lines = (bounds.height + style.line_spacing * style.scale) / style.line_height(font).max(1);
+    spans.iter().take(max_lines).enumerate().map(|(idx, (start, end))| {
+        let width = measure_line(font, &text[*start..*end], style.scale);
+        let free = bounds.width.saturating_sub(width) as i32;
+        let x = match style.align {
+            Align::Left => bounds.x,
+            Align::Center => bounds.x + free / 2,
+            #[cfg(test)]
+            Align::Right => bounds.x + free,
+        };
+        let y = bounds.y + (idx * style.line_height(font)) as i32;
+        Line { start: *start, end: *end, x, y, width }
+    }).collect()
+}
+
+fn wrap(font: &Font, paragraph: &str, offset: usize, max_width: usize, scale: usize, spans: &mut Vec<(usize, usize)>) {
+    let fits = |start: usize, end: usize| measure_line(font, &paragraph[start..end], scale) <= max_width;
+    let mut line_start = 0;
+    let mut line_end = 0;
+    if paragraph.is_empty() {
+        spans.push((offset, offset));
+        return;
+    }
+    for (word_start, word) in words(paragraph) {
+        let word_end = word_start + word.len();
+        if line_end > line_start && !fits(line_start, word_end) {
+            spans.push((offset + line_start, offset + line_end));
+            line_start = word_start;
+        }
+        if line_end <= line_start {
+            line_start = word_start;
+        }
+        // a word that doesn't fit on its own line is split
+        while !fits(line_start, word_end) {
+            let split = paragraph[line_start..word_end].char_indices().skip(1)
+                .map(|(idx, _)| line_start + idx)
+                .take_while(|split| fits(line_start, *split))
+                .last();
+            match split {
+                Some(split) => {
+                    spans.push((offset + line_start, offset + split));
+                    line_start = split;
+                },
+                None => break,
+            }
+        }
+        line_end = word_end;
+    }
+    spans.push((offset + line_start, offset + line_end));
+}
+
+// words with their byte offsets, separated by spaces
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(' ').scan(0, |offset, word| {
+        let start = *offset;
+        *offset += word.len() + 1;
+        Some((start, word))
+    }).filter(|(_, word)| !word.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines<'a>(text: &'a str, lines: &[Line]) -> Vec<&'a str> {
+        lines.iter().map(|line| &text[line.start..line.end]).collect()
+    }
+
+    #[test]
+    fn test_measure() {
+        let font = Font::builtin();
+        let style = TextStyle::new(2, 0);
+        assert_eq!(measure(&font, "HI", &style), (14, 10));
+        assert_eq!(measure(&font, "HI\nHI HI", &style), (34, 24));
+    }
+
+    #[test]
+    fn test_wrap_and_align() {
+        let font = Font::builtin();
+        let text = "SHOOT THE INVADERS BEFORE THEY LAND";
+        let bounds = TextBox { x: 10, y: 20, width: 50, height: 100 };
+        let style = TextStyle::new(1, 0).align(Align::Right);
+        let laid_out = layout(&font, text, &bounds, &style);
+        assert_eq!(lines(text, &laid_out), ["SHOOT THE", "INVADERS", "BEFORE", "THEY LAND"]);
+        for (idx, line) in laid_out.iter().enumerate() {
+            assert!(line.width <= bounds.width);
+            assert_eq!(line.x + line.width as i32, 60);
+            assert_eq!(line.y, 20 + idx as i32 * 7);
+        }
+
+        let style = TextStyle::new(1, 0).align(Align::Center);
+        let laid_out = layout(&font, "HI", &bounds, &style);
+        assert_eq!(laid_out[0].x, 10 + (50 - 7) / 2);
+    }
+
+    #[test]
+    fn test_long_words_and_newlines() {
+        let font = Font::builtin();
+        let text = "ABCDEFGHIJ\n\nOK";
+        let bounds = TextBox { x: 0, y: 0, width: 20, height: 100 };
+        let laid_out = layout(&font, text, &bounds, &TextStyle::new(1, 0));
+        assert_eq!(lines(text, &laid_out), ["ABCD", "EFGH", "IJ", "", "OK"]);
+
+        // only full lines that fit in the box
+        let bounds = TextBox { x: 0, y: 0, width: 20, height: 12 };
+        assert_eq!(layout(&font, text, &bounds, &TextStyle::new(1, 0)).len(), 2);
+    }
+}
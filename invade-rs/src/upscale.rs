@@ -0,0 +1,205 @@
+// Scaling passes from a native resolution framebuffer to the size the host
+// displays, for hosts that can't (or shouldn't) scale the canvas themselves.
+
+const BORDER_COLOR: u32 = 0xFF_00_00_00;
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum Mode {
+    // stretches to the whole output, pixels may end up uneven
+    Nearest,
+    // largest whole multiple that fits, centered with a border around
+    IntegerFit,
+    // EPX/Scale2x smoothing of the edges, then integer fit
+    Scale2x,
+}
+
+impl Mode {
+    pub fn from_u32(val: u32) -> Option<Mode> {
+        match val {
+            1 => Some(Mode::Nearest),
+            2 => Some(Mode::IntegerFit),
+            3 => Some(Mode::Scale2x),
+            _ => None,
+        }
+    }
+}
+
+pub struct Upscaler {
+    mode: Mode,
+    width: usize,
+    height: usize,
+    pixels: Vec<u32>,
+    scratch: Vec<u32>,
+}
+
+impl Upscaler {
+    pub fn new(mode: Mode, width: usize, height: usize) -> Upscaler {
+        Upscaler { mode, width, height, pixels: vec![BORDER_COLOR; width * height], scratch: Vec::new() }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        &self.pixels
+    }
+
+    pub fn run(&mut self, src: &[u32], src_width: usize, src_height: usize) {
+        match self.mode {
+            Mode::Nearest => nearest(src, src_width, src_height, &mut self.pixels, self.width, self.height),
+            Mode::IntegerFit => integer_fit(src, src_width, src_height, &mut self.pixels, self.width, self.height),
+            Mode::Scale2x => {
+                self.scratch.resize(src_width * src_height * 4, 0);
+                scale2x(src, src_width, src_height, &mut self.scratch);
+                integer_fit(&self.scratch, src_width * 2, src_height * 2, &mut self.pixels, self.width, self.height);
+            },
+        }
+    }
+}
+
+pub fn nearest(src: &[u32], src_width: usize, src_height: usize, dst: &mut [u32], dst_width: usize, dst_height: usize) {
+    if src_width == 0 || src_height == 0 {
+        return;
+    }
+    for y in 0..dst_height {
+        let src_row = y * src_height / dst_height * src_width;
+        if let (Some(src_row), Some(dst_row)) = (src.get(src_row..src_row + src_width), dst.get_mut(y * dst_width..(y + 1) * dst_width)) {
+            for (x, pixel) in dst_row.iter_mut().enumerate() {
+                if let Some(src_pixel) = src_row.get(x * src_width / dst_width) {
+                    *pixel = *src_pixel;
+                }
+            }
+        }
+    }
+}
+
+pub fn integer_fit(src: &[u32], src_width: usize, src_height: usize, dst: &mut [u32], dst_width: usize, dst_height: usize) {
+    if src_width == 0 || src_height == 0 {
+        return;
+    }
+    // output smaller than the source still shows something
+    let scale = (dst_width / src_width).min(dst_height / src_height).max(1);
+    let fit_width = (src_width * scale).min(dst_width);
+    let fit_height = (src_height * scale).min(dst_height);
+    let x0 = (dst_width - fit_width) / 2;
+    let y0 = (dst_height - fit_height) / 2;
+    for y in 0..dst_height {
+        let dst_row = match dst.get_mut(y * dst_width..(y + 1) * dst_width) {
+            Some(row) => row,
+            None => return,
+        };
+        let src_row = match y.checked_sub(y0) {
+            Some(y) if y < fit_height => src.get(y / scale * src_width..(y / scale + 1) * src_width),
+            _ => None,
+        };
+        match src_row {
+            Some(src_row) => {
+                for (x, pixel) in dst_row.iter_mut().enumerate() {
+                    *pixel = match x.checked_sub(x0) {
+                        Some(x) if x < fit_width => src_row.get(x / scale).copied().unwrap_or(BORDER_COLOR),
+                        _ => BORDER_COLOR,
+                    };
+                }
+            },
+            None => dst_row.fill(BORDER_COLOR),
+        }
+    }
+}
+
+// dst has to hold (2 * src_width) x (2 * src_height) pixels
+pub fn scale2x(src: &[u32], src_width: usize, src_height: usize, dst: &mut [u32]) {
+    let pixel = |x: usize, y: usize| src.get(y * src_width + x).copied().unwrap_or(BORDER_COLOR);
+    let dst_width = 2 * src_width;
+    for y in 0..src_height {
+        for x in 0..src_width {
+            //   A
+            // C P B
+            //   D
+            let p = pixel(x, y);
+            let a = if y > 0 { pixel(x, y - 1) } else { p };
+            let b = if x + 1 < src_width { pixel(x + 1, y) } else { p };
+            let c = if x > 0 { pixel(x - 1, y) } else { p };
+            let d = if y + 1 < src_height { pixel(x, y + 1) } else { p };
+            let e = [
+                if c == a && c != d && a != b { a } else { p },
+                if a == b && a != c && b != d { b } else { p },
+                if d == c && d != b && c != a { c } else { p },
+                if b == d && b != a && d != c { d } else { p },
+            ];
+            let ind0 = 2 * y * dst_width + 2 * x;
+            if let Some(row) = dst.get_mut(ind0..ind0 + 2) {
+                row.copy_from_slice(&e[0..2]);
+            }
+            if let Some(row) = dst.get_mut(ind0 + dst_width..ind0 + dst_width + 2) {
+                row.copy_from_slice(&e[2..4]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const W: u32 = 0xFF_FF_FF_FF;
+    const K: u32 = 0xFF_00_00_00;
+    const R: u32 = 0xFF_00_00_FF;
+
+    #[test]
+    fn test_nearest() {
+        let src = [R, W, K, R];
+        let mut dst = [0; 3 * 4];
+        nearest(&src, 2, 2, &mut dst, 3, 4);
+        assert_eq!(dst, [
+            R, R, W,
+            R, R, W,
+            K, K, R,
+            K, K, R,
+        ]);
+    }
+
+    #[test]
+    fn test_integer_fit_centers_with_border() {
+        let src = [R, W];
+        let mut dst = [0; 5 * 4];
+        integer_fit(&src, 2, 1, &mut dst, 5, 4);
+        assert_eq!(dst, [
+            K, K, K, K, K,
+            R, R, W, W, K,
+            R, R, W, W, K,
+            K, K, K, K, K,
+        ]);
+    }
+
+    #[test]
+    fn test_scale2x_rounds_diagonals() {
+        let src = [
+            W, R, W,
+            R, W, W,
+            W, W, W,
+        ];
+        let mut dst = [0; 6 * 6];
+        scale2x(&src, 3, 3, &mut dst);
+        // top left corner of the middle pixel is filled in along the diagonal
+        assert_eq!(dst[2 * 6 + 2], R);
+        assert_eq!(dst[2 * 6 + 3], W);
+        // flat areas stay flat
+        assert!(dst[4 * 6..].iter().all(|x| *x == W));
+        assert_eq!(dst[0], W);
+        assert_eq!(dst[2], R);
+    }
+
+    #[test]
+    fn test_upscaler_output() {
+        let src = [R; 4 * 3];
+        let mut upscaler = Upscaler::new(Mode::Scale2x, 17, 12);
+        upscaler.run(&src, 4, 3);
+        assert_eq!(upscaler.pixels().iter().filter(|x| **x == R).count(), 16 * 12);
+        assert!(Mode::from_u32(0).is_none());
+    }
+}